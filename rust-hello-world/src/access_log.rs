@@ -0,0 +1,315 @@
+//! One `access` line per request, sampled so a load test does not flood the
+//! log store. `ACCESS_LOG_SAMPLE_RATE` (0.0 to 1.0) is the share of
+//! ordinary requests logged; server errors, 429s and requests slower than
+//! `SLOW_REQUEST_MS` are always logged, the slow ones at warn with
+//! `slow_request=true`. Both can be changed through `PUT /config/logging`.
+//!
+//! With an `x-request-id` the decision is a hash of it, so every instance a
+//! request passes through keeps or drops its line alike and the lines of a
+//! sampled trace are all there.
+//! Dropped lines are counted in `access_log_sampled_out_total`; the request
+//! metrics count every request either way.
+
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use warp::http::{HeaderMap, Method, StatusCode};
+use warp::path::FullPath;
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+use crate::chain::REQUEST_ID;
+use crate::client_ip::{self, ClientInfo, ProxyTrust};
+use crate::config::Config;
+use crate::metrics::Metrics;
+use crate::reload::LiveConfig;
+use crate::{admin, filters, reply, ErrorResponse};
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, ToSchema)]
+pub(crate) struct LoggingSettings {
+    /// Share of ordinary requests logged, from 0.0 to 1.0.
+    access_log_sample_rate: f64,
+    /// Requests at least this slow are always logged, at warn.
+    slow_request_ms: Option<u64>,
+}
+
+/// Fields left out are kept; a `slow_request_ms` of 0 turns slow-request
+/// logging off.
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct LoggingUpdate {
+    access_log_sample_rate: Option<f64>,
+    slow_request_ms: Option<u64>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Verdict {
+    Log,
+    Slow,
+    SampledOut,
+}
+
+pub struct Sampling {
+    settings: RwLock<LoggingSettings>,
+    rng: Mutex<StdRng>,
+    metrics: Metrics,
+}
+
+impl Sampling {
+    pub fn from_config(config: &Config, metrics: Metrics) -> Arc<Self> {
+        Arc::new(Self {
+            settings: RwLock::new(LoggingSettings {
+                access_log_sample_rate: config.access_log_sample_rate,
+                slow_request_ms: config.slow_request_ms,
+            }),
+            rng: Mutex::new(StdRng::from_entropy()),
+            metrics,
+        })
+    }
+
+    fn settings(&self) -> LoggingSettings {
+        *self.settings.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn verdict(&self, status: StatusCode, latency: Duration, request_id: Option<&str>) -> Verdict {
+        let settings = self.settings();
+        if settings
+            .slow_request_ms
+            .is_some_and(|ms| latency >= Duration::from_millis(ms))
+        {
+            return Verdict::Slow;
+        }
+        let rate = settings.access_log_sample_rate;
+        let keep = status.is_server_error()
+            || status == StatusCode::TOO_MANY_REQUESTS
+            || rate >= 1.0
+            || match request_id {
+                Some(id) => (hash(id) as f64 / u64::MAX as f64) < rate,
+                None => {
+                    let mut rng = self.rng.lock().unwrap_or_else(|e| e.into_inner());
+                    rng.gen_bool(rate.max(0.0))
+                }
+            };
+        if keep {
+            Verdict::Log
+        } else {
+            Verdict::SampledOut
+        }
+    }
+
+    fn update(&self, update: LoggingUpdate) -> Result<LoggingSettings, String> {
+        if let Some(rate) = update.access_log_sample_rate {
+            if !(0.0..=1.0).contains(&rate) {
+                return Err("access_log_sample_rate must be between 0 and 1".to_string());
+            }
+        }
+        let mut settings = self.settings.write().unwrap_or_else(|e| e.into_inner());
+        if let Some(rate) = update.access_log_sample_rate {
+            settings.access_log_sample_rate = rate;
+        }
+        if let Some(ms) = update.slow_request_ms {
+            settings.slow_request_ms = Some(ms).filter(|ms| *ms > 0);
+        }
+        Ok(*settings)
+    }
+}
+
+/// 64-bit FNV-1a with the MurmurHash3 finalizer, since request IDs often
+/// differ only in their last bytes. Stable across builds and platforms,
+/// unlike `DefaultHasher`, so every instance hashes an ID the same way.
+fn hash(value: &str) -> u64 {
+    let mut hash = value.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    });
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    hash ^ (hash >> 33)
+}
+
+/// Logs one line per request that `sampling` keeps, including ones that
+/// ended in a rejection, so `filter` must already have been through
+/// `recover`. `request_id` is the caller's `x-request-id`, or `-` without
+/// one.
+pub fn wrap<F, R>(
+    filter: F,
+    trust: Arc<ProxyTrust>,
+    sampling: Arc<Sampling>,
+) -> impl Filter<Extract = (Response,), Error = Infallible> + Clone
+where
+    F: Filter<Extract = (R,), Error = Infallible> + Clone + Send + Sync + 'static,
+    R: Reply,
+{
+    warp::any()
+        .map(Instant::now)
+        .and(warp::method())
+        .and(warp::path::full())
+        .and(client_ip::client_info(trust))
+        .and(request_id())
+        .and(filter)
+        .map(
+            move |start: Instant,
+                  method: Method,
+                  path: FullPath,
+                  client: ClientInfo,
+                  request_id: Option<String>,
+                  reply: R| {
+                let response = reply.into_response();
+                let latency = start.elapsed();
+                let status = response.status();
+                macro_rules! line {
+                    ($level:ident $(, $field:ident = $value:expr)*) => {
+                        tracing::$level!(
+                            target: "access",
+                            method = %method,
+                            path = path.as_str(),
+                            status = status.as_u16(),
+                            client_ip = %client.client_ip_display(),
+                            client_ip_source = client.source,
+                            peer_addr = %client
+                                .remote_addr
+                                .map_or_else(|| "-".to_string(), |addr| addr.to_string()),
+                            latency_ms = latency.as_secs_f64() * 1000.0,
+                            request_id = request_id.as_deref().unwrap_or("-"),
+                            $($field = $value,)*
+                        )
+                    };
+                }
+                match sampling.verdict(status, latency, request_id.as_deref()) {
+                    Verdict::Log => line!(info),
+                    Verdict::Slow => line!(warn, slow_request = true),
+                    Verdict::SampledOut => sampling.metrics.record_access_log_sampled_out(),
+                }
+                response
+            },
+        )
+}
+
+fn request_id() -> impl Filter<Extract = (Option<String>,), Error = Infallible> + Clone {
+    warp::header::headers_cloned().map(|headers: HeaderMap| {
+        headers
+            .get(REQUEST_ID)
+            .and_then(|value| value.to_str().ok())
+            .map(String::from)
+    })
+}
+
+#[utoipa::path(
+    put,
+    operation_id = "config_logging",
+    path = "/config/logging",
+    tag = "admin",
+    request_body = LoggingUpdate,
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "The sampling now in effect", body = LoggingSettings),
+        (status = 400, description = "Malformed body or access_log_sample_rate out of range", body = ErrorResponse),
+        (status = 401, description = "Missing or wrong X-Admin-Token", body = ErrorResponse),
+    )
+)]
+/// `PUT /config/logging`. The new values are written to the live config
+/// too, so `GET /config` shows what is in effect. Absent unless
+/// `ADMIN_TOKEN` is set.
+pub fn update_route(
+    sampling: Arc<Sampling>,
+    live: LiveConfig,
+    max_json_depth: usize,
+    admin_token: Option<Arc<str>>,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    warp::path!("config" / "logging")
+        .and(warp::put())
+        .and(admin::protected(admin_token))
+        .and(filters::json::<LoggingUpdate>(max_json_depth))
+        .map(move |update: LoggingUpdate| match sampling.update(update) {
+            Ok(settings) => {
+                let mut config = live.write().unwrap_or_else(|e| e.into_inner());
+                config.access_log_sample_rate = settings.access_log_sample_rate;
+                config.slow_request_ms = settings.slow_request_ms;
+                tracing::warn!(
+                    access_log_sample_rate = settings.access_log_sample_rate,
+                    slow_request_ms = settings.slow_request_ms,
+                    "access log sampling changed via /config/logging"
+                );
+                reply::json(&settings).into_response()
+            }
+            Err(error) => warp::reply::with_status(
+                reply::json(&ErrorResponse { error }),
+                StatusCode::BAD_REQUEST,
+            )
+            .into_response(),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sampling(rate: f64, slow_request_ms: Option<u64>) -> Arc<Sampling> {
+        Sampling::from_config(
+            &Config {
+                access_log_sample_rate: rate,
+                slow_request_ms,
+                ..Config::from_env()
+            },
+            Metrics::new(None, Arc::default()),
+        )
+    }
+
+    const FAST: Duration = Duration::from_millis(1);
+
+    #[test]
+    fn errors_rate_limits_and_slow_requests_are_always_logged() {
+        let sampling = sampling(0.0, Some(500));
+        let ok = StatusCode::OK;
+        assert_eq!(sampling.verdict(ok, FAST, None), Verdict::SampledOut);
+        assert_eq!(sampling.verdict(ok, FAST, Some("id")), Verdict::SampledOut);
+        let error = StatusCode::BAD_GATEWAY;
+        assert_eq!(sampling.verdict(error, FAST, None), Verdict::Log);
+        let limited = StatusCode::TOO_MANY_REQUESTS;
+        assert_eq!(sampling.verdict(limited, FAST, None), Verdict::Log);
+        let slow = Duration::from_millis(500);
+        assert_eq!(sampling.verdict(ok, slow, None), Verdict::Slow);
+    }
+
+    #[test]
+    fn request_ids_sample_deterministically() {
+        let sampling = sampling(0.3, None);
+        let ids: Vec<String> = (0..1000).map(|n| format!("req-{}", n)).collect();
+        let kept = |sampling: &Sampling| -> Vec<bool> {
+            ids.iter()
+                .map(|id| sampling.verdict(StatusCode::OK, FAST, Some(id)) == Verdict::Log)
+                .collect()
+        };
+        let first = kept(&sampling);
+        assert_eq!(kept(&sampling), first);
+        let share = first.iter().filter(|kept| **kept).count();
+        assert!((200..400).contains(&share), "{share}");
+    }
+
+    #[test]
+    fn update_validates_and_turns_slow_logging_off_with_zero() {
+        let sampling = sampling(1.0, Some(250));
+        let bad = LoggingUpdate {
+            access_log_sample_rate: Some(1.5),
+            slow_request_ms: None,
+        };
+        assert!(sampling.update(bad).is_err());
+        let update = LoggingUpdate {
+            access_log_sample_rate: Some(0.1),
+            slow_request_ms: Some(0),
+        };
+        let settings = sampling.update(update).unwrap();
+        assert_eq!(
+            settings,
+            LoggingSettings {
+                access_log_sample_rate: 0.1,
+                slow_request_ms: None,
+            }
+        );
+    }
+}
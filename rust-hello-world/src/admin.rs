@@ -0,0 +1,49 @@
+use std::sync::Arc;
+
+use warp::reject::Reject;
+use warp::{Filter, Rejection};
+
+use crate::filters;
+
+#[derive(Debug)]
+pub struct Unauthorized;
+
+impl Reject for Unauthorized {}
+
+/// Requires a matching `X-Admin-Token` header when `expected` is set; with
+/// no token configured every request passes.
+pub fn require_token(
+    expected: Option<Arc<str>>,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::header::optional::<String>("x-admin-token")
+        .and_then(move |provided: Option<String>| {
+            let expected = expected.clone();
+            async move {
+                match expected {
+                    None => Ok(()),
+                    Some(expected) if provided.is_some_and(|p| constant_time_eq(&p, &expected)) => {
+                        Ok(())
+                    }
+                    Some(_) => Err(warp::reject::custom(Unauthorized)),
+                }
+            }
+        })
+        .untuple_one()
+}
+
+/// For admin-only routes that should not exist at all without a token:
+/// rejects as not found when `expected` is `None`, otherwise requires a
+/// matching `X-Admin-Token`.
+pub fn protected(
+    expected: Option<Arc<str>>,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    filters::enabled(expected.is_some()).and(require_token(expected))
+}
+
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0u8, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
@@ -0,0 +1,179 @@
+use std::sync::Arc;
+
+use serde::Serialize;
+use warp::path::FullPath;
+use warp::reject::Reject;
+use warp::{Filter, Rejection};
+
+use crate::config::Config;
+
+/// Rejection for a body over its route's limit. Serialized as-is into the
+/// 413 response.
+#[derive(Debug, Serialize)]
+pub struct PayloadTooLarge {
+    error: &'static str,
+    pub limit_bytes: u64,
+}
+
+impl PayloadTooLarge {
+    pub fn new(limit_bytes: u64) -> Self {
+        Self {
+            error: "request body too large",
+            limit_bytes,
+        }
+    }
+}
+
+impl Reject for PayloadTooLarge {}
+
+/// `MAX_BODY_BYTES` for every route, except path prefixes that were given
+/// their own limit with `with_override`.
+#[derive(Debug)]
+pub struct BodyLimit {
+    default: u64,
+    overrides: Vec<(&'static str, u64)>,
+}
+
+impl BodyLimit {
+    pub fn new(default: u64) -> Self {
+        Self {
+            default,
+            overrides: Vec::new(),
+        }
+    }
+
+    pub fn from_config(config: &Config) -> Self {
+        Self::new(config.max_body_bytes)
+    }
+
+    pub fn with_override(mut self, prefix: &'static str, limit: u64) -> Self {
+        self.overrides.push((prefix, limit));
+        self
+    }
+
+    pub fn for_path(&self, path: &str) -> u64 {
+        self.overrides
+            .iter()
+            .find(|(prefix, _)| path.starts_with(prefix))
+            .map_or(self.default, |(_, limit)| *limit)
+    }
+}
+
+/// Rejects with `PayloadTooLarge` when `Content-Length` is over the limit,
+/// before any route gets to buffer the body. Unlike
+/// `warp::body::content_length_limit` it lets through requests that have
+/// no body and no `Content-Length`, so plain GETs stay untouched. Chunked bodies carry no
+/// length; the filters that read them enforce the limit as they go.
+pub fn check(limit: Arc<BodyLimit>) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::path::full()
+        .and(warp::header::optional::<u64>("content-length"))
+        .and_then(move |path: FullPath, length: Option<u64>| {
+            let limit = limit.for_path(path.as_str());
+            async move {
+                match length {
+                    Some(length) if length > limit => {
+                        Err(warp::reject::custom(PayloadTooLarge::new(limit)))
+                    }
+                    _ => Ok(()),
+                }
+            }
+        })
+        .untuple_one()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use warp::http::StatusCode;
+    use warp::Reply;
+
+    use super::*;
+    use crate::filters;
+
+    fn route(
+        limit: BodyLimit,
+        ran: Arc<AtomicBool>,
+    ) -> impl Filter<Extract = (warp::reply::Response,), Error = std::convert::Infallible> + Clone
+    {
+        check(Arc::new(limit))
+            .and(warp::post())
+            .and(filters::json::<serde_json::Value>(
+                filters::DEFAULT_MAX_JSON_DEPTH,
+            ))
+            .map(move |_| {
+                ran.store(true, Ordering::SeqCst);
+                StatusCode::OK.into_response()
+            })
+            .recover(crate::handle_rejection)
+            .unify()
+    }
+
+    #[tokio::test]
+    async fn oversized_body_is_413_and_never_reaches_the_handler() {
+        let ran = Arc::new(AtomicBool::new(false));
+        let response = warp::test::request()
+            .method("POST")
+            .path("/echo")
+            .body(vec![b' '; 2048])
+            .reply(&route(BodyLimit::new(1024), ran.clone()))
+            .await;
+
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["error"], "request body too large");
+        assert_eq!(body["limit_bytes"], 1024);
+        assert!(!ran.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn deeply_nested_json_is_400_and_never_reaches_the_handler() {
+        let ran = Arc::new(AtomicBool::new(false));
+        let depth = filters::DEFAULT_MAX_JSON_DEPTH + 1;
+        let body = format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+        let response = warp::test::request()
+            .method("POST")
+            .path("/echo")
+            .body(body)
+            .reply(&route(BodyLimit::new(1024), ran.clone()))
+            .await;
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert!(body["error"].as_str().unwrap().contains("nested deeper"));
+        assert!(!ran.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn bodies_within_limits_reach_the_handler() {
+        let ran = Arc::new(AtomicBool::new(false));
+        let response = warp::test::request()
+            .method("POST")
+            .path("/echo")
+            .body(r#"{"a": [[1], {"b": "[[[["}]}"#)
+            .reply(&route(BodyLimit::new(1024), ran.clone()))
+            .await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(ran.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn requests_without_a_body_are_unaffected() {
+        let filter = check(Arc::new(BodyLimit::new(0)))
+            .and(warp::get())
+            .map(|| StatusCode::OK.into_response())
+            .recover(crate::handle_rejection)
+            .unify();
+        let response = warp::test::request().path("/").reply(&filter).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn overrides_apply_by_prefix() {
+        let limit = BodyLimit::new(1024).with_override("/upload", 1 << 30);
+        assert_eq!(limit.for_path("/upload"), 1 << 30);
+        assert_eq!(limit.for_path("/upload/stream"), 1 << 30);
+        assert_eq!(limit.for_path("/admin/crash"), 1024);
+    }
+}
@@ -0,0 +1,56 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// One value and when it was made, served until it is `ttl` old.
+///
+/// Concurrent misses may each rebuild the value; the last one stored wins.
+/// That is cheaper than making every reader wait on the one doing the work.
+pub struct TimedCache<T> {
+    ttl: Duration,
+    entry: Mutex<Option<(Instant, T)>>,
+}
+
+impl<T: Clone> TimedCache<T> {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entry: Mutex::new(None),
+        }
+    }
+
+    /// The cached value, unless there is none or it has expired.
+    pub fn get(&self) -> Option<T> {
+        let entry = self.entry.lock().unwrap_or_else(|e| e.into_inner());
+        entry
+            .as_ref()
+            .filter(|(created, _)| created.elapsed() < self.ttl)
+            .map(|(_, value)| value.clone())
+    }
+
+    pub fn put(&self, value: T) {
+        *self.entry.lock().unwrap_or_else(|e| e.into_inner()) = Some((Instant::now(), value));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serves_the_value_until_it_expires() {
+        let cache = TimedCache::new(Duration::from_millis(50));
+        assert_eq!(cache.get(), None);
+        cache.put("first".to_string());
+        assert_eq!(cache.get().as_deref(), Some("first"));
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(cache.get(), None);
+    }
+
+    #[test]
+    fn put_replaces_and_restarts_the_clock() {
+        let cache = TimedCache::new(Duration::from_secs(60));
+        cache.put(1);
+        cache.put(2);
+        assert_eq!(cache.get(), Some(2));
+    }
+}
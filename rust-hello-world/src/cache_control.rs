@@ -0,0 +1,74 @@
+//! `Cache-Control` per route group. Every value comes from the environment,
+//! so a CDN in front of one deployment can be told something different
+//! from another without a rebuild.
+
+use warp::http::header::{HeaderValue, CACHE_CONTROL};
+use warp::reply::Response;
+use warp::Reply;
+
+use crate::config::Config;
+
+pub struct CacheControl {
+    /// `/`, from `CACHE_CONTROL_ROOT`. `CACHE_CONTROL` never applies: the
+    /// body carries a timestamp and a request count.
+    pub root: HeaderValue,
+    /// `/version`, from `CACHE_CONTROL_VERSION` or else `CACHE_CONTROL`.
+    pub version: HeaderValue,
+    /// `/labels` and `/podinfo`, from `CACHE_CONTROL`.
+    pub data: HeaderValue,
+    /// `/static`, with `max-age` from `STATIC_CACHE_MAX_AGE`.
+    pub static_files: HeaderValue,
+}
+
+impl CacheControl {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            root: header_value("CACHE_CONTROL_ROOT", &config.cache_control_root),
+            version: match &config.cache_control_version {
+                Some(value) => header_value("CACHE_CONTROL_VERSION", value),
+                None => header_value("CACHE_CONTROL", &config.cache_control),
+            },
+            data: header_value("CACHE_CONTROL", &config.cache_control),
+            static_files: HeaderValue::from_str(&format!(
+                "public, max-age={}",
+                config.static_cache_max_age
+            ))
+            .expect("digits are a valid header value"),
+        }
+    }
+}
+
+/// `value`, or `no-store` if it cannot be sent as a header.
+fn header_value(name: &str, value: &str) -> HeaderValue {
+    HeaderValue::from_str(value).unwrap_or_else(|_| {
+        tracing::warn!(name, value, "invalid Cache-Control value, using no-store");
+        HeaderValue::from_static("no-store")
+    })
+}
+
+/// For `.map(...)` on a route group: sets `Cache-Control` on every
+/// response it produces, replacing any the handler set.
+pub fn set(value: HeaderValue) -> impl Fn(Response) -> Response + Clone {
+    move |reply| warp::reply::with_header(reply, CACHE_CONTROL, value.clone()).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid_values_fall_back_to_no_store() {
+        assert_eq!(header_value("X", "max-age=60"), "max-age=60");
+        assert_eq!(header_value("X", "bad\nvalue"), "no-store");
+    }
+
+    #[test]
+    fn set_replaces_the_handlers_value() {
+        let mut response = "hello".into_response();
+        response
+            .headers_mut()
+            .insert(CACHE_CONTROL, HeaderValue::from_static("max-age=5"));
+        let response = set(HeaderValue::from_static("no-store"))(response);
+        assert_eq!(response.headers()[CACHE_CONTROL], "no-store");
+    }
+}
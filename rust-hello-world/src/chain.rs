@@ -0,0 +1,367 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use warp::http::{HeaderMap, HeaderValue, StatusCode};
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+use crate::config::Config;
+use crate::hostname::Hostname;
+use crate::{reply, ErrorResponse};
+
+/// Forwarded untouched to the next hop so a mesh (or Zipkin/Jaeger via
+/// Envoy) stitches the hops into one trace. `x-request-id` is added when
+/// the caller did not send one.
+const PROPAGATED_HEADERS: &[&str] = &[
+    "x-request-id",
+    "traceparent",
+    "tracestate",
+    "b3",
+    "x-b3-traceid",
+    "x-b3-spanid",
+    "x-b3-parentspanid",
+    "x-b3-sampled",
+    "x-b3-flags",
+];
+
+pub(crate) const REQUEST_ID: &str = "x-request-id";
+
+/// Milliseconds of `CHAIN_BUDGET_MS` left when a hop was sent, so every
+/// instance in the chain shares the first caller's budget rather than
+/// starting its own.
+const BUDGET_HEADER: &str = "x-chain-budget-ms";
+
+/// A downstream answer larger than this is not a chain node; it is
+/// dropped rather than buffered.
+const MAX_DOWNSTREAM_BYTES: usize = 1024 * 1024;
+
+/// Settings and the pooled outbound client for `GET /chain`, from
+/// `CHAIN_MAX_HOPS`, `CHAIN_HOP_TIMEOUT_MS`, `CHAIN_BUDGET_MS` and
+/// `CHAIN_ALLOWED_VIA`.
+pub struct Chain {
+    client: Option<reqwest::Client>,
+    hostname: Hostname,
+    max_hops: u32,
+    hop_timeout: Duration,
+    budget: Duration,
+    allowed_via: Vec<String>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ChainQuery {
+    /// Further hops to make after this one; 0 when absent.
+    hops: Option<u32>,
+    /// Base URL of the next instance; required when `hops` is above 0.
+    via: Option<String>,
+}
+
+/// One instance's part of the chain, with the next hop nested inside.
+#[derive(Serialize, ToSchema)]
+pub(crate) struct ChainHop {
+    hostname: String,
+    /// Hops still to go below this one.
+    hops: u32,
+    request_id: String,
+    /// Time spent here, including every hop below.
+    latency_ms: f64,
+    /// The next hop's own `ChainHop`, or a `ChainError` if it failed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Object>)]
+    downstream: Option<Downstream>,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum Downstream {
+    Hop(serde_json::Value),
+    Error(ChainError),
+}
+
+/// Stands in for a hop that could not be reached or gave no usable answer.
+#[derive(Serialize, ToSchema)]
+pub(crate) struct ChainError {
+    via: String,
+    error: String,
+    latency_ms: f64,
+}
+
+impl Chain {
+    pub fn from_config(config: &Config, hostname: Hostname) -> Self {
+        let hop_timeout = Duration::from_millis(config.chain_hop_timeout_ms);
+        // Built once and shared by every request, so hops to the same
+        // service reuse pooled keep-alive connections.
+        let client = match reqwest::Client::builder()
+            .connect_timeout(hop_timeout)
+            .build()
+        {
+            Ok(client) => Some(client),
+            Err(err) => {
+                tracing::error!(error = %err, "cannot build chain client, hops will fail");
+                None
+            }
+        };
+        Self {
+            client,
+            hostname,
+            max_hops: config.chain_max_hops,
+            hop_timeout,
+            budget: Duration::from_millis(config.chain_budget_ms),
+            allowed_via: config.chain_allowed_via.clone(),
+        }
+    }
+
+    async fn handle(&self, query: ChainQuery, headers: HeaderMap) -> Response {
+        let start = Instant::now();
+        let hops = query.hops.unwrap_or(0);
+        if hops > self.max_hops {
+            return error(
+                StatusCode::BAD_REQUEST,
+                format!("at most {} hops", self.max_hops),
+            );
+        }
+        let mut forwarded = propagated(&headers);
+        let request_id = forwarded
+            .get(REQUEST_ID)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string)
+            .unwrap_or_else(|| {
+                let id = uuid::Uuid::new_v4().to_string();
+                if let Ok(value) = HeaderValue::from_str(&id) {
+                    forwarded.insert(REQUEST_ID, value);
+                }
+                id
+            });
+
+        let downstream = if hops == 0 {
+            None
+        } else {
+            let Some(via) = query.via else {
+                return error(
+                    StatusCode::BAD_REQUEST,
+                    "via is required when hops is above 0".to_string(),
+                );
+            };
+            let url = match self.next_hop(&via, hops - 1) {
+                Ok(url) => url,
+                Err(response) => return *response,
+            };
+            let budget = headers
+                .get(BUDGET_HEADER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse().ok())
+                .map_or(self.budget, |ms| self.budget.min(Duration::from_millis(ms)));
+            Some(self.call(url, via, forwarded, start, budget).await)
+        };
+
+        reply::json(&ChainHop {
+            hostname: self.hostname.get(),
+            hops,
+            request_id,
+            latency_ms: millis(start),
+            downstream,
+        })
+        .into_response()
+    }
+
+    /// Builds `{via}/chain?hops=..&via=..`, keeping any path prefix `via`
+    /// has. `hops` only ever decreases from one instance to the next, so a
+    /// `via` pointing back at ourselves still ends after `hops` calls.
+    fn next_hop(&self, via: &str, hops: u32) -> Result<reqwest::Url, Box<Response>> {
+        if !self.allowed_via.is_empty()
+            && !self
+                .allowed_via
+                .iter()
+                .any(|prefix| via.starts_with(prefix))
+        {
+            return Err(Box::new(error(
+                StatusCode::FORBIDDEN,
+                format!("{via} is not in CHAIN_ALLOWED_VIA"),
+            )));
+        }
+        let mut url = match reqwest::Url::parse(via) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") => url,
+            _ => {
+                return Err(Box::new(error(
+                    StatusCode::BAD_REQUEST,
+                    format!("{via} is not an http(s) URL"),
+                )))
+            }
+        };
+        let path = format!("{}/chain", url.path().trim_end_matches('/'));
+        url.set_path(&path);
+        url.query_pairs_mut()
+            .clear()
+            .append_pair("hops", &hops.to_string())
+            .append_pair("via", via);
+        Ok(url)
+    }
+
+    async fn call(
+        &self,
+        url: reqwest::Url,
+        via: String,
+        headers: HeaderMap,
+        start: Instant,
+        budget: Duration,
+    ) -> Downstream {
+        let sent = Instant::now();
+        let failed = |error: String| {
+            Downstream::Error(ChainError {
+                via: via.clone(),
+                error,
+                latency_ms: millis(sent),
+            })
+        };
+        let Some(client) = &self.client else {
+            return failed("no outbound client".to_string());
+        };
+        let remaining = budget.saturating_sub(start.elapsed());
+        if remaining.is_zero() {
+            return failed("chain budget exhausted".to_string());
+        }
+        // reqwest is on a newer `http` than warp, so headers cross as bytes.
+        let mut request = client
+            .get(url)
+            .header(BUDGET_HEADER, remaining.as_millis().to_string());
+        for (name, value) in &headers {
+            request = request.header(name.as_str(), value.as_bytes());
+        }
+        let timeout = self.hop_timeout.min(remaining);
+        let result = tokio::time::timeout(timeout, async {
+            let mut response = request.send().await.map_err(|err| err.to_string())?;
+            let status = response.status();
+            if !status.is_success() {
+                return Err(format!("answered {status}"));
+            }
+            let mut body = Vec::new();
+            while let Some(chunk) = response.chunk().await.map_err(|err| err.to_string())? {
+                if body.len() + chunk.len() > MAX_DOWNSTREAM_BYTES {
+                    return Err(format!("answer exceeds {MAX_DOWNSTREAM_BYTES} bytes"));
+                }
+                body.extend_from_slice(&chunk);
+            }
+            serde_json::from_slice(&body).map_err(|err| format!("answer is not JSON: {err}"))
+        })
+        .await;
+        match result {
+            Ok(Ok(hop)) => Downstream::Hop(hop),
+            Ok(Err(err)) => failed(err),
+            Err(_) => failed(format!("timed out after {}ms", timeout.as_millis())),
+        }
+    }
+}
+
+fn propagated(headers: &HeaderMap) -> HeaderMap {
+    let mut forwarded = HeaderMap::new();
+    for name in PROPAGATED_HEADERS {
+        if let Some(value) = headers.get(*name) {
+            forwarded.insert(*name, value.clone());
+        }
+    }
+    forwarded
+}
+
+fn millis(since: Instant) -> f64 {
+    since.elapsed().as_secs_f64() * 1000.0
+}
+
+fn error(status: StatusCode, error: String) -> Response {
+    warp::reply::with_status(reply::json(&ErrorResponse { error }), status).into_response()
+}
+
+#[utoipa::path(
+    get,
+    operation_id = "chain",
+    path = "/chain",
+    tag = "app",
+    params(ChainQuery),
+    responses(
+        (status = 200, description = "This hop, with any further hops nested in `downstream`", body = ChainHop),
+        (status = 400, description = "More than CHAIN_MAX_HOPS, or a missing or malformed via", body = ErrorResponse),
+        (status = 403, description = "via is not in CHAIN_ALLOWED_VIA", body = ErrorResponse),
+    )
+)]
+/// `GET /chain?hops=N&via=URL`: calls `via` with `hops - 1`, passing trace
+/// and request-id headers along, and nests its answer under `downstream`.
+/// A failed hop becomes an error node; the response is still a 200.
+pub fn route(chain: Arc<Chain>) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    warp::path("chain")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(warp::query::<ChainQuery>())
+        .and(warp::header::headers_cloned())
+        .then(move |query, headers| {
+            let chain = chain.clone();
+            async move { chain.handle(query, headers).await }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chain(allowed_via: &[&str]) -> Arc<Chain> {
+        let mut config = Config::from_env();
+        config.chain_max_hops = 5;
+        config.chain_hop_timeout_ms = 2000;
+        config.chain_budget_ms = 10_000;
+        config.chain_allowed_via = allowed_via.iter().map(|s| s.to_string()).collect();
+        Arc::new(Chain::from_config(
+            &config,
+            Hostname::Static(Arc::from("pod-a")),
+        ))
+    }
+
+    async fn get(chain: Arc<Chain>, path: &str) -> (StatusCode, serde_json::Value) {
+        let response = warp::test::request()
+            .path(path)
+            .header(
+                "traceparent",
+                "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01",
+            )
+            .reply(&route(chain))
+            .await;
+        let body = serde_json::from_slice(response.body()).unwrap();
+        (response.status(), body)
+    }
+
+    #[tokio::test]
+    async fn hops_nest_and_share_the_request_id() {
+        let (addr, server) = warp::serve(route(chain(&[]))).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        let (status, body) = get(chain(&[]), &format!("/chain?hops=2&via=http://{addr}")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["hops"], 2);
+        assert_eq!(body["downstream"]["hops"], 1);
+        assert_eq!(body["downstream"]["downstream"]["hops"], 0);
+        assert!(body["downstream"]["downstream"]["downstream"].is_null());
+        let id = body["request_id"].as_str().unwrap();
+        assert_eq!(body["downstream"]["request_id"], id);
+        assert_eq!(body["downstream"]["downstream"]["request_id"], id);
+    }
+
+    #[tokio::test]
+    async fn unreachable_hop_becomes_an_error_node() {
+        let (status, body) = get(chain(&[]), "/chain?hops=1&via=http://127.0.0.1:1").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["downstream"]["via"], "http://127.0.0.1:1");
+        assert!(body["downstream"]["error"].is_string());
+    }
+
+    #[tokio::test]
+    async fn too_many_hops_are_refused() {
+        let (status, _) = get(chain(&[]), "/chain?hops=6&via=http://127.0.0.1:1").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn via_outside_the_allow_list_is_refused() {
+        let chain = chain(&["http://rust-hello."]);
+        let (status, _) = get(chain, "/chain?hops=1&via=http://169.254.169.254").await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+}
@@ -0,0 +1,391 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rand_distr::{Distribution, Normal};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use warp::http::StatusCode;
+use warp::reject::Reject;
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+use crate::config::Config;
+use crate::lifecycle::Lifecycle;
+use crate::{admin, feature_flags, filters, reply, ErrorResponse};
+
+/// Floor on the `/admin/crash` delay so the 202 has left the socket before
+/// the process goes away.
+const MIN_EXIT_DELAY_MS: u64 = 100;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JitterDistribution {
+    /// Anywhere in `[0, max]` with equal probability.
+    Uniform,
+    /// Bell curve centred on `max / 2` (σ = `max / 6`), clamped to `[0, max]`.
+    Normal,
+}
+
+/// Artificial latency for `/`, from `RESPONSE_JITTER_MS` (upper bound),
+/// `JITTER_DISTRIBUTION` (`uniform` or `normal`) and `JITTER_SEED`. A fixed
+/// seed replays the same sequence of delays.
+pub struct Jitter {
+    max_ms: f64,
+    distribution: JitterDistribution,
+    rng: Mutex<StdRng>,
+}
+
+impl Jitter {
+    pub fn from_config(config: &Config) -> Option<Arc<Self>> {
+        let max_ms = config.response_jitter_ms.filter(|ms| *ms > 0)?;
+        let distribution = match config.jitter_distribution.as_deref() {
+            None | Some("uniform") => JitterDistribution::Uniform,
+            Some("normal") => JitterDistribution::Normal,
+            Some(other) => {
+                tracing::warn!(value = other, "unknown JITTER_DISTRIBUTION, using uniform");
+                JitterDistribution::Uniform
+            }
+        };
+        Some(Arc::new(Self::new(
+            max_ms,
+            distribution,
+            config.jitter_seed,
+        )))
+    }
+
+    pub fn new(max_ms: u64, distribution: JitterDistribution, seed: Option<u64>) -> Self {
+        Self {
+            max_ms: max_ms as f64,
+            distribution,
+            rng: seeded_rng(seed),
+        }
+    }
+
+    pub fn sample(&self) -> Duration {
+        let mut rng = self.rng.lock().unwrap_or_else(|e| e.into_inner());
+        let ms = match self.distribution {
+            JitterDistribution::Uniform => rng.gen_range(0.0..=self.max_ms),
+            JitterDistribution::Normal => {
+                let normal = Normal::new(self.max_ms / 2.0, self.max_ms / 6.0)
+                    .expect("standard deviation is finite and non-negative");
+                normal.sample(&mut *rng).clamp(0.0, self.max_ms)
+            }
+        };
+        Duration::from_secs_f64(ms / 1000.0)
+    }
+}
+
+/// Sleeps for a sampled delay before letting the request through. A no-op
+/// when jitter is not configured or `FF_SIMULATION_ENABLED` is off.
+pub fn jitter(jitter: Option<Arc<Jitter>>) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::any()
+        .and_then(move || {
+            let delay = jitter
+                .as_ref()
+                .filter(|_| feature_flags::is_enabled(feature_flags::SIMULATION))
+                .map(|j| j.sample());
+            async move {
+                if let Some(delay) = delay {
+                    tokio::time::sleep(delay).await;
+                }
+                Ok::<_, Rejection>(())
+            }
+        })
+        .untuple_one()
+}
+
+/// Rejection for a request picked to fail by `errors`.
+#[derive(Debug)]
+pub struct InjectedError;
+
+impl Reject for InjectedError {}
+
+/// Fails a random share of requests to `/`, from `VARIANT_ERROR_RATE`
+/// (`0.0`–`1.0`), so a canary can be made to trip error-rate analysis.
+/// `JITTER_SEED` fixes which requests fail as well.
+pub struct ErrorInjection {
+    rate: f64,
+    rng: Mutex<StdRng>,
+}
+
+impl ErrorInjection {
+    pub fn from_config(config: &Config) -> Option<Arc<Self>> {
+        let rate = config.variant_error_rate.filter(|rate| *rate > 0.0)?;
+        if rate > 1.0 {
+            tracing::warn!(rate, "VARIANT_ERROR_RATE is a fraction; failing every request");
+        }
+        Some(Arc::new(Self::new(rate, config.jitter_seed)))
+    }
+
+    pub fn new(rate: f64, seed: Option<u64>) -> Self {
+        Self {
+            rate: rate.min(1.0),
+            rng: seeded_rng(seed),
+        }
+    }
+
+    pub fn should_fail(&self) -> bool {
+        let mut rng = self.rng.lock().unwrap_or_else(|e| e.into_inner());
+        rng.gen_bool(self.rate)
+    }
+}
+
+/// Rejects with `InjectedError` at the configured rate. A no-op when
+/// `VARIANT_ERROR_RATE` is not set or `FF_SIMULATION_ENABLED` is off.
+pub fn errors(
+    injection: Option<Arc<ErrorInjection>>,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::any()
+        .and_then(move || {
+            let fail = injection.as_ref().is_some_and(|i| {
+                feature_flags::is_enabled(feature_flags::SIMULATION) && i.should_fail()
+            });
+            async move {
+                if fail {
+                    Err(warp::reject::custom(InjectedError))
+                } else {
+                    Ok(())
+                }
+            }
+        })
+        .untuple_one()
+}
+
+/// Turns `InjectedError` into its 500 on the route itself, so
+/// `metrics::instrument` counts it like any other failed request; error
+/// rate analysis would never see it as a rejection.
+pub async fn recover_injected(err: Rejection) -> Result<Response, Rejection> {
+    if err.find::<InjectedError>().is_none() {
+        return Err(err);
+    }
+    let error = "injected failure (VARIANT_ERROR_RATE)".to_string();
+    Ok(warp::reply::with_status(
+        reply::json(&ErrorResponse { error }),
+        StatusCode::INTERNAL_SERVER_ERROR,
+    )
+    .into_response())
+}
+
+/// A fixed seed replays the same sequence; otherwise every run differs.
+fn seeded_rng(seed: Option<u64>) -> Mutex<StdRng> {
+    Mutex::new(match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    })
+}
+
+#[derive(Deserialize, ToSchema)]
+#[serde(default)]
+pub(crate) struct CrashRequest {
+    exit_code: i32,
+    delay_ms: u64,
+}
+
+impl Default for CrashRequest {
+    fn default() -> Self {
+        Self {
+            exit_code: 1,
+            delay_ms: 0,
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct CrashResponse {
+    exit_code: i32,
+    delay_ms: u64,
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/crash",
+    tag = "admin",
+    request_body(content = Option<CrashRequest>, description = "Defaults to exit code 1 with no delay"),
+    security(("admin_token" = [])),
+    responses(
+        (status = 202, description = "Exit scheduled", body = CrashResponse),
+        (status = 400, description = "Malformed body", body = ErrorResponse),
+        (status = 401, description = "Missing or wrong X-Admin-Token", body = ErrorResponse),
+    )
+)]
+/// `POST /admin/crash`: replies 202, then exits with `exit_code` from a
+/// separate task once `delay_ms` has passed. Skips graceful shutdown on
+/// purpose, like a real crash would.
+pub fn crash(
+    max_json_depth: usize,
+    admin_token: Option<Arc<str>>,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    warp::path!("admin" / "crash")
+        .and(warp::post())
+        .and(admin::protected(admin_token))
+        .and(filters::optional_json::<CrashRequest>(max_json_depth))
+        .map(|request: CrashRequest| {
+            let CrashRequest {
+                exit_code,
+                delay_ms,
+            } = request;
+            tracing::warn!(exit_code, delay_ms, "crash requested via /admin/crash");
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(delay_ms.max(MIN_EXIT_DELAY_MS))).await;
+                tracing::warn!(exit_code, "exiting");
+                std::process::exit(exit_code);
+            });
+            warp::reply::with_status(
+                reply::json(&CrashResponse {
+                    exit_code,
+                    delay_ms,
+                }),
+                StatusCode::ACCEPTED,
+            )
+            .into_response()
+        })
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct PanicResponse {
+    panicked: bool,
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/panic",
+    tag = "admin",
+    security(("admin_token" = [])),
+    responses(
+        (status = 202, description = "Panic raised on a worker task", body = PanicResponse),
+        (status = 401, description = "Missing or wrong X-Admin-Token", body = ErrorResponse),
+    )
+)]
+/// `POST /admin/panic`: panics on a spawned worker task. Tokio contains the
+/// panic to that task, so the process keeps serving; the point is to see
+/// what a panic looks like in the logs.
+pub fn panic(
+    admin_token: Option<Arc<str>>,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    warp::path!("admin" / "panic")
+        .and(warp::post())
+        .and(admin::protected(admin_token))
+        .map(|| {
+            tracing::warn!("panic requested via /admin/panic");
+            tokio::spawn(async {
+                panic!("panic requested via /admin/panic");
+            });
+            warp::reply::with_status(
+                reply::json(&PanicResponse { panicked: true }),
+                StatusCode::ACCEPTED,
+            )
+            .into_response()
+        })
+}
+
+#[derive(Default, Deserialize, ToSchema)]
+pub(crate) struct UnhealthyRequest {
+    seconds: Option<u64>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct LivenessResponse {
+    healthy: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seconds: Option<u64>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/unhealthy",
+    tag = "admin",
+    request_body(content = Option<UnhealthyRequest>, description = "Omit `seconds` to fail until /admin/healthy"),
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "Liveness now failing", body = LivenessResponse),
+        (status = 400, description = "Malformed body", body = ErrorResponse),
+        (status = 401, description = "Missing or wrong X-Admin-Token", body = ErrorResponse),
+    )
+)]
+/// `POST /admin/unhealthy`: fails `/healthz` for `seconds`, or until
+/// `/admin/healthy` when omitted, so the kubelet restarts the container.
+pub fn unhealthy(
+    lifecycle: Arc<Lifecycle>,
+    max_json_depth: usize,
+    admin_token: Option<Arc<str>>,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    warp::path!("admin" / "unhealthy")
+        .and(warp::post())
+        .and(admin::protected(admin_token))
+        .and(filters::optional_json::<UnhealthyRequest>(max_json_depth))
+        .map(move |request: UnhealthyRequest| {
+            tracing::warn!(seconds = ?request.seconds, "liveness forced to fail");
+            lifecycle.force_unhealthy(request.seconds.map(Duration::from_secs));
+            reply::json(&LivenessResponse {
+                healthy: false,
+                seconds: request.seconds,
+            })
+            .into_response()
+        })
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/healthy",
+    tag = "admin",
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "Liveness override cleared", body = LivenessResponse),
+        (status = 401, description = "Missing or wrong X-Admin-Token", body = ErrorResponse),
+    )
+)]
+/// `POST /admin/healthy`: undoes `/admin/unhealthy`.
+pub fn healthy(
+    lifecycle: Arc<Lifecycle>,
+    admin_token: Option<Arc<str>>,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    warp::path!("admin" / "healthy")
+        .and(warp::post())
+        .and(admin::protected(admin_token))
+        .map(move || {
+            tracing::info!("liveness override cleared");
+            lifecycle.clear_unhealthy();
+            reply::json(&LivenessResponse {
+                healthy: true,
+                seconds: None,
+            })
+            .into_response()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samples(jitter: &Jitter) -> Vec<Duration> {
+        (0..32).map(|_| jitter.sample()).collect()
+    }
+
+    #[test]
+    fn same_seed_gives_same_delays() {
+        for distribution in [JitterDistribution::Uniform, JitterDistribution::Normal] {
+            let a = Jitter::new(200, distribution, Some(42));
+            let b = Jitter::new(200, distribution, Some(42));
+            assert_eq!(samples(&a), samples(&b));
+        }
+    }
+
+    #[test]
+    fn delays_stay_within_bound() {
+        for distribution in [JitterDistribution::Uniform, JitterDistribution::Normal] {
+            let jitter = Jitter::new(50, distribution, Some(7));
+            assert!(samples(&jitter)
+                .iter()
+                .all(|d| *d <= Duration::from_millis(50)));
+        }
+    }
+
+    #[test]
+    fn error_rate_is_roughly_honoured() {
+        let injection = ErrorInjection::new(0.25, Some(3));
+        let failures = (0..10_000).filter(|_| injection.should_fail()).count();
+        assert!((2_000..3_000).contains(&failures), "{} failures", failures);
+        assert!((0..100).all(|_| ErrorInjection::new(1.0, None).should_fail()));
+    }
+}
@@ -0,0 +1,364 @@
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+use ipnet::IpNet;
+use serde::Serialize;
+use utoipa::ToSchema;
+use warp::http::HeaderMap;
+use warp::Filter;
+
+use crate::server::{PeerAddr, CLIENT_CERT_SUBJECT};
+
+/// Which peers are allowed to tell us who the real client is.
+///
+/// Configured through `TRUST_PROXY`: unset/`false` ignores proxy headers,
+/// `true` trusts them from any peer, and a comma-separated CIDR list
+/// (e.g. `10.0.0.0/8,192.168.0.0/16`) trusts them only from those ranges.
+/// `TRUST_PROXY_HEADERS` is accepted as an alias. Off by default, so a
+/// directly exposed server cannot be fed a spoofed client address.
+#[derive(Debug, Default)]
+pub struct ProxyTrust {
+    enabled: bool,
+    allowlist: Vec<IpNet>,
+}
+
+impl ProxyTrust {
+    pub fn parse(value: Option<&str>) -> Self {
+        let value = match value.map(str::trim) {
+            None | Some("") => return Self::default(),
+            Some(v) => v,
+        };
+
+        match value.to_ascii_lowercase().as_str() {
+            "false" | "0" | "no" | "off" => return Self::default(),
+            "true" | "1" | "yes" | "on" => {
+                return Self {
+                    enabled: true,
+                    allowlist: Vec::new(),
+                }
+            }
+            _ => {}
+        }
+
+        let mut allowlist = Vec::new();
+        for entry in value.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let parsed = entry
+                .parse::<IpNet>()
+                .or_else(|_| entry.parse::<IpAddr>().map(IpNet::from));
+            match parsed {
+                Ok(net) => allowlist.push(net),
+                Err(_) => tracing::warn!(entry, "ignoring invalid TRUST_PROXY range"),
+            }
+        }
+
+        if allowlist.is_empty() {
+            tracing::warn!("TRUST_PROXY has no valid ranges, proxy headers will be ignored");
+            return Self::default();
+        }
+
+        Self {
+            enabled: true,
+            allowlist,
+        }
+    }
+
+    /// Whether forwarding headers sent by `peer` should be believed.
+    fn trusts_peer(&self, peer: Option<IpAddr>) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        if self.allowlist.is_empty() {
+            return true;
+        }
+        peer.is_some_and(|ip| self.is_trusted_proxy(ip))
+    }
+
+    fn is_trusted_proxy(&self, ip: IpAddr) -> bool {
+        self.allowlist.iter().any(|net| net.contains(&ip))
+    }
+}
+
+/// Everything we know about who is on the other end of a request.
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct ClientInfo {
+    #[schema(value_type = Option<String>, example = "10.0.0.7:51234")]
+    pub remote_addr: Option<SocketAddr>,
+    #[schema(value_type = Option<String>, example = "203.0.113.9")]
+    pub client_ip: Option<IpAddr>,
+    #[schema(value_type = String, example = "x-forwarded-for")]
+    pub source: &'static str,
+    #[schema(value_type = Vec<String>)]
+    pub forwarded_chain: Vec<IpAddr>,
+    pub proxy_headers_trusted: bool,
+    pub unparsed: Vec<String>,
+    /// Subject CN of the verified client certificate, under mTLS.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_cert_subject: Option<String>,
+}
+
+impl ClientInfo {
+    pub fn resolve(
+        trust: &ProxyTrust,
+        remote_addr: Option<SocketAddr>,
+        headers: &HeaderMap,
+    ) -> Self {
+        let peer_ip = remote_addr.map(|addr| addr.ip().to_canonical());
+        let mut info = ClientInfo {
+            remote_addr,
+            client_ip: peer_ip,
+            source: "socket",
+            forwarded_chain: Vec::new(),
+            proxy_headers_trusted: false,
+            unparsed: Vec::new(),
+            client_cert_subject: headers
+                .get(CLIENT_CERT_SUBJECT)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string),
+        };
+
+        if !trust.trusts_peer(peer_ip) {
+            return info;
+        }
+        info.proxy_headers_trusted = true;
+
+        let (source, nodes) = if headers.contains_key("forwarded") {
+            ("forwarded", forwarded_nodes(headers, &mut info.unparsed))
+        } else if headers.contains_key("x-forwarded-for") {
+            (
+                "x-forwarded-for",
+                list_nodes(headers, "x-forwarded-for", &mut info.unparsed),
+            )
+        } else if headers.contains_key("x-real-ip") {
+            (
+                "x-real-ip",
+                list_nodes(headers, "x-real-ip", &mut info.unparsed),
+            )
+        } else {
+            return info;
+        };
+
+        for node in nodes {
+            match parse_node(&node) {
+                Some(ip) => info.forwarded_chain.push(ip),
+                None => info.unparsed.push(node),
+            }
+        }
+
+        if let Some(ip) = pick_client(trust, &info.forwarded_chain) {
+            info.client_ip = Some(ip);
+            info.source = source;
+        }
+        info
+    }
+
+    /// Client IP formatted for log lines.
+    pub fn client_ip_display(&self) -> String {
+        self.client_ip
+            .map(|ip| ip.to_string())
+            .unwrap_or_else(|| "-".to_string())
+    }
+}
+
+/// Resolves the client for every request; never rejects.
+pub fn client_info(
+    trust: Arc<ProxyTrust>,
+) -> impl Filter<Extract = (ClientInfo,), Error = Infallible> + Clone {
+    warp::ext::optional::<PeerAddr>()
+        .and(warp::addr::remote())
+        .and(warp::header::headers_cloned())
+        .map(
+            move |peer: Option<PeerAddr>, remote: Option<SocketAddr>, headers: HeaderMap| {
+                let remote = peer.map(|p| p.0).or(remote);
+                ClientInfo::resolve(&trust, remote, &headers)
+            },
+        )
+}
+
+fn header_values(headers: &HeaderMap, name: &str, unparsed: &mut Vec<String>) -> Vec<String> {
+    let mut values = Vec::new();
+    for value in headers.get_all(name) {
+        match value.to_str() {
+            Ok(v) => values.push(v.to_string()),
+            Err(_) => unparsed.push(String::from_utf8_lossy(value.as_bytes()).into_owned()),
+        }
+    }
+    values
+}
+
+fn list_nodes(headers: &HeaderMap, name: &str, unparsed: &mut Vec<String>) -> Vec<String> {
+    header_values(headers, name, unparsed)
+        .iter()
+        .flat_map(|v| v.split(','))
+        .map(str::trim)
+        .filter(|n| !n.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Pulls the `for=` parameter out of each RFC 7239 `Forwarded` element.
+fn forwarded_nodes(headers: &HeaderMap, unparsed: &mut Vec<String>) -> Vec<String> {
+    let mut nodes = Vec::new();
+    for value in header_values(headers, "forwarded", unparsed) {
+        for element in value.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let node = element.split(';').find_map(|pair| {
+                let (key, value) = pair.split_once('=')?;
+                key.trim()
+                    .eq_ignore_ascii_case("for")
+                    .then(|| value.trim().to_string())
+            });
+            match node {
+                Some(node) => nodes.push(node),
+                None => unparsed.push(element.to_string()),
+            }
+        }
+    }
+    nodes
+}
+
+/// Accepts `ip`, `ip:port`, `[v6]`, `[v6]:port`, optionally double-quoted.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    if let Some(rest) = node.strip_prefix('[') {
+        let (host, _) = rest.split_once(']')?;
+        return host.parse::<IpAddr>().ok().map(|ip| ip.to_canonical());
+    }
+    node.parse::<IpAddr>()
+        .or_else(|_| node.parse::<SocketAddr>().map(|addr| addr.ip()))
+        .ok()
+        .map(|ip| ip.to_canonical())
+}
+
+/// With an allowlist, walk the chain from the nearest hop and take the first
+/// address that is not one of our own proxies. Without one, the leftmost
+/// entry is the original client.
+fn pick_client(trust: &ProxyTrust, chain: &[IpAddr]) -> Option<IpAddr> {
+    if trust.allowlist.is_empty() {
+        return chain.first().copied();
+    }
+    chain
+        .iter()
+        .rev()
+        .find(|ip| !trust.is_trusted_proxy(**ip))
+        .or_else(|| chain.first())
+        .copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use warp::http::HeaderValue;
+
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(*name, HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    fn peer(addr: &str) -> Option<SocketAddr> {
+        Some(addr.parse().unwrap())
+    }
+
+    fn ip(addr: &str) -> IpAddr {
+        addr.parse().unwrap()
+    }
+
+    #[test]
+    fn untrusted_peers_cannot_set_the_client() {
+        let spoofed = headers(&[
+            ("x-forwarded-for", "203.0.113.9"),
+            ("forwarded", "for=198.51.100.1"),
+        ]);
+        for trust in [ProxyTrust::parse(None), ProxyTrust::parse(Some("false"))] {
+            let info = ClientInfo::resolve(&trust, peer("10.0.0.7:5000"), &spoofed);
+            assert_eq!(info.client_ip, Some(ip("10.0.0.7")));
+            assert_eq!(info.source, "socket");
+            assert!(!info.proxy_headers_trusted);
+        }
+
+        let trust = ProxyTrust::parse(Some("10.0.0.0/8"));
+        let info = ClientInfo::resolve(&trust, peer("192.168.1.5:5000"), &spoofed);
+        assert_eq!(info.client_ip, Some(ip("192.168.1.5")));
+        assert!(!info.proxy_headers_trusted);
+    }
+
+    #[test]
+    fn trust_all_takes_the_leftmost_entry() {
+        let trust = ProxyTrust::parse(Some("true"));
+        let chain = headers(&[("x-forwarded-for", "203.0.113.9, 10.0.0.2, 10.0.0.3")]);
+        let info = ClientInfo::resolve(&trust, peer("192.168.1.5:5000"), &chain);
+        assert_eq!(info.client_ip, Some(ip("203.0.113.9")));
+        assert_eq!(info.source, "x-forwarded-for");
+        assert_eq!(info.forwarded_chain.len(), 3);
+    }
+
+    #[test]
+    fn cidr_list_walks_right_to_left_to_the_first_untrusted_hop() {
+        let trust = ProxyTrust::parse(Some("10.0.0.0/8, 192.168.0.1, not-a-range"));
+        assert_eq!(trust.allowlist.len(), 2);
+        let chain = headers(&[
+            ("x-forwarded-for", "198.51.100.7, 203.0.113.9"),
+            ("x-forwarded-for", "10.0.0.2"),
+        ]);
+        let info = ClientInfo::resolve(&trust, peer("10.0.0.3:5000"), &chain);
+        // 198.51.100.7 is further left but could have been sent by the
+        // client itself; 203.0.113.9 is the last hop we did not add.
+        assert_eq!(info.client_ip, Some(ip("203.0.113.9")));
+
+        let all_ours = headers(&[("x-forwarded-for", "10.0.0.1, 10.0.0.2")]);
+        let info = ClientInfo::resolve(&trust, peer("10.0.0.3:5000"), &all_ours);
+        assert_eq!(info.client_ip, Some(ip("10.0.0.1")));
+    }
+
+    #[test]
+    fn forwarded_accepts_bracketed_and_quoted_ipv6() {
+        let trust = ProxyTrust::parse(Some("true"));
+        let forwarded = headers(&[(
+            "forwarded",
+            r#"for="[2001:db8::1]:4711";proto=https, For="[::1]:80", for=192.0.2.60:8080"#,
+        )]);
+        let info = ClientInfo::resolve(&trust, peer("10.0.0.3:5000"), &forwarded);
+        assert_eq!(info.source, "forwarded");
+        assert_eq!(
+            info.forwarded_chain,
+            [ip("2001:db8::1"), ip("::1"), ip("192.0.2.60")]
+        );
+        assert_eq!(info.client_ip, Some(ip("2001:db8::1")));
+        assert_eq!(parse_node("[::ffff:192.0.2.1]:80"), Some(ip("192.0.2.1")));
+    }
+
+    #[test]
+    fn unknown_and_obfuscated_nodes_are_reported_not_used() {
+        let trust = ProxyTrust::parse(Some("true"));
+        let forwarded = headers(&[(
+            "forwarded",
+            "for=unknown, for=_hidden, proto=http, for=198.51.100.4",
+        )]);
+        let info = ClientInfo::resolve(&trust, peer("10.0.0.3:5000"), &forwarded);
+        assert_eq!(info.client_ip, Some(ip("198.51.100.4")));
+        assert_eq!(info.unparsed, ["proto=http", "unknown", "_hidden"]);
+    }
+
+    #[test]
+    fn malformed_values_end_up_in_unparsed() {
+        let trust = ProxyTrust::parse(Some("true"));
+        let mut garbage = headers(&[("x-forwarded-for", "999.1.1.1, [::1, :::, 203.0.113.9")]);
+        garbage.append(
+            "x-forwarded-for",
+            HeaderValue::from_bytes(b"\xff\xfe").unwrap(),
+        );
+        let info = ClientInfo::resolve(&trust, peer("10.0.0.3:5000"), &garbage);
+        assert_eq!(info.client_ip, Some(ip("203.0.113.9")));
+        assert_eq!(info.unparsed.len(), 4);
+        assert!(info.unparsed.contains(&"[::1".to_string()));
+
+        let nothing_usable = headers(&[("x-real-ip", "not-an-ip")]);
+        let info = ClientInfo::resolve(&trust, peer("10.0.0.3:5000"), &nothing_usable);
+        assert_eq!(info.client_ip, Some(ip("10.0.0.3")));
+        assert_eq!(info.source, "socket");
+        assert_eq!(info.unparsed, ["not-an-ip"]);
+    }
+}
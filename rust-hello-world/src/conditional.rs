@@ -0,0 +1,180 @@
+//! Strong `ETag`s and conditional GETs for JSON that only changes with the
+//! data behind it: `/version`, `/labels` and `/podinfo`.
+//!
+//! The ETag is a hash of the serialized body, recomputed only when the body
+//! differs from the last one rather than on every request. `Last-Modified`
+//! is the later of that change and the last config load, so clients that
+//! only keep dates can use `If-Modified-Since`.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+use warp::http::header::{HeaderValue, ETAG, LAST_MODIFIED};
+use warp::http::StatusCode;
+use warp::hyper::body::Bytes;
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+use crate::reload;
+use crate::reply::JsonBody;
+
+/// `If-None-Match` and `If-Modified-Since` from the request.
+#[derive(Default)]
+pub struct Conditions {
+    if_none_match: Option<String>,
+    if_modified_since: Option<String>,
+}
+
+impl Conditions {
+    /// Whether the client's copy is current. `If-Modified-Since` is only
+    /// looked at when there is no `If-None-Match`, as RFC 9110 requires.
+    fn fresh(&self, etag: &HeaderValue, last_modified: DateTime<Utc>) -> bool {
+        if let Some(if_none_match) = &self.if_none_match {
+            return etag_matches(if_none_match, etag);
+        }
+        self.if_modified_since
+            .as_deref()
+            .and_then(|since| DateTime::parse_from_rfc2822(since).ok())
+            .is_some_and(|since| last_modified.timestamp() <= since.timestamp())
+    }
+}
+
+pub fn conditions() -> impl Filter<Extract = (Conditions,), Error = Rejection> + Clone {
+    warp::header::optional::<String>("if-none-match")
+        .and(warp::header::optional::<String>("if-modified-since"))
+        .map(|if_none_match, if_modified_since| Conditions {
+            if_none_match,
+            if_modified_since,
+        })
+}
+
+/// `If-None-Match` uses weak comparison, so `W/` prefixes are ignored.
+fn etag_matches(if_none_match: &str, etag: &HeaderValue) -> bool {
+    let etag = etag.to_str().unwrap_or_default();
+    if_none_match.split(',').map(str::trim).any(|candidate| {
+        candidate == "*" || candidate.strip_prefix("W/").unwrap_or(candidate) == etag
+    })
+}
+
+struct Current {
+    body: Bytes,
+    etag: HeaderValue,
+    changed_at: DateTime<Utc>,
+}
+
+/// The validators for one route's body, kept until the body changes.
+#[derive(Default)]
+pub struct Tagged {
+    current: Mutex<Option<Current>>,
+}
+
+impl Tagged {
+    /// `body`, or a bodiless 304 when `conditions` match it. Both carry
+    /// `ETag` and `Last-Modified`.
+    pub fn reply(&self, body: JsonBody, conditions: &Conditions) -> Response {
+        let Some(bytes) = body.bytes() else {
+            return body.into_response();
+        };
+        let (etag, changed_at) = self.validators(bytes);
+        let last_modified = changed_at.max(reload::loaded_at());
+
+        let mut response = if conditions.fresh(&etag, last_modified) {
+            StatusCode::NOT_MODIFIED.into_response()
+        } else {
+            body.into_response()
+        };
+        let headers = response.headers_mut();
+        headers.insert(ETAG, etag);
+        if let Ok(value) = HeaderValue::try_from(http_date(last_modified)) {
+            headers.insert(LAST_MODIFIED, value);
+        }
+        response
+    }
+
+    fn validators(&self, body: Bytes) -> (HeaderValue, DateTime<Utc>) {
+        let mut current = self.current.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(current) = current.as_ref().filter(|current| current.body == body) {
+            return (current.etag.clone(), current.changed_at);
+        }
+        let mut hasher = DefaultHasher::new();
+        body.hash(&mut hasher);
+        let etag = HeaderValue::try_from(format!("\"{:016x}\"", hasher.finish()))
+            .expect("hex ETag is a valid header value");
+        let changed_at = Utc::now();
+        *current = Some(Current {
+            body,
+            etag: etag.clone(),
+            changed_at,
+        });
+        (etag, changed_at)
+    }
+}
+
+/// IMF-fixdate, the only format `Last-Modified` may be sent in.
+fn http_date(at: DateTime<Utc>) -> String {
+    at.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reply;
+
+    fn conditions(if_none_match: Option<&str>, if_modified_since: Option<&str>) -> Conditions {
+        Conditions {
+            if_none_match: if_none_match.map(String::from),
+            if_modified_since: if_modified_since.map(String::from),
+        }
+    }
+
+    #[test]
+    fn matching_etag_is_a_bodiless_304() {
+        let tagged = Tagged::default();
+        let first = tagged.reply(reply::json(&"a"), &Conditions::default());
+        assert_eq!(first.status(), StatusCode::OK);
+        let etag = first.headers()[ETAG].to_str().unwrap().to_string();
+
+        let weak = format!("W/{}", etag);
+        for candidate in [etag.as_str(), "\"other\", *", weak.as_str()] {
+            let res = tagged.reply(reply::json(&"a"), &conditions(Some(candidate), None));
+            assert_eq!(res.status(), StatusCode::NOT_MODIFIED, "{candidate}");
+            assert_eq!(res.headers()[ETAG], etag.as_str());
+        }
+        let stale = tagged.reply(reply::json(&"a"), &conditions(Some("\"other\""), None));
+        assert_eq!(stale.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn etag_changes_with_the_body() {
+        let tagged = Tagged::default();
+        let etag = |body| {
+            tagged
+                .reply(reply::json(&body), &Conditions::default())
+                .headers()[ETAG]
+                .clone()
+        };
+        let a = etag("a");
+        assert_eq!(etag("a"), a);
+        assert_ne!(etag("b"), a);
+    }
+
+    #[test]
+    fn if_modified_since_is_honoured_unless_if_none_match_is_sent() {
+        let tagged = Tagged::default();
+        let first = tagged.reply(reply::json(&"a"), &Conditions::default());
+        let last_modified = first.headers()[LAST_MODIFIED].to_str().unwrap().to_string();
+
+        let res = tagged.reply(reply::json(&"a"), &conditions(None, Some(&last_modified)));
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+        let old = "Wed, 21 Oct 2015 07:28:00 GMT";
+        let res = tagged.reply(reply::json(&"a"), &conditions(None, Some(old)));
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = tagged.reply(
+            reply::json(&"a"),
+            &conditions(Some("\"other\""), Some(&last_modified)),
+        );
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+}
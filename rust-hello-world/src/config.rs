@@ -0,0 +1,477 @@
+use std::env;
+use std::str::FromStr;
+
+use std::sync::LazyLock;
+
+use regex::Regex;
+use serde::ser::{SerializeStruct, Serializer};
+use serde::Serialize;
+
+use crate::filters;
+
+/// How `/config` writes a redacted value.
+pub const REDACTED: &str = "[REDACTED]";
+/// How `/debug/config` writes one.
+pub const DEBUG_REDACTED: &str = "***";
+const DEFAULT_GREETING: &str = "Hello World from Rust! 🦀";
+
+/// Field names whose values never leave the process. Matching is on the
+/// Rust field name, so new secrets are covered as long as they are named
+/// like one.
+static SENSITIVE_FIELD: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new("(?i)(secret|password|token|key|credential)").expect("valid regex")
+});
+
+/// Effective configuration, read once from the environment at startup.
+///
+/// Not `Serialize` itself: every serialized view (`/config`,
+/// `/debug/config`) goes through `redacted`, so sensitive fields are
+/// replaced without touching the struct and each view keeps its own marker.
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub port: u16,
+    pub bind_addr: Option<String>,
+    pub unix_socket_path: Option<String>,
+    pub admin_port: Option<u16>,
+    pub port_retry_seconds: u64,
+    pub trust_proxy: Option<String>,
+    pub tcp_keepalive_secs: Option<u64>,
+    pub tcp_keepalive_interval_secs: Option<u64>,
+    pub tcp_keepalive_retries: Option<u32>,
+    pub admin_token: Option<String>,
+    pub enable_debug_endpoints: bool,
+    pub pod_namespace: Option<String>,
+    pub node_name: Option<String>,
+    pub extra_response_headers: Option<String>,
+    pub labels_file: Option<String>,
+    pub annotations_file: Option<String>,
+    pub response_label_allowlist: Vec<String>,
+    pub warmup_seconds: u64,
+    pub cors_allowed_origins: Vec<String>,
+    pub cors_allowed_methods: Option<String>,
+    pub cors_allowed_headers: Option<String>,
+    pub cors_max_age: Option<u64>,
+    pub cors_allow_credentials: bool,
+    pub upstream_url: Option<String>,
+    pub upstream_check_interval_secs: u64,
+    pub upstream_max_backoff_secs: u64,
+    pub response_jitter_ms: Option<u64>,
+    pub jitter_distribution: Option<String>,
+    pub jitter_seed: Option<u64>,
+    pub fs_allowed_paths: Vec<String>,
+    pub fs_max_file_bytes: u64,
+    pub refresh_hostname_seconds: Option<u64>,
+    pub env_redact_patterns: Vec<String>,
+    pub metrics_push_url: Option<String>,
+    pub metrics_push_job: String,
+    pub metrics_push_interval_seconds: u64,
+    pub metrics_push_delete_on_shutdown: bool,
+    pub mirror_url: Option<String>,
+    pub mirror_sample_percent: f64,
+    pub mirror_max_in_flight: usize,
+    pub mirror_max_per_second: u32,
+    pub maintenance_mode: bool,
+    pub max_connections: Option<usize>,
+    pub max_body_bytes: u64,
+    pub max_json_depth: usize,
+    pub enable_upload: bool,
+    pub upload_dir: String,
+    pub max_upload_bytes: u64,
+    pub upload_allowed_types: Vec<String>,
+    pub max_transfer_mb: u64,
+    pub max_concurrent_transfers: usize,
+    pub dependency_checks: Vec<String>,
+    pub self_ping_targets: Vec<String>,
+    pub self_ping_interval_seconds: u64,
+    pub self_ping_max_backoff_seconds: u64,
+    pub variant: Option<String>,
+    pub variant_error_rate: Option<f64>,
+    pub tls_cert_path: Option<String>,
+    pub tls_key_path: Option<String>,
+    pub mtls_ca_cert_path: Option<String>,
+    pub greeting: String,
+    pub log_level: Option<String>,
+    pub access_log_sample_rate: f64,
+    pub slow_request_ms: Option<u64>,
+    pub config_file: Option<String>,
+    pub hello_cache_ttl_ms: u64,
+    pub timezone: Option<String>,
+    pub trailing_slash: Option<String>,
+    pub cache_control_root: String,
+    pub cache_control: String,
+    pub cache_control_version: Option<String>,
+    pub static_dir: Option<String>,
+    pub static_cache_max_age: u64,
+    pub chain_max_hops: u32,
+    pub chain_hop_timeout_ms: u64,
+    pub chain_budget_ms: u64,
+    pub chain_allowed_via: Vec<String>,
+    pub enable_profiling: bool,
+    pub kv_max_entries: usize,
+    pub kv_max_bytes: u64,
+    pub kv_sweep_interval_seconds: u64,
+    pub resource_sample_interval_seconds: u64,
+    pub health_mem_threshold_percent: Option<f64>,
+    pub health_mem_consecutive_samples: u32,
+    pub health_mem_hysteresis_percent: f64,
+}
+
+impl Config {
+    pub fn from_env() -> Self {
+        Self {
+            port: env::var("PORT")
+                .unwrap_or_else(|_| "8080".to_string())
+                .parse::<u16>()
+                .unwrap_or(8080),
+            bind_addr: env_string("BIND_ADDR"),
+            unix_socket_path: env_string("UNIX_SOCKET_PATH"),
+            admin_port: env_parse("ADMIN_PORT"),
+            port_retry_seconds: env_parse("PORT_RETRY_SECONDS").unwrap_or(0),
+            trust_proxy: env_string("TRUST_PROXY").or_else(|| env_string("TRUST_PROXY_HEADERS")),
+            tcp_keepalive_secs: env_parse("TCP_KEEPALIVE_SECS"),
+            tcp_keepalive_interval_secs: env_parse("TCP_KEEPALIVE_INTERVAL_SECS"),
+            tcp_keepalive_retries: env_parse("TCP_KEEPALIVE_RETRIES"),
+            admin_token: env_string("ADMIN_TOKEN"),
+            enable_debug_endpoints: env_flag("ENABLE_DEBUG_ENDPOINTS"),
+            pod_namespace: env_string("POD_NAMESPACE"),
+            node_name: env_string("NODE_NAME"),
+            extra_response_headers: env_string("EXTRA_RESPONSE_HEADERS"),
+            labels_file: env_string("LABELS_FILE"),
+            annotations_file: env_string("ANNOTATIONS_FILE"),
+            response_label_allowlist: env_list("RESPONSE_LABEL_KEYS"),
+            warmup_seconds: env_parse("WARMUP_SECONDS").unwrap_or(0),
+            cors_allowed_origins: env_list("CORS_ALLOWED_ORIGINS"),
+            cors_allowed_methods: env_string("CORS_ALLOWED_METHODS"),
+            cors_allowed_headers: env_string("CORS_ALLOWED_HEADERS"),
+            cors_max_age: env_parse("CORS_MAX_AGE"),
+            cors_allow_credentials: env_flag("CORS_ALLOW_CREDENTIALS"),
+            upstream_url: env_string("UPSTREAM_URL"),
+            upstream_check_interval_secs: env_parse("UPSTREAM_CHECK_INTERVAL_SECS").unwrap_or(10),
+            upstream_max_backoff_secs: env_parse("UPSTREAM_MAX_BACKOFF_SECS").unwrap_or(60),
+            response_jitter_ms: env_parse("RESPONSE_JITTER_MS"),
+            jitter_distribution: env_string("JITTER_DISTRIBUTION").map(|v| v.to_ascii_lowercase()),
+            jitter_seed: env_parse("JITTER_SEED"),
+            fs_allowed_paths: match env_list("FS_ALLOWED_PATHS") {
+                paths if paths.is_empty() => ["/etc/config", "/etc/secrets", "/data"]
+                    .map(String::from)
+                    .to_vec(),
+                paths => paths,
+            },
+            fs_max_file_bytes: env_parse("FS_MAX_FILE_BYTES").unwrap_or(1024 * 1024),
+            refresh_hostname_seconds: env_parse("REFRESH_HOSTNAME_SECONDS"),
+            metrics_push_url: env_string("METRICS_PUSH_URL"),
+            metrics_push_job: env_string("METRICS_PUSH_JOB")
+                .unwrap_or_else(|| "rust-hello-world".to_string()),
+            metrics_push_interval_seconds: env_parse("METRICS_PUSH_INTERVAL_SECONDS").unwrap_or(15),
+            metrics_push_delete_on_shutdown: env_flag("METRICS_PUSH_DELETE_ON_SHUTDOWN"),
+            mirror_url: env_string("MIRROR_TARGET").or_else(|| env_string("MIRROR_URL")),
+            mirror_sample_percent: env_parse("MIRROR_SAMPLE_PERCENT").unwrap_or(100.0),
+            mirror_max_in_flight: env_parse("MIRROR_MAX_IN_FLIGHT").unwrap_or(64),
+            mirror_max_per_second: env_parse("MIRROR_MAX_PER_SECOND").unwrap_or(100),
+            maintenance_mode: env_flag("MAINTENANCE_MODE"),
+            max_connections: env_parse("MAX_CONNECTIONS"),
+            max_body_bytes: env_parse("MAX_BODY_BYTES").unwrap_or(1024 * 1024),
+            enable_upload: env_flag("ENABLE_UPLOAD"),
+            upload_dir: env_string("UPLOAD_DIR").unwrap_or_else(|| "/tmp/uploads".to_string()),
+            max_upload_bytes: env_parse("MAX_UPLOAD_BYTES").unwrap_or(10 * 1024 * 1024),
+            upload_allowed_types: match env_list("UPLOAD_ALLOWED_TYPES") {
+                types if types.is_empty() => [
+                    "text/plain",
+                    "application/json",
+                    "application/octet-stream",
+                    "application/pdf",
+                    "image/png",
+                    "image/jpeg",
+                ]
+                .map(String::from)
+                .to_vec(),
+                types => types,
+            },
+            max_transfer_mb: env_parse("MAX_TRANSFER_MB").unwrap_or(512),
+            max_concurrent_transfers: env_parse("MAX_CONCURRENT_TRANSFERS").unwrap_or(4),
+            dependency_checks: env_list("DEPENDENCY_CHECKS"),
+            self_ping_targets: env_list("SELF_PING_TARGETS"),
+            self_ping_interval_seconds: env_parse("SELF_PING_INTERVAL_SECONDS").unwrap_or(30),
+            self_ping_max_backoff_seconds: env_parse("SELF_PING_MAX_BACKOFF_SECONDS")
+                .unwrap_or(300),
+            variant: env_string("VARIANT"),
+            variant_error_rate: env_parse("VARIANT_ERROR_RATE"),
+            tls_cert_path: env_string("TLS_CERT_PATH"),
+            tls_key_path: env_string("TLS_KEY_PATH"),
+            mtls_ca_cert_path: env_string("MTLS_CA_CERT_PATH"),
+            greeting: env_string("GREETING").unwrap_or_else(|| DEFAULT_GREETING.to_string()),
+            log_level: env_string("LOG_LEVEL"),
+            access_log_sample_rate: env_parse::<f64>("ACCESS_LOG_SAMPLE_RATE")
+                .filter(|rate| rate.is_finite())
+                .map_or(1.0, |rate| rate.clamp(0.0, 1.0)),
+            slow_request_ms: env_parse("SLOW_REQUEST_MS").filter(|ms| *ms > 0),
+            config_file: env_string("CONFIG_FILE"),
+            hello_cache_ttl_ms: env_parse("HELLO_CACHE_TTL_MS").unwrap_or(100),
+            timezone: env_string("TIMEZONE"),
+            trailing_slash: env_string("TRAILING_SLASH").map(|v| v.to_ascii_lowercase()),
+            cache_control_root: env_string("CACHE_CONTROL_ROOT")
+                .unwrap_or_else(|| "no-store".to_string()),
+            cache_control: env_string("CACHE_CONTROL").unwrap_or_else(|| "no-store".to_string()),
+            cache_control_version: env_string("CACHE_CONTROL_VERSION"),
+            static_dir: env_string("STATIC_DIR"),
+            static_cache_max_age: env_parse("STATIC_CACHE_MAX_AGE").unwrap_or(300),
+            chain_max_hops: env_parse("CHAIN_MAX_HOPS").unwrap_or(5),
+            chain_hop_timeout_ms: env_parse("CHAIN_HOP_TIMEOUT_MS").unwrap_or(2000),
+            chain_budget_ms: env_parse("CHAIN_BUDGET_MS").unwrap_or(10_000),
+            chain_allowed_via: env_list("CHAIN_ALLOWED_VIA"),
+            enable_profiling: env_flag("ENABLE_PROFILING"),
+            kv_max_entries: env_parse("KV_MAX_ENTRIES").unwrap_or(10_000),
+            kv_max_bytes: env_parse("KV_MAX_BYTES").unwrap_or(16 * 1024 * 1024),
+            kv_sweep_interval_seconds: env_parse("KV_SWEEP_INTERVAL_SECONDS").unwrap_or(30),
+            resource_sample_interval_seconds: env_parse("RESOURCE_SAMPLE_INTERVAL_SECONDS")
+                .unwrap_or(5),
+            health_mem_threshold_percent: env_parse("HEALTH_MEM_THRESHOLD_PERCENT"),
+            health_mem_consecutive_samples: env_parse("HEALTH_MEM_CONSECUTIVE_SAMPLES")
+                .unwrap_or(3),
+            health_mem_hysteresis_percent: env_parse("HEALTH_MEM_HYSTERESIS_PERCENT")
+                .unwrap_or(5.0),
+            max_json_depth: env_parse("MAX_JSON_DEPTH").unwrap_or(filters::DEFAULT_MAX_JSON_DEPTH),
+            env_redact_patterns: match env_list("ENV_REDACT_PATTERNS") {
+                patterns if patterns.is_empty() => ["*TOKEN*", "*SECRET*", "*PASSWORD*", "*KEY*"]
+                    .map(String::from)
+                    .to_vec(),
+                patterns => patterns,
+            },
+        }
+    }
+}
+
+/// `Config` with each sensitive value written as `marker`.
+pub struct Redacted<'a> {
+    config: &'a Config,
+    marker: &'static str,
+}
+
+impl Config {
+    pub fn redacted(&self, marker: &'static str) -> Redacted<'_> {
+        Redacted {
+            config: self,
+            marker,
+        }
+    }
+}
+
+/// Writes each listed field of `Redacted`'s config, swapping sensitive
+/// values for its marker. The exhaustive destructuring makes forgetting a
+/// new field a compile error.
+macro_rules! redacting_serialize {
+    ($ty:ident { $($field:ident),* $(,)? }) => {
+        impl Serialize for Redacted<'_> {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                let $ty { $($field),* } = self.config;
+                let mut state =
+                    serializer.serialize_struct(stringify!($ty), [$(stringify!($field)),*].len())?;
+                $(serialize_field(&mut state, stringify!($field), $field, self.marker)?;)*
+                state.end()
+            }
+        }
+    };
+}
+
+redacting_serialize!(Config {
+    port,
+    bind_addr,
+    unix_socket_path,
+    admin_port,
+    port_retry_seconds,
+    trust_proxy,
+    tcp_keepalive_secs,
+    tcp_keepalive_interval_secs,
+    tcp_keepalive_retries,
+    admin_token,
+    enable_debug_endpoints,
+    pod_namespace,
+    node_name,
+    extra_response_headers,
+    labels_file,
+    annotations_file,
+    response_label_allowlist,
+    warmup_seconds,
+    cors_allowed_origins,
+    cors_allowed_methods,
+    cors_allowed_headers,
+    cors_max_age,
+    cors_allow_credentials,
+    upstream_url,
+    upstream_check_interval_secs,
+    upstream_max_backoff_secs,
+    response_jitter_ms,
+    jitter_distribution,
+    jitter_seed,
+    fs_allowed_paths,
+    fs_max_file_bytes,
+    refresh_hostname_seconds,
+    env_redact_patterns,
+    metrics_push_url,
+    metrics_push_job,
+    metrics_push_interval_seconds,
+    metrics_push_delete_on_shutdown,
+    mirror_url,
+    mirror_sample_percent,
+    mirror_max_in_flight,
+    mirror_max_per_second,
+    maintenance_mode,
+    max_connections,
+    max_body_bytes,
+    max_json_depth,
+    enable_upload,
+    upload_dir,
+    max_upload_bytes,
+    upload_allowed_types,
+    max_transfer_mb,
+    max_concurrent_transfers,
+    dependency_checks,
+    self_ping_targets,
+    self_ping_interval_seconds,
+    self_ping_max_backoff_seconds,
+    variant,
+    variant_error_rate,
+    tls_cert_path,
+    tls_key_path,
+    mtls_ca_cert_path,
+    greeting,
+    log_level,
+    access_log_sample_rate,
+    slow_request_ms,
+    config_file,
+    hello_cache_ttl_ms,
+    timezone,
+    trailing_slash,
+    cache_control_root,
+    cache_control,
+    cache_control_version,
+    static_dir,
+    static_cache_max_age,
+    chain_max_hops,
+    chain_hop_timeout_ms,
+    chain_budget_ms,
+    chain_allowed_via,
+    enable_profiling,
+    kv_max_entries,
+    kv_max_bytes,
+    kv_sweep_interval_seconds,
+    resource_sample_interval_seconds,
+    health_mem_threshold_percent,
+    health_mem_consecutive_samples,
+    health_mem_hysteresis_percent,
+});
+
+fn serialize_field<S, T>(
+    state: &mut S,
+    name: &'static str,
+    value: &T,
+    marker: &'static str,
+) -> Result<(), S::Error>
+where
+    S: SerializeStruct,
+    T: Serialize + ?Sized,
+{
+    if !SENSITIVE_FIELD.is_match(name) {
+        return state.serialize_field(name, value);
+    }
+    // Leave unset secrets as null so "was it injected at all?" stays answerable.
+    if serde_json::to_value(value).is_ok_and(|v| v.is_null()) {
+        state.serialize_field(name, &None::<()>)
+    } else {
+        state.serialize_field(name, marker)
+    }
+}
+
+/// Non-empty value of `name`, if set.
+pub fn env_string(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|v| !v.trim().is_empty())
+}
+
+/// Comma-separated `name`, trimmed, with empty items dropped.
+pub fn env_list(name: &str) -> Vec<String> {
+    env_string(name)
+        .map(|v| {
+            v.split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// `true`/`1`/`yes`/`on` (any case) enable a flag; anything else leaves it off.
+pub fn env_flag(name: &str) -> bool {
+    env::var(name).map(|v| parse_flag(&v)).unwrap_or(false)
+}
+
+pub fn parse_flag(value: &str) -> bool {
+    matches!(
+        value.trim().to_ascii_lowercase().as_str(),
+        "true" | "1" | "yes" | "on"
+    )
+}
+
+/// Parses `name`, warning and falling back to `None` when it is malformed.
+pub fn env_parse<T: FromStr>(name: &str) -> Option<T> {
+    let value = env_string(name)?;
+    match value.trim().parse() {
+        Ok(parsed) => Some(parsed),
+        Err(_) => {
+            tracing::warn!(name, value, "ignoring unparseable environment variable");
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secrets_are_redacted_and_the_rest_pass_through() {
+        let config = Config {
+            port: 8123,
+            admin_token: Some("hunter2".to_string()),
+            tls_key_path: Some("/etc/tls/tls.key".to_string()),
+            tls_cert_path: Some("/etc/tls/tls.crt".to_string()),
+            ..Config::from_env()
+        };
+        let shown = serde_json::to_value(config.redacted(REDACTED)).unwrap();
+        assert_eq!(shown["admin_token"], REDACTED);
+        assert_eq!(shown["tls_key_path"], REDACTED);
+        assert_eq!(shown["port"], 8123);
+        assert_eq!(shown["tls_cert_path"], "/etc/tls/tls.crt");
+        assert!(!shown.to_string().contains("hunter2"));
+    }
+
+    #[test]
+    fn unset_secrets_stay_null() {
+        let config = Config {
+            admin_token: None,
+            tls_key_path: None,
+            ..Config::from_env()
+        };
+        let shown = serde_json::to_value(config.redacted(REDACTED)).unwrap();
+        assert!(shown["admin_token"].is_null());
+        assert!(shown["tls_key_path"].is_null());
+    }
+
+    #[test]
+    fn sensitive_names_are_matched_whatever_their_affixes() {
+        for name in [
+            "admin_token",
+            "tls_key_path",
+            "upstream_api_key",
+            "db_password",
+            "client_secret",
+            "aws_credentials",
+            "REFRESH_TOKEN",
+        ] {
+            assert!(SENSITIVE_FIELD.is_match(name), "{name}");
+        }
+        for name in ["port", "tls_cert_path", "mtls_ca_cert_path", "greeting"] {
+            assert!(!SENSITIVE_FIELD.is_match(name), "{name}");
+        }
+    }
+}
@@ -0,0 +1,333 @@
+use std::convert::Infallible;
+use std::sync::Arc;
+
+use warp::http::header::{
+    HeaderMap, HeaderValue, ACCESS_CONTROL_ALLOW_CREDENTIALS, ACCESS_CONTROL_ALLOW_HEADERS,
+    ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_MAX_AGE,
+    ACCESS_CONTROL_REQUEST_HEADERS, ORIGIN, VARY,
+};
+use warp::http::StatusCode;
+use warp::reply::Response;
+use warp::{Filter, Reply};
+
+use crate::config::Config;
+
+const DEFAULT_METHODS: &str = "GET, POST, PUT, DELETE, OPTIONS";
+
+enum AllowedOrigins {
+    Any,
+    List(Vec<String>),
+}
+
+/// CORS settings from `CORS_ALLOWED_ORIGINS`, `CORS_ALLOWED_METHODS`,
+/// `CORS_ALLOWED_HEADERS`, `CORS_MAX_AGE` and `CORS_ALLOW_CREDENTIALS`.
+pub struct CorsPolicy {
+    origins: AllowedOrigins,
+    methods: HeaderValue,
+    headers: Option<HeaderValue>,
+    max_age: Option<HeaderValue>,
+    credentials: bool,
+}
+
+impl CorsPolicy {
+    /// `None` when no origins are configured, which leaves CORS off.
+    pub fn from_config(config: &Config) -> Option<Self> {
+        Self::new(
+            &config.cors_allowed_origins,
+            config.cors_allowed_methods.as_deref(),
+            config.cors_allowed_headers.as_deref(),
+            config.cors_max_age,
+            config.cors_allow_credentials,
+        )
+    }
+
+    pub fn new(
+        origins: &[String],
+        methods: Option<&str>,
+        headers: Option<&str>,
+        max_age: Option<u64>,
+        credentials: bool,
+    ) -> Option<Self> {
+        if origins.is_empty() {
+            return None;
+        }
+        let origins = if origins.iter().any(|o| o == "*") {
+            AllowedOrigins::Any
+        } else {
+            AllowedOrigins::List(origins.to_vec())
+        };
+        let header = |value: &str, name: &str| {
+            HeaderValue::from_str(value)
+                .map_err(|_| tracing::warn!(name, value, "ignoring invalid CORS setting"))
+                .ok()
+        };
+
+        Some(Self {
+            origins,
+            methods: methods
+                .and_then(|m| header(m, "CORS_ALLOWED_METHODS"))
+                .unwrap_or_else(|| HeaderValue::from_static(DEFAULT_METHODS)),
+            headers: headers.and_then(|h| header(h, "CORS_ALLOWED_HEADERS")),
+            max_age: max_age.map(HeaderValue::from),
+            credentials,
+        })
+    }
+
+    /// Value for `Access-Control-Allow-Origin`, or `None` for origins we do
+    /// not serve. `*` is only used when credentials are off; browsers reject
+    /// it on credentialed requests, so the origin is echoed instead.
+    fn allow_origin(&self, origin: &str) -> Option<HeaderValue> {
+        let allowed = match &self.origins {
+            AllowedOrigins::Any if !self.credentials => return Some(HeaderValue::from_static("*")),
+            AllowedOrigins::Any => true,
+            AllowedOrigins::List(list) => list.iter().any(|o| o == origin),
+        };
+        allowed.then(|| HeaderValue::from_str(origin).ok())?
+    }
+
+    fn apply(&self, origin: &str, headers: &mut HeaderMap) -> bool {
+        let Some(allow_origin) = self.allow_origin(origin) else {
+            return false;
+        };
+        if allow_origin != "*" {
+            headers.append(VARY, HeaderValue::from_static("Origin"));
+        }
+        headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+        if self.credentials {
+            headers.insert(
+                ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                HeaderValue::from_static("true"),
+            );
+        }
+        true
+    }
+
+    fn preflight(&self, origin: &str, request_headers: Option<HeaderValue>) -> Response {
+        let mut response = StatusCode::NO_CONTENT.into_response();
+        let headers = response.headers_mut();
+        if self.apply(origin, headers) {
+            headers.insert(ACCESS_CONTROL_ALLOW_METHODS, self.methods.clone());
+            // Without an explicit list, allow whatever the browser asked for.
+            if let Some(allowed) = self.headers.clone().or(request_headers) {
+                headers.insert(ACCESS_CONTROL_ALLOW_HEADERS, allowed);
+            }
+            if let Some(max_age) = &self.max_age {
+                headers.insert(ACCESS_CONTROL_MAX_AGE, max_age.clone());
+            }
+        }
+        response
+    }
+}
+
+/// Answers CORS preflights for any path with 204 before `filter` runs and
+/// adds `Access-Control-Allow-Origin` to other responses for allowed
+/// origins. Disallowed origins simply get no CORS headers.
+pub fn wrap<F, R>(
+    filter: F,
+    policy: Option<Arc<CorsPolicy>>,
+) -> impl Filter<Extract = (Response,), Error = Infallible> + Clone
+where
+    F: Filter<Extract = (R,), Error = Infallible> + Clone + Send + Sync + 'static,
+    R: Reply,
+{
+    let preflight = {
+        let policy = policy.clone();
+        warp::options()
+            .and(warp::header::<String>("origin"))
+            .and(warp::header::<String>("access-control-request-method"))
+            .and(warp::header::headers_cloned())
+            .and_then(move |origin: String, _method: String, headers: HeaderMap| {
+                let policy = policy.clone();
+                async move {
+                    match policy {
+                        Some(policy) => Ok(policy.preflight(
+                            &origin,
+                            headers.get(ACCESS_CONTROL_REQUEST_HEADERS).cloned(),
+                        )),
+                        None => Err(warp::reject::not_found()),
+                    }
+                }
+            })
+    };
+
+    let actual =
+        warp::header::headers_cloned()
+            .and(filter)
+            .map(move |headers: HeaderMap, reply: R| {
+                let mut response = reply.into_response();
+                let origin = headers.get(ORIGIN).and_then(|o| o.to_str().ok());
+                if let (Some(policy), Some(origin)) = (&policy, origin) {
+                    policy.apply(origin, response.headers_mut());
+                }
+                response
+            });
+
+    preflight.or(actual).unify()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn policy(origins: &[&str], credentials: bool) -> Option<Arc<CorsPolicy>> {
+        let origins: Vec<String> = origins.iter().map(|o| o.to_string()).collect();
+        CorsPolicy::new(&origins, None, Some("content-type"), Some(600), credentials).map(Arc::new)
+    }
+
+    fn counting_route(
+        hits: Arc<AtomicUsize>,
+    ) -> impl Filter<Extract = (Response,), Error = Infallible> + Clone {
+        warp::any().map(move || {
+            hits.fetch_add(1, Ordering::SeqCst);
+            "hello".into_response()
+        })
+    }
+
+    #[tokio::test]
+    async fn preflight_is_answered_without_calling_handler() {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let filter = wrap(
+            counting_route(hits.clone()),
+            policy(&["https://dash.local"], false),
+        );
+
+        let response = warp::test::request()
+            .method("OPTIONS")
+            .path("/anything")
+            .header("origin", "https://dash.local")
+            .header("access-control-request-method", "POST")
+            .reply(&filter)
+            .await;
+
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(
+            response.headers()[ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://dash.local"
+        );
+        assert_eq!(
+            response.headers()[ACCESS_CONTROL_ALLOW_METHODS],
+            DEFAULT_METHODS
+        );
+        assert_eq!(
+            response.headers()[ACCESS_CONTROL_ALLOW_HEADERS],
+            "content-type"
+        );
+        assert_eq!(response.headers()[ACCESS_CONTROL_MAX_AGE], "600");
+        assert_eq!(hits.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn preflight_from_disallowed_origin_has_no_cors_headers() {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let filter = wrap(
+            counting_route(hits.clone()),
+            policy(&["https://dash.local"], false),
+        );
+
+        let response = warp::test::request()
+            .method("OPTIONS")
+            .header("origin", "https://evil.example")
+            .header("access-control-request-method", "GET")
+            .reply(&filter)
+            .await;
+
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert!(response
+            .headers()
+            .get(ACCESS_CONTROL_ALLOW_ORIGIN)
+            .is_none());
+        assert_eq!(hits.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn simple_request_from_allowed_origin_is_tagged() {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let filter = wrap(
+            counting_route(hits.clone()),
+            policy(&["https://dash.local"], false),
+        );
+
+        let response = warp::test::request()
+            .header("origin", "https://dash.local")
+            .reply(&filter)
+            .await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://dash.local"
+        );
+        assert_eq!(response.headers()[VARY], "Origin");
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn simple_request_from_disallowed_origin_still_succeeds() {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let filter = wrap(
+            counting_route(hits.clone()),
+            policy(&["https://dash.local"], false),
+        );
+
+        let response = warp::test::request()
+            .header("origin", "https://evil.example")
+            .reply(&filter)
+            .await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response
+            .headers()
+            .get(ACCESS_CONTROL_ALLOW_ORIGIN)
+            .is_none());
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn wildcard_uses_star_without_credentials() {
+        let filter = wrap(counting_route(Arc::default()), policy(&["*"], false));
+
+        let response = warp::test::request()
+            .header("origin", "https://any.example")
+            .reply(&filter)
+            .await;
+
+        assert_eq!(response.headers()[ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+        assert!(response
+            .headers()
+            .get(ACCESS_CONTROL_ALLOW_CREDENTIALS)
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn wildcard_echoes_origin_with_credentials() {
+        let filter = wrap(counting_route(Arc::default()), policy(&["*"], true));
+
+        let response = warp::test::request()
+            .header("origin", "https://any.example")
+            .reply(&filter)
+            .await;
+
+        assert_eq!(
+            response.headers()[ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://any.example"
+        );
+        assert_eq!(response.headers()[ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+    }
+
+    #[tokio::test]
+    async fn disabled_policy_passes_options_through() {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let filter = wrap(counting_route(hits.clone()), None);
+
+        let response = warp::test::request()
+            .method("OPTIONS")
+            .header("origin", "https://dash.local")
+            .header("access-control-request-method", "GET")
+            .reply(&filter)
+            .await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+    }
+}
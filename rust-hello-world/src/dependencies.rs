@@ -0,0 +1,167 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use tokio::net::TcpStream;
+use utoipa::ToSchema;
+use warp::http::StatusCode;
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+use crate::config::Config;
+use crate::reply;
+
+/// Per-dependency bound, covering DNS resolution as well as the connect,
+/// so one black-holed address cannot stall the whole check.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The `host:port` endpoints listed in `DEPENDENCY_CHECKS`.
+pub struct Dependencies {
+    targets: Vec<String>,
+    timeout: Duration,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct DependencyStatus {
+    name: String,
+    ok: bool,
+    latency_ms: f64,
+    error: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct DependencyReport {
+    /// `ok` when every dependency connected, otherwise `degraded`.
+    status: &'static str,
+    dependencies: Vec<DependencyStatus>,
+}
+
+impl Dependencies {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            targets: config.dependency_checks.clone(),
+            timeout: CONNECT_TIMEOUT,
+        }
+    }
+
+    /// Checks every target concurrently, so the report takes as long as
+    /// the slowest one rather than the sum of them.
+    async fn check_all(&self) -> DependencyReport {
+        let checks: Vec<_> = self
+            .targets
+            .iter()
+            .map(|target| tokio::spawn(check(target.clone(), self.timeout)))
+            .collect();
+        let mut dependencies = Vec::with_capacity(checks.len());
+        for (target, handle) in self.targets.iter().zip(checks) {
+            dependencies.push(handle.await.unwrap_or_else(|err| DependencyStatus {
+                name: target.clone(),
+                ok: false,
+                latency_ms: 0.0,
+                error: Some(err.to_string()),
+            }));
+        }
+        let status = if dependencies.iter().all(|d| d.ok) {
+            "ok"
+        } else {
+            "degraded"
+        };
+        DependencyReport {
+            status,
+            dependencies,
+        }
+    }
+}
+
+async fn check(name: String, timeout: Duration) -> DependencyStatus {
+    let start = Instant::now();
+    let error = match tokio::time::timeout(timeout, TcpStream::connect(name.as_str())).await {
+        Ok(Ok(_)) => None,
+        Ok(Err(err)) => Some(err.to_string()),
+        Err(_) => Some(format!("timed out after {}s", timeout.as_secs_f64())),
+    };
+    DependencyStatus {
+        name,
+        ok: error.is_none(),
+        latency_ms: start.elapsed().as_secs_f64() * 1000.0,
+        error,
+    }
+}
+
+#[utoipa::path(
+    get,
+    operation_id = "dependencies",
+    path = "/healthz/dependencies",
+    tag = "probes",
+    responses(
+        (status = 200, description = "Every dependency accepted a TCP connection", body = DependencyReport),
+        (status = 503, description = "At least one dependency is unreachable", body = DependencyReport),
+    )
+)]
+/// `GET /healthz/dependencies`: TCP-connects to each `DEPENDENCY_CHECKS`
+/// endpoint. Deliberately separate from `/healthz`, since a database
+/// outage is no reason for the kubelet to restart us.
+pub fn route(
+    dependencies: Arc<Dependencies>,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    warp::path!("healthz" / "dependencies")
+        .and(warp::get())
+        .and_then(move || {
+            let dependencies = dependencies.clone();
+            async move {
+                let report = dependencies.check_all().await;
+                let status = if report.status == "ok" {
+                    StatusCode::OK
+                } else {
+                    StatusCode::SERVICE_UNAVAILABLE
+                };
+                Ok::<_, Rejection>(
+                    warp::reply::with_status(reply::json(&report), status).into_response(),
+                )
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn reports_each_target_and_fails_if_any_is_down() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let up = listener.local_addr().unwrap().to_string();
+        let down = {
+            let closed = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            closed.local_addr().unwrap().to_string()
+        };
+        let dependencies = Dependencies {
+            targets: vec![up.clone(), down.clone()],
+            timeout: CONNECT_TIMEOUT,
+        };
+
+        let report = dependencies.check_all().await;
+
+        assert_eq!(report.status, "degraded");
+        assert_eq!(report.dependencies[0].name, up);
+        assert!(report.dependencies[0].ok);
+        assert!(report.dependencies[0].error.is_none());
+        assert_eq!(report.dependencies[1].name, down);
+        assert!(!report.dependencies[1].ok);
+        assert!(report.dependencies[1].error.is_some());
+    }
+
+    #[tokio::test]
+    async fn no_dependencies_is_healthy() {
+        let filter = route(Arc::new(Dependencies {
+            targets: Vec::new(),
+            timeout: CONNECT_TIMEOUT,
+        }));
+        let res = warp::test::request()
+            .path("/healthz/dependencies")
+            .reply(&filter)
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+}
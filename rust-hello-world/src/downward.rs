@@ -0,0 +1,248 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long a parsed file is reused before it is read again. Short enough
+/// that a `kubectl label` shows up within a few seconds.
+const CACHE_TTL: Duration = Duration::from_secs(5);
+
+/// A Downward API volume file (`metadata.labels` / `metadata.annotations`),
+/// re-read when the cached copy is older than `CACHE_TTL` so runtime label
+/// patches are picked up without a restart.
+pub struct DownwardFile {
+    path: PathBuf,
+    ttl: Duration,
+    cache: Mutex<Option<(Instant, BTreeMap<String, String>)>>,
+}
+
+impl DownwardFile {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            ttl: CACHE_TTL,
+            cache: Mutex::new(None),
+        }
+    }
+
+    pub fn read(&self) -> BTreeMap<String, String> {
+        let mut cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some((loaded_at, entries)) = cache.as_ref() {
+            if loaded_at.elapsed() < self.ttl {
+                return entries.clone();
+            }
+        }
+
+        let entries = match fs::read_to_string(&self.path) {
+            Ok(content) => parse(&content),
+            Err(err) => {
+                tracing::warn!(path = %self.path.display(), error = %err, "failed to read downward API file");
+                BTreeMap::new()
+            }
+        };
+        *cache = Some((Instant::now(), entries.clone()));
+        entries
+    }
+}
+
+/// Parses the `key="value"` lines kubelet writes. Values are Go-quoted, so
+/// they may contain escapes; a value whose closing quote is on a later line
+/// is joined across lines. Malformed entries are skipped with a warning, and
+/// when joining leads nowhere only the first line is dropped, so one stray
+/// quote does not take the entries after it along.
+pub fn parse(content: &str) -> BTreeMap<String, String> {
+    let lines: Vec<&str> = content.lines().collect();
+    let mut entries = BTreeMap::new();
+    let mut start = 0;
+
+    while start < lines.len() {
+        if lines[start].trim().is_empty() {
+            start += 1;
+            continue;
+        }
+        let mut entry = lines[start].to_string();
+        let mut end = start;
+        loop {
+            match parse_entry(&entry) {
+                Ok((key, value)) => {
+                    entries.insert(key, value);
+                    start = end + 1;
+                    break;
+                }
+                Err(EntryError::Unterminated) if end + 1 < lines.len() => {
+                    end += 1;
+                    entry.push('\n');
+                    entry.push_str(lines[end]);
+                }
+                Err(err) => {
+                    let reason = match err {
+                        EntryError::Unterminated => "unterminated value",
+                        EntryError::Invalid(reason) => reason,
+                    };
+                    tracing::warn!(
+                        line = start + 1,
+                        reason,
+                        "skipping malformed downward API entry"
+                    );
+                    start += 1;
+                    break;
+                }
+            }
+        }
+    }
+    entries
+}
+
+enum EntryError {
+    Unterminated,
+    Invalid(&'static str),
+}
+
+fn parse_entry(entry: &str) -> Result<(String, String), EntryError> {
+    let (key, rest) = entry
+        .split_once('=')
+        .ok_or(EntryError::Invalid("missing '='"))?;
+    let key = key.trim();
+    if key.is_empty() || key.chars().any(char::is_whitespace) {
+        return Err(EntryError::Invalid("invalid key"));
+    }
+
+    let rest = rest.trim_start();
+    let quoted = rest
+        .strip_prefix('"')
+        .ok_or(EntryError::Invalid("value is not quoted"))?;
+    let (value, trailing) = unquote(quoted)?;
+    if !trailing.trim().is_empty() {
+        return Err(EntryError::Invalid("unexpected text after value"));
+    }
+    Ok((key.to_string(), value))
+}
+
+/// Decodes a Go-quoted string body (after the opening quote), returning the
+/// value and whatever follows the closing quote.
+fn unquote(input: &str) -> Result<(String, &str), EntryError> {
+    let mut value = String::new();
+    let mut chars = input.char_indices();
+
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '"' => return Ok((value, &input[index + 1..])),
+            '\\' => {
+                let (_, escape) = chars.next().ok_or(EntryError::Unterminated)?;
+                match escape {
+                    '"' => value.push('"'),
+                    '\\' => value.push('\\'),
+                    '\'' => value.push('\''),
+                    'n' => value.push('\n'),
+                    't' => value.push('\t'),
+                    'r' => value.push('\r'),
+                    'a' => value.push('\u{07}'),
+                    'b' => value.push('\u{08}'),
+                    'f' => value.push('\u{0c}'),
+                    'v' => value.push('\u{0b}'),
+                    'x' | 'u' | 'U' => {
+                        let digits = match escape {
+                            'x' => 2,
+                            'u' => 4,
+                            _ => 8,
+                        };
+                        let hex: String = chars.by_ref().take(digits).map(|(_, c)| c).collect();
+                        let code = u32::from_str_radix(&hex, 16)
+                            .ok()
+                            .filter(|_| hex.len() == digits)
+                            .ok_or(EntryError::Invalid("bad hex escape"))?;
+                        value.push(
+                            char::from_u32(code).ok_or(EntryError::Invalid("bad code point"))?,
+                        );
+                    }
+                    '0'..='7' => {
+                        let rest: String = chars.by_ref().take(2).map(|(_, c)| c).collect();
+                        let code = u32::from_str_radix(&format!("{escape}{rest}"), 8)
+                            .ok()
+                            .filter(|_| rest.len() == 2)
+                            .ok_or(EntryError::Invalid("bad octal escape"))?;
+                        value.push(
+                            char::from_u32(code).ok_or(EntryError::Invalid("bad code point"))?,
+                        );
+                    }
+                    _ => return Err(EntryError::Invalid("unknown escape")),
+                }
+            }
+            c => value.push(c),
+        }
+    }
+    Err(EntryError::Unterminated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn parses_escaped_quotes() {
+        assert_eq!(parse(r#"key="a \"b\"""#), entries(&[("key", r#"a "b""#)]));
+    }
+
+    #[test]
+    fn decodes_escapes_and_joins_multi_line_values() {
+        let content = concat!(
+            r#"app="web""#,
+            "\n",
+            r#"note="line one\nline two\t\u00e9\x41\101\\""#,
+            "\n",
+            "multi=\"first\nsecond\"\n",
+        );
+        assert_eq!(
+            parse(content),
+            entries(&[
+                ("app", "web"),
+                ("note", "line one\nline two\t\u{e9}AA\\"),
+                ("multi", "first\nsecond"),
+            ])
+        );
+    }
+
+    #[test]
+    fn malformed_lines_are_skipped_and_the_rest_survive() {
+        let content = concat!(
+            "a=\"1\"\n",
+            "no-equals-sign\n",
+            "b=\"unterminated\n",
+            "c=\"3\"\n",
+            "d=unquoted\n",
+            "e=\"bad \\q escape\"\n",
+            "f=\"6\" trailing\n",
+            "g=\"7\"\n",
+            "h=\"never closed\n",
+        );
+        assert_eq!(
+            parse(content),
+            entries(&[("a", "1"), ("c", "3"), ("g", "7")])
+        );
+    }
+
+    #[test]
+    fn rereads_the_file_once_the_cache_expires() {
+        let path = std::env::temp_dir().join(format!("labels-{}", uuid::Uuid::new_v4()));
+        fs::write(&path, "app=\"v1\"\n").unwrap();
+        let file = DownwardFile {
+            ttl: Duration::from_millis(50),
+            ..DownwardFile::new(&path)
+        };
+        assert_eq!(file.read(), entries(&[("app", "v1")]));
+
+        fs::write(&path, "app=\"v2\"\n").unwrap();
+        assert_eq!(file.read(), entries(&[("app", "v1")]), "still cached");
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(file.read(), entries(&[("app", "v2")]));
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,163 @@
+//! Drain mode: every application route answers 503 while probes and admin
+//! routes keep working.
+//!
+//! Meant for node maintenance: the load balancer sees 503s and stops
+//! sending traffic, while `/health`, `/healthz` and `/healthz/live` never
+//! look at the flag so the kubelet does not restart the pod. Unlike `POST /shutdown`, the
+//! process keeps running and `POST /undrain` puts it straight back.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use serde::Serialize;
+use utoipa::ToSchema;
+use warp::reject::Reject;
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+use crate::{admin, reply};
+
+#[derive(Debug)]
+pub struct Draining;
+
+impl Reject for Draining {}
+
+/// Flipped at runtime through `POST /drain` and `POST /undrain`.
+#[derive(Default)]
+pub struct Drain {
+    draining: AtomicBool,
+}
+
+impl Drain {
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::Acquire)
+    }
+
+    pub fn set(&self, draining: bool) {
+        self.draining.store(draining, Ordering::Release);
+    }
+}
+
+/// Rejects with `Draining` while drain mode is on. Goes in front of the
+/// application routes, before any path matching, so none of their
+/// handlers run.
+pub fn check(drain: Arc<Drain>) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::any()
+        .and_then(move || {
+            let draining = drain.is_draining();
+            async move {
+                if draining {
+                    Err(warp::reject::custom(Draining))
+                } else {
+                    Ok(())
+                }
+            }
+        })
+        .untuple_one()
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct DrainState {
+    draining: bool,
+}
+
+fn toggle(
+    name: &'static str,
+    draining: bool,
+    drain: Arc<Drain>,
+    admin_token: Option<Arc<str>>,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    warp::path(name)
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(admin::protected(admin_token))
+        .map(move || {
+            drain.set(draining);
+            tracing::warn!(draining, "drain mode changed via /{}", name);
+            reply::json(&DrainState { draining }).into_response()
+        })
+}
+
+#[utoipa::path(
+    post,
+    operation_id = "drain",
+    path = "/drain",
+    tag = "admin",
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "Application routes now answer 503; probes are unaffected", body = DrainState),
+        (status = 401, description = "Missing or wrong X-Admin-Token", body = ErrorResponse),
+    )
+)]
+/// `POST /drain`. Absent unless `ADMIN_TOKEN` is set.
+pub fn route(
+    drain: Arc<Drain>,
+    admin_token: Option<Arc<str>>,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    toggle("drain", true, drain, admin_token)
+}
+
+#[utoipa::path(
+    post,
+    operation_id = "undrain",
+    path = "/undrain",
+    tag = "admin",
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "Application routes serve traffic again", body = DrainState),
+        (status = 401, description = "Missing or wrong X-Admin-Token", body = ErrorResponse),
+    )
+)]
+/// `POST /undrain`. Absent unless `ADMIN_TOKEN` is set.
+pub fn undrain_route(
+    drain: Arc<Drain>,
+    admin_token: Option<Arc<str>>,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    toggle("undrain", false, drain, admin_token)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn drain_and_undrain_toggle_the_check() {
+        let drain = Arc::new(Drain::default());
+        let token = Some(Arc::from("secret"));
+        let routes = route(drain.clone(), token.clone()).or(undrain_route(drain.clone(), token));
+        let guarded = check(drain.clone()).map(warp::reply);
+
+        assert!(warp::test::request().filter(&guarded).await.is_ok());
+
+        let res = warp::test::request()
+            .method("POST")
+            .path("/drain")
+            .header("x-admin-token", "secret")
+            .reply(&routes)
+            .await;
+        assert_eq!(res.body(), r#"{"draining":true}"#);
+        let rejection = warp::test::request().filter(&guarded).await.err().unwrap();
+        assert!(rejection.find::<Draining>().is_some());
+
+        warp::test::request()
+            .method("POST")
+            .path("/undrain")
+            .header("x-admin-token", "secret")
+            .reply(&routes)
+            .await;
+        assert!(!drain.is_draining());
+        assert!(warp::test::request().filter(&guarded).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn requires_the_admin_token() {
+        let drain = Arc::new(Drain::default());
+        let res = warp::test::request()
+            .method("POST")
+            .path("/drain")
+            .filter(&route(drain.clone(), Some(Arc::from("secret"))))
+            .await;
+        assert!(res.is_err_and(|err| err.find::<admin::Unauthorized>().is_some()));
+        assert!(!drain.is_draining());
+    }
+}
@@ -0,0 +1,79 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use serde::Deserialize;
+use utoipa::IntoParams;
+use warp::http::StatusCode;
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+use crate::redact::RedactPatterns;
+use crate::{admin, reply, ErrorResponse};
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct EnvQuery {
+    /// Return only this variable; 404 if it is not set.
+    name: Option<String>,
+    /// Return only variables starting with this prefix.
+    prefix: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    operation_id = "env",
+    path = "/env",
+    tag = "admin",
+    params(EnvQuery),
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "Environment, sorted, with matching values replaced by `<redacted:len=N>`", body = BTreeMap<String, String>),
+        (status = 401, description = "Missing or wrong X-Admin-Token", body = ErrorResponse),
+        (status = 404, description = "`name` is not set", body = ErrorResponse),
+    )
+)]
+/// `GET /env`: the process environment as the pod actually received it,
+/// with values whose names match `ENV_REDACT_PATTERNS` redacted. Absent
+/// unless `ADMIN_TOKEN` is set.
+pub fn route(
+    patterns: Arc<RedactPatterns>,
+    admin_token: Option<Arc<str>>,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    warp::path("env")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(admin::protected(admin_token))
+        .and(warp::query::<EnvQuery>())
+        .map(move |query: EnvQuery| {
+            let vars: BTreeMap<String, String> = std::env::vars_os()
+                .map(|(name, value)| {
+                    (
+                        name.to_string_lossy().into_owned(),
+                        value.to_string_lossy().into_owned(),
+                    )
+                })
+                .filter(|(name, _)| query.name.as_ref().is_none_or(|wanted| name == wanted))
+                .filter(|(name, _)| {
+                    query
+                        .prefix
+                        .as_ref()
+                        .is_none_or(|prefix| name.starts_with(prefix.as_str()))
+                })
+                .map(|(name, value)| {
+                    let value = patterns.apply(&name, value);
+                    (name, value)
+                })
+                .collect();
+
+            if let Some(name) = query.name.filter(|_| vars.is_empty()) {
+                return warp::reply::with_status(
+                    reply::json(&ErrorResponse {
+                        error: format!("{} is not set", name),
+                    }),
+                    StatusCode::NOT_FOUND,
+                )
+                .into_response();
+            }
+            reply::json(&vars).into_response()
+        })
+}
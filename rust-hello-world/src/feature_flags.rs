@@ -0,0 +1,97 @@
+//! Feature flags read from `FF_*` environment variables on every check.
+//!
+//! Nothing is cached at startup, so a flag always reports what the process
+//! environment holds right now. Values parse like every other boolean
+//! setting (`true`/`1`/`yes`/`on`); an unset flag takes its default, which
+//! keeps today's behaviour.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+use crate::config::parse_flag;
+use crate::{admin, reply};
+
+/// `/ws` echo server.
+pub const ECHO: &str = "FF_ECHO_ENABLED";
+/// Jitter and error injection on `/`.
+pub const SIMULATION: &str = "FF_SIMULATION_ENABLED";
+
+/// Every flag and its default. Checking a name not listed here is a bug.
+const KNOWN: &[(&str, bool)] = &[(ECHO, true), (SIMULATION, true)];
+
+/// The flag's current value. An unknown name is reported and treated as
+/// off, and fails debug builds outright so a typo is caught in tests.
+pub fn is_enabled(flag: &str) -> bool {
+    let Some((_, default)) = KNOWN.iter().find(|(name, _)| *name == flag) else {
+        debug_assert!(false, "unknown feature flag {flag}");
+        tracing::error!(flag, "unknown feature flag");
+        return false;
+    };
+    std::env::var(flag).map_or(*default, |value| parse_flag(&value))
+}
+
+/// Like `filters::enabled`, but decided per request from `flag`.
+pub fn require(flag: &'static str) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::any()
+        .and_then(move || async move {
+            if is_enabled(flag) {
+                Ok(())
+            } else {
+                Err(warp::reject::not_found())
+            }
+        })
+        .untuple_one()
+}
+
+#[utoipa::path(
+    get,
+    operation_id = "flags",
+    path = "/flags",
+    tag = "admin",
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "Every known flag and its value right now", body = BTreeMap<String, bool>),
+        (status = 401, description = "Missing or wrong X-Admin-Token", body = ErrorResponse),
+    )
+)]
+/// `GET /flags`. Absent unless `ADMIN_TOKEN` is set.
+pub fn route(
+    admin_token: Option<Arc<str>>,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    warp::path("flags")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(admin::protected(admin_token))
+        .map(|| {
+            let flags: BTreeMap<&str, bool> = KNOWN
+                .iter()
+                .map(|(name, _)| (*name, is_enabled(name)))
+                .collect();
+            reply::json(&flags).into_response()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unset_flags_take_their_default() {
+        for (name, default) in KNOWN {
+            assert!(name.starts_with("FF_"), "{name}");
+            if std::env::var_os(name).is_none() {
+                assert_eq!(is_enabled(name), *default, "{name}");
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "unknown feature flag")]
+    fn unknown_flags_fail_in_debug_builds() {
+        is_enabled("FF_ECHO_ENABLE");
+    }
+}
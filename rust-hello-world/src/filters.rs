@@ -0,0 +1,127 @@
+use serde::de::DeserializeOwned;
+use tokio_stream::{Stream, StreamExt};
+use warp::hyper::body::{Buf, Bytes};
+use warp::reject::Reject;
+use warp::{Filter, Rejection};
+
+use crate::body_limit::PayloadTooLarge;
+
+/// Passes when `flag` is set and otherwise rejects as not found, so a
+/// disabled route is indistinguishable from one that does not exist.
+pub fn enabled(flag: bool) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::any()
+        .and_then(move || async move {
+            if flag {
+                Ok(())
+            } else {
+                Err(warp::reject::not_found())
+            }
+        })
+        .untuple_one()
+}
+
+/// A request body that could not be decoded.
+#[derive(Debug)]
+pub struct InvalidBody(pub String);
+
+impl Reject for InvalidBody {}
+
+/// Admin and chaos payloads are a handful of fields; nothing legitimate
+/// comes close to these.
+const MAX_JSON_BODY_BYTES: u64 = 64 * 1024;
+pub const DEFAULT_MAX_JSON_DEPTH: usize = 32;
+
+/// Decodes a small JSON body whatever its `Content-Type`, so plain
+/// `curl -d '{...}'` works. `max_depth` is `MAX_JSON_DEPTH`.
+pub fn json<T>(max_depth: usize) -> impl Filter<Extract = (T,), Error = Rejection> + Clone
+where
+    T: DeserializeOwned + Send,
+{
+    small_body().and_then(move |body: Bytes| async move { decode(&body, max_depth) })
+}
+
+/// Like `json`, but an empty body is `T::default()` so callers can
+/// `curl -X POST` without `-d '{}'`.
+pub fn optional_json<T>(max_depth: usize) -> impl Filter<Extract = (T,), Error = Rejection> + Clone
+where
+    T: DeserializeOwned + Default + Send,
+{
+    small_body().and_then(move |body: Bytes| async move {
+        if body.iter().all(u8::is_ascii_whitespace) {
+            return Ok(T::default());
+        }
+        decode(&body, max_depth)
+    })
+}
+
+/// The body, up to `MAX_JSON_BODY_BYTES`.
+fn small_body() -> impl Filter<Extract = (Bytes,), Error = Rejection> + Clone {
+    body(MAX_JSON_BODY_BYTES)
+}
+
+/// Reads the body up to `limit` bytes, stopping as soon as a chunked body
+/// goes over rather than buffering all of it.
+pub fn body(limit: u64) -> impl Filter<Extract = (Bytes,), Error = Rejection> + Clone {
+    warp::body::stream().and_then(move |stream| read_limited(stream, limit))
+}
+
+async fn read_limited<S, B>(stream: S, limit: u64) -> Result<Bytes, Rejection>
+where
+    S: Stream<Item = Result<B, warp::Error>>,
+    B: Buf,
+{
+    let mut stream = std::pin::pin!(stream);
+    let mut body = Vec::new();
+    while let Some(chunk) = stream.next().await {
+        let mut chunk = chunk.map_err(|err| warp::reject::custom(InvalidBody(err.to_string())))?;
+        if body.len() as u64 + chunk.remaining() as u64 > limit {
+            return Err(warp::reject::custom(PayloadTooLarge::new(limit)));
+        }
+        while chunk.has_remaining() {
+            let piece = chunk.chunk();
+            let len = piece.len();
+            body.extend_from_slice(piece);
+            chunk.advance(len);
+        }
+    }
+    Ok(Bytes::from(body))
+}
+
+/// Deserializes `body` after checking it against `max_depth`.
+pub fn decode<T: DeserializeOwned>(body: &[u8], max_depth: usize) -> Result<T, Rejection> {
+    if json_depth(body) > max_depth {
+        return Err(warp::reject::custom(InvalidBody(format!(
+            "JSON nested deeper than {} levels",
+            max_depth
+        ))));
+    }
+    serde_json::from_slice(body).map_err(|err| warp::reject::custom(InvalidBody(err.to_string())))
+}
+
+/// Deepest array/object nesting in `body`, ignoring brackets inside
+/// strings. Runs before deserializing so a hostile body never recurses.
+fn json_depth(body: &[u8]) -> usize {
+    let (mut depth, mut deepest) = (0usize, 0usize);
+    let (mut in_string, mut escaped) = (false, false);
+    for &byte in body {
+        if in_string {
+            match byte {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match byte {
+            b'"' => in_string = true,
+            b'[' | b'{' => {
+                depth += 1;
+                deepest = deepest.max(depth);
+            }
+            b']' | b'}' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    deepest
+}
@@ -0,0 +1,375 @@
+use std::convert::Infallible;
+use std::fs::{self, Metadata};
+use std::io::{self, Read};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use warp::http::StatusCode;
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+use crate::admin;
+use crate::config::Config;
+use crate::{reply, ErrorResponse};
+
+/// Read-only view of the paths under `FS_ALLOWED_PATHS`, for checking what a
+/// ConfigMap or Secret mount actually looks like from inside the pod.
+pub struct FsBrowser {
+    roots: Vec<PathBuf>,
+    max_file_bytes: u64,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct FsQuery {
+    /// Absolute path under one of `FS_ALLOWED_PATHS`.
+    path: String,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub(crate) enum Listing {
+    Directory {
+        #[schema(value_type = String)]
+        path: PathBuf,
+        entries: Vec<Entry>,
+    },
+    File {
+        #[schema(value_type = String)]
+        path: PathBuf,
+        size: u64,
+        truncated: bool,
+        #[schema(value_type = String, example = "utf-8")]
+        encoding: &'static str,
+        content: String,
+    },
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct Entry {
+    name: String,
+    #[serde(rename = "type")]
+    #[schema(value_type = String, example = "symlink")]
+    kind: &'static str,
+    size: u64,
+    mode: String,
+    modified: Option<String>,
+    symlink: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>)]
+    target: Option<PathBuf>,
+}
+
+impl FsBrowser {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            roots: config.fs_allowed_paths.iter().map(PathBuf::from).collect(),
+            max_file_bytes: config.fs_max_file_bytes,
+        }
+    }
+
+    fn inspect(&self, requested: &str) -> Response {
+        let requested = Path::new(requested);
+        let canonical = match fs::canonicalize(requested) {
+            Ok(canonical) => canonical,
+            // Only admit that a path is missing if it would have been
+            // allowed, so the endpoint cannot probe the rest of the disk.
+            Err(err) if self.lexically_allowed(requested) => {
+                return error(StatusCode::NOT_FOUND, err.to_string())
+            }
+            Err(_) => return forbidden(),
+        };
+        if !self.canonically_allowed(&canonical) {
+            return forbidden();
+        }
+
+        let result = fs::metadata(&canonical).and_then(|meta| {
+            if meta.is_dir() {
+                list_directory(&canonical)
+            } else {
+                self.read_file(&canonical, meta.len())
+            }
+        });
+        match result {
+            Ok(listing) => reply::json(&listing).into_response(),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                error(StatusCode::NOT_FOUND, err.to_string())
+            }
+            Err(err) if err.kind() == io::ErrorKind::PermissionDenied => {
+                error(StatusCode::FORBIDDEN, err.to_string())
+            }
+            Err(err) => error(StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
+        }
+    }
+
+    /// Roots are canonicalized per request because volumes may be mounted
+    /// (and their `..data` symlinks swapped) after startup.
+    fn canonically_allowed(&self, path: &Path) -> bool {
+        self.roots
+            .iter()
+            .filter_map(|root| fs::canonicalize(root).ok())
+            .any(|root| path.starts_with(root))
+    }
+
+    fn lexically_allowed(&self, path: &Path) -> bool {
+        path.is_absolute()
+            && !path.components().any(|c| matches!(c, Component::ParentDir))
+            && self.roots.iter().any(|root| path.starts_with(root))
+    }
+
+    fn read_file(&self, path: &Path, size: u64) -> io::Result<Listing> {
+        let mut content = Vec::new();
+        fs::File::open(path)?
+            .take(self.max_file_bytes)
+            .read_to_end(&mut content)?;
+        let truncated = size > content.len() as u64;
+        let (encoding, content) = match String::from_utf8(content) {
+            Ok(text) => ("utf-8", text),
+            Err(err) => ("base64", STANDARD.encode(err.into_bytes())),
+        };
+        Ok(Listing::File {
+            path: path.to_path_buf(),
+            size,
+            truncated,
+            encoding,
+            content,
+        })
+    }
+}
+
+fn list_directory(path: &Path) -> io::Result<Listing> {
+    let mut entries = Vec::new();
+    for dirent in fs::read_dir(path)? {
+        let dirent = dirent?;
+        let meta = dirent.path().symlink_metadata()?;
+        let symlink = meta.file_type().is_symlink();
+        entries.push(Entry {
+            name: dirent.file_name().to_string_lossy().into_owned(),
+            kind: kind(&meta),
+            size: meta.len(),
+            mode: format!("{:04o}", meta.permissions().mode() & 0o7777),
+            modified: meta
+                .modified()
+                .ok()
+                .map(|t| DateTime::<Utc>::from(t).to_rfc3339()),
+            symlink,
+            target: symlink.then(|| fs::read_link(dirent.path()).ok()).flatten(),
+        });
+    }
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(Listing::Directory {
+        path: path.to_path_buf(),
+        entries,
+    })
+}
+
+fn kind(meta: &Metadata) -> &'static str {
+    let file_type = meta.file_type();
+    if file_type.is_symlink() {
+        "symlink"
+    } else if file_type.is_dir() {
+        "directory"
+    } else if file_type.is_file() {
+        "file"
+    } else {
+        "other"
+    }
+}
+
+fn error(status: StatusCode, error: String) -> Response {
+    warp::reply::with_status(reply::json(&ErrorResponse { error }), status).into_response()
+}
+
+fn forbidden() -> Response {
+    error(
+        StatusCode::FORBIDDEN,
+        "path is outside FS_ALLOWED_PATHS".to_string(),
+    )
+}
+
+#[utoipa::path(
+    get,
+    operation_id = "fs",
+    path = "/fs",
+    tag = "admin",
+    params(FsQuery),
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "Directory listing or file content", body = Listing),
+        (status = 400, description = "Missing `path`", body = ErrorResponse),
+        (status = 401, description = "Missing or wrong X-Admin-Token", body = ErrorResponse),
+        (status = 403, description = "Outside FS_ALLOWED_PATHS", body = ErrorResponse),
+        (status = 404, description = "No such file or directory", body = ErrorResponse),
+    )
+)]
+/// `GET /fs?path=...`: lists a directory or returns a file's content
+/// (UTF-8 as-is, anything else base64). Absent unless `ADMIN_TOKEN` is set.
+pub fn route(
+    browser: Arc<FsBrowser>,
+    admin_token: Option<Arc<str>>,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    warp::path("fs")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(admin::protected(admin_token))
+        .and(warp::query::<FsQuery>())
+        .and_then(move |query: FsQuery| {
+            let browser = browser.clone();
+            async move {
+                let response = tokio::task::spawn_blocking(move || browser.inspect(&query.path))
+                    .await
+                    .unwrap_or_else(|err| {
+                        error(StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
+                    });
+                Ok::<_, Infallible>(response)
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::symlink;
+
+    use super::*;
+
+    /// `<tmp>/fs-<uuid>` holding `allowed/` (the only root) and `secret.txt`
+    /// beside it.
+    struct Scratch {
+        dir: PathBuf,
+        browser: FsBrowser,
+    }
+
+    impl Scratch {
+        fn new(max_file_bytes: u64) -> Self {
+            let dir = std::env::temp_dir().join(format!("fs-{}", uuid::Uuid::new_v4()));
+            fs::create_dir_all(dir.join("allowed/sub")).unwrap();
+            fs::write(dir.join("secret.txt"), "secret").unwrap();
+            let browser = FsBrowser {
+                roots: vec![dir.join("allowed")],
+                max_file_bytes,
+            };
+            Self { dir, browser }
+        }
+
+        fn path(&self, relative: &str) -> String {
+            self.dir.join(relative).to_string_lossy().into_owned()
+        }
+
+        async fn inspect(&self, relative: &str) -> (StatusCode, serde_json::Value) {
+            let response = self.browser.inspect(&self.path(relative));
+            let status = response.status();
+            let body = warp::hyper::body::to_bytes(response.into_body())
+                .await
+                .unwrap();
+            (status, serde_json::from_slice(&body).unwrap())
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    #[tokio::test]
+    async fn parent_dir_traversal_is_forbidden() {
+        let scratch = Scratch::new(1024);
+        let (status, body) = scratch.inspect("allowed/../secret.txt").await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body["error"], "path is outside FS_ALLOWED_PATHS");
+        // Missing paths behind `..` must not be told apart from forbidden.
+        let (status, _) = scratch.inspect("allowed/../missing.txt").await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn symlink_out_of_the_root_is_forbidden() {
+        let scratch = Scratch::new(1024);
+        symlink(
+            scratch.dir.join("secret.txt"),
+            scratch.dir.join("allowed/link"),
+        )
+        .unwrap();
+        let (status, _) = scratch.inspect("allowed/link").await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn paths_outside_the_roots_are_forbidden() {
+        let scratch = Scratch::new(1024);
+        assert_eq!(scratch.inspect("secret.txt").await.0, StatusCode::FORBIDDEN);
+        assert_eq!(
+            scratch.inspect("nowhere.txt").await.0,
+            StatusCode::FORBIDDEN
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_path_under_a_root_is_not_found() {
+        let scratch = Scratch::new(1024);
+        let (status, _) = scratch.inspect("allowed/missing.txt").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn lists_a_directory() {
+        let scratch = Scratch::new(1024);
+        fs::write(scratch.dir.join("allowed/b.txt"), "hello").unwrap();
+        symlink("b.txt", scratch.dir.join("allowed/a-link")).unwrap();
+
+        let (status, body) = scratch.inspect("allowed").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["type"], "directory");
+        let entries = body["entries"].as_array().unwrap();
+        let names: Vec<_> = entries
+            .iter()
+            .map(|e| e["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, ["a-link", "b.txt", "sub"]);
+        assert_eq!(entries[0]["type"], "symlink");
+        assert_eq!(entries[0]["target"], "b.txt");
+        assert_eq!(
+            (entries[1]["type"].as_str(), entries[1]["size"].as_u64()),
+            (Some("file"), Some(5))
+        );
+        assert_eq!(entries[2]["type"], "directory");
+    }
+
+    #[tokio::test]
+    async fn binary_content_is_base64() {
+        let scratch = Scratch::new(1024);
+        fs::write(scratch.dir.join("allowed/blob"), [0xff, 0x00, 0xfe]).unwrap();
+        let (status, body) = scratch.inspect("allowed/blob").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["encoding"], "base64");
+        assert_eq!(body["content"], "/wD+");
+
+        fs::write(scratch.dir.join("allowed/text"), "plain").unwrap();
+        let (_, body) = scratch.inspect("allowed/text").await;
+        assert_eq!(
+            (body["encoding"].as_str(), body["content"].as_str()),
+            (Some("utf-8"), Some("plain"))
+        );
+    }
+
+    #[tokio::test]
+    async fn content_is_capped_at_max_file_bytes() {
+        let scratch = Scratch::new(4);
+        fs::write(scratch.dir.join("allowed/long"), "0123456789").unwrap();
+        let (status, body) = scratch.inspect("allowed/long").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["content"], "0123");
+        assert_eq!(body["size"], 10);
+        assert_eq!(body["truncated"], true);
+
+        fs::write(scratch.dir.join("allowed/short"), "0123").unwrap();
+        let (_, body) = scratch.inspect("allowed/short").await;
+        assert_eq!(body["truncated"], false);
+    }
+}
@@ -0,0 +1,58 @@
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use crate::config::Config;
+
+/// The hostname reported in responses. Read once by default, which is right
+/// for pods; with `REFRESH_HOSTNAME_SECONDS` a background task re-reads it
+/// for hosts where it can change underneath us. `X-Served-By` keeps the
+/// name from startup.
+#[derive(Clone)]
+pub enum Hostname {
+    Static(Arc<str>),
+    Refreshing(Arc<RwLock<String>>),
+}
+
+impl Hostname {
+    pub fn from_config(config: &Config) -> Self {
+        match config.refresh_hostname_seconds.filter(|secs| *secs > 0) {
+            None => Hostname::Static(Arc::from(read())),
+            Some(secs) => {
+                let current = Arc::new(RwLock::new(read()));
+                spawn_refresh(current.clone(), Duration::from_secs(secs));
+                Hostname::Refreshing(current)
+            }
+        }
+    }
+
+    pub fn get(&self) -> String {
+        match self {
+            Hostname::Static(name) => name.to_string(),
+            Hostname::Refreshing(current) => {
+                current.read().unwrap_or_else(|e| e.into_inner()).clone()
+            }
+        }
+    }
+}
+
+fn read() -> String {
+    gethostname::gethostname()
+        .into_string()
+        .unwrap_or_else(|_| "unknown".to_string())
+}
+
+fn spawn_refresh(current: Arc<RwLock<String>>, interval: Duration) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            let latest = read();
+            let mut hostname = current.write().unwrap_or_else(|e| e.into_inner());
+            if *hostname != latest {
+                tracing::info!(from = %hostname, to = %latest, "hostname changed");
+                *hostname = latest;
+            }
+        }
+    });
+}
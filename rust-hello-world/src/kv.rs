@@ -0,0 +1,604 @@
+//! In-memory key/value store under `/kv`, for showing that state held in a
+//! pod is per pod: every replica has its own, and a restart empties it.
+
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::{IntoParams, ToSchema};
+use warp::http::StatusCode;
+use warp::hyper::body::Bytes;
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+use crate::config::Config;
+use crate::hostname::Hostname;
+use crate::metrics::Metrics;
+use crate::shutdown::Shutdown;
+use crate::{filters, reply, ErrorResponse};
+
+const DEFAULT_PAGE: usize = 100;
+const MAX_PAGE: usize = 1000;
+
+/// A year; long enough for any demo and far from `Instant` overflow.
+const MAX_TTL_SECONDS: u64 = 365 * 24 * 60 * 60;
+
+/// Entries capped by `KV_MAX_ENTRIES` and `KV_MAX_BYTES`, where an entry's
+/// size is its key plus its value as sent.
+///
+/// Reads share the lock; only writes and purges take it exclusively.
+/// Expired entries are dropped when a read finds them and otherwise by a
+/// sweep every `KV_SWEEP_INTERVAL_SECONDS`.
+pub struct Store {
+    inner: RwLock<Inner>,
+    max_entries: usize,
+    max_bytes: u64,
+    sweep_interval: Duration,
+    hostname: Hostname,
+    metrics: Metrics,
+}
+
+#[derive(Default)]
+struct Inner {
+    entries: BTreeMap<String, Entry>,
+    /// Sum of `Entry::size` over `entries`.
+    bytes: u64,
+}
+
+struct Entry {
+    value: Value,
+    size: u64,
+    created_at: DateTime<Utc>,
+    expires: Option<(Instant, DateTime<Utc>)>,
+    stored_by: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct KvEntry {
+    key: String,
+    #[schema(value_type = Object)]
+    value: Value,
+    size_bytes: u64,
+    created_at: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expires_at: Option<String>,
+    /// Hostname of the pod that stored the value.
+    stored_by: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct KvPage {
+    keys: Vec<String>,
+    /// Pass as `cursor` for the next page; absent on the last one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next_cursor: Option<String>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct PutQuery {
+    /// Seconds until the entry expires; it never does when absent.
+    ttl_seconds: Option<u64>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ListQuery {
+    /// Keys per page, 1 to 1000; 100 when absent.
+    limit: Option<usize>,
+    /// `next_cursor` from the previous page.
+    cursor: Option<String>,
+}
+
+/// Which cap a write would have gone over.
+#[derive(Debug, PartialEq)]
+enum Full {
+    Entries(usize),
+    Bytes(u64),
+}
+
+impl Entry {
+    fn is_expired(&self, now: Instant) -> bool {
+        self.expires.is_some_and(|(at, _)| at <= now)
+    }
+
+    fn view(&self, key: &str) -> KvEntry {
+        KvEntry {
+            key: key.to_string(),
+            value: self.value.clone(),
+            size_bytes: self.size,
+            created_at: self.created_at.to_rfc3339(),
+            expires_at: self.expires.map(|(_, at)| at.to_rfc3339()),
+            stored_by: self.stored_by.clone(),
+        }
+    }
+}
+
+impl Inner {
+    fn remove(&mut self, key: &str) -> Option<Entry> {
+        let entry = self.entries.remove(key)?;
+        self.bytes -= entry.size;
+        Some(entry)
+    }
+
+    fn purge_expired(&mut self, now: Instant) -> usize {
+        let before = self.entries.len();
+        self.entries.retain(|_, entry| !entry.is_expired(now));
+        self.bytes = self.entries.values().map(|entry| entry.size).sum();
+        before - self.entries.len()
+    }
+
+    fn publish(&self, metrics: &Metrics) {
+        metrics.set_kv_usage(self.entries.len(), self.bytes);
+    }
+}
+
+impl Store {
+    pub fn from_config(config: &Config, hostname: Hostname, metrics: Metrics) -> Arc<Self> {
+        Arc::new(Self::new(
+            config.kv_max_entries,
+            config.kv_max_bytes,
+            Duration::from_secs(config.kv_sweep_interval_seconds.max(1)),
+            hostname,
+            metrics,
+        ))
+    }
+
+    fn new(
+        max_entries: usize,
+        max_bytes: u64,
+        sweep_interval: Duration,
+        hostname: Hostname,
+        metrics: Metrics,
+    ) -> Self {
+        Self {
+            inner: RwLock::default(),
+            max_entries,
+            max_bytes,
+            sweep_interval,
+            hostname,
+            metrics,
+        }
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, Inner> {
+        self.inner.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, Inner> {
+        self.inner.write().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Starts the periodic sweep; it stops at the next shutdown signal.
+    pub fn spawn(self: &Arc<Self>, shutdown: &Shutdown) {
+        let store = self.clone();
+        let stop = shutdown.wait();
+        tokio::spawn(async move {
+            let sweep = async {
+                let mut ticker = tokio::time::interval(store.sweep_interval);
+                ticker.tick().await;
+                loop {
+                    ticker.tick().await;
+                    let mut inner = store.write();
+                    let purged = inner.purge_expired(Instant::now());
+                    if purged > 0 {
+                        inner.publish(&store.metrics);
+                        tracing::debug!(purged, "swept expired kv entries");
+                    }
+                }
+            };
+            tokio::select! {
+                _ = sweep => {}
+                _ = stop => {}
+            }
+        });
+    }
+
+    fn get(&self, key: &str) -> Option<KvEntry> {
+        let now = Instant::now();
+        match self.read().entries.get(key) {
+            None => return None,
+            Some(entry) if !entry.is_expired(now) => return Some(entry.view(key)),
+            Some(_) => {}
+        }
+        let mut inner = self.write();
+        // Checked again: the key may have been rewritten in between.
+        if inner
+            .entries
+            .get(key)
+            .is_some_and(|entry| entry.is_expired(now))
+        {
+            inner.remove(key);
+            inner.publish(&self.metrics);
+        }
+        None
+    }
+
+    /// Stores `value` under `key`, replacing what was there. `value_bytes`
+    /// is the size of the body it came from. Also says whether the key is
+    /// new, counting an expired entry as gone.
+    fn put(
+        &self,
+        key: String,
+        value: Value,
+        value_bytes: u64,
+        ttl: Option<Duration>,
+    ) -> Result<(KvEntry, bool), Full> {
+        let now = Instant::now();
+        let created_at = Utc::now();
+        let entry = Entry {
+            value,
+            size: key.len() as u64 + value_bytes,
+            created_at,
+            expires: ttl.map(|ttl| {
+                let wall = chrono::Duration::from_std(ttl).unwrap_or(chrono::Duration::MAX);
+                (now + ttl, created_at + wall)
+            }),
+            stored_by: self.hostname.get(),
+        };
+
+        let mut inner = self.write();
+        if self.room_for(&inner, &key, entry.size).is_err() {
+            inner.purge_expired(now);
+        }
+        if let Err(full) = self.room_for(&inner, &key, entry.size) {
+            inner.publish(&self.metrics);
+            return Err(full);
+        }
+        let view = entry.view(&key);
+        let created = inner.remove(&key).is_none_or(|old| old.is_expired(now));
+        inner.bytes += entry.size;
+        inner.entries.insert(key, entry);
+        inner.publish(&self.metrics);
+        Ok((view, created))
+    }
+
+    /// Whether `key` could hold `size` bytes without going over either cap.
+    fn room_for(&self, inner: &Inner, key: &str, size: u64) -> Result<(), Full> {
+        let (entries, bytes) = match inner.entries.get(key) {
+            Some(old) => (inner.entries.len(), inner.bytes - old.size + size),
+            None => (inner.entries.len() + 1, inner.bytes + size),
+        };
+        if entries > self.max_entries {
+            Err(Full::Entries(self.max_entries))
+        } else if bytes > self.max_bytes {
+            Err(Full::Bytes(self.max_bytes))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Whether a live entry was removed.
+    fn delete(&self, key: &str) -> bool {
+        let mut inner = self.write();
+        let removed = inner.remove(key);
+        inner.publish(&self.metrics);
+        removed.is_some_and(|entry| !entry.is_expired(Instant::now()))
+    }
+
+    /// Live keys after `cursor` in byte order, at most `limit` of them.
+    fn list(&self, limit: usize, cursor: Option<&str>) -> KvPage {
+        let now = Instant::now();
+        let start = cursor.map_or(Bound::Unbounded, Bound::Excluded);
+        let mut keys: Vec<String> = self
+            .read()
+            .entries
+            .range::<str, _>((start, Bound::Unbounded))
+            .filter(|(_, entry)| !entry.is_expired(now))
+            .map(|(key, _)| key.clone())
+            .take(limit + 1)
+            .collect();
+        let next_cursor = if keys.len() > limit {
+            keys.truncate(limit);
+            keys.last().cloned()
+        } else {
+            None
+        };
+        KvPage { keys, next_cursor }
+    }
+}
+
+/// A JSON body is stored as the value it encodes; anything else as a
+/// string, which must then be UTF-8.
+fn parse_value(
+    content_type: Option<&str>,
+    body: &Bytes,
+    max_json_depth: usize,
+) -> Result<Value, Rejection> {
+    let is_json = content_type.is_some_and(|value| {
+        value
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .eq_ignore_ascii_case("application/json")
+    });
+    if is_json {
+        return filters::decode(body, max_json_depth);
+    }
+    String::from_utf8(body.to_vec())
+        .map(Value::String)
+        .map_err(|_| {
+            warp::reject::custom(filters::InvalidBody(
+                "value is neither JSON nor UTF-8 text".to_string(),
+            ))
+        })
+}
+
+fn error(status: StatusCode, error: String) -> Response {
+    warp::reply::with_status(reply::json(&ErrorResponse { error }), status).into_response()
+}
+
+fn not_found() -> Response {
+    error(StatusCode::NOT_FOUND, "key not found".to_string())
+}
+
+#[utoipa::path(
+    get,
+    operation_id = "kv_list",
+    path = "/kv",
+    tag = "app",
+    params(ListQuery),
+    responses(
+        (status = 200, description = "One page of live keys, in byte order", body = KvPage),
+        (status = 400, description = "limit outside 1..=1000", body = ErrorResponse),
+    )
+)]
+/// `GET /kv?limit=&cursor=`: pages through this pod's keys.
+pub fn list_route(
+    store: Arc<Store>,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    warp::path("kv")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(warp::query::<ListQuery>())
+        .map(move |query: ListQuery| {
+            let limit = query.limit.unwrap_or(DEFAULT_PAGE);
+            if !(1..=MAX_PAGE).contains(&limit) {
+                return error(
+                    StatusCode::BAD_REQUEST,
+                    format!("limit must be between 1 and {MAX_PAGE}"),
+                );
+            }
+            reply::json(&store.list(limit, query.cursor.as_deref())).into_response()
+        })
+}
+
+#[utoipa::path(
+    get,
+    operation_id = "kv_get",
+    path = "/kv/{key}",
+    tag = "app",
+    params(("key" = String, Path, description = "Key to read")),
+    responses(
+        (status = 200, description = "The value and where and when it was stored", body = KvEntry),
+        (status = 404, description = "No such key on this pod, or it expired", body = ErrorResponse),
+    )
+)]
+/// `GET /kv/{key}`.
+pub fn get_route(
+    store: Arc<Store>,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    warp::path!("kv" / String)
+        .and(warp::get())
+        .map(move |key: String| match store.get(&key) {
+            Some(entry) => reply::json(&entry).into_response(),
+            None => not_found(),
+        })
+}
+
+#[utoipa::path(
+    put,
+    operation_id = "kv_put",
+    path = "/kv/{key}",
+    tag = "app",
+    params(("key" = String, Path, description = "Key to write"), PutQuery),
+    request_body(content = String, description = "Stored as JSON with `Content-Type: application/json`, as a string otherwise"),
+    responses(
+        (status = 200, description = "Replaced an existing value", body = KvEntry),
+        (status = 201, description = "Stored a new key", body = KvEntry),
+        (status = 400, description = "Invalid JSON, non-UTF-8 text or ttl_seconds out of range", body = ErrorResponse),
+        (status = 413, description = "Larger than KV_MAX_BYTES"),
+        (status = 507, description = "KV_MAX_ENTRIES or KV_MAX_BYTES reached", body = ErrorResponse),
+    )
+)]
+/// `PUT /kv/{key}?ttl_seconds=N`.
+pub fn put_route(
+    store: Arc<Store>,
+    max_json_depth: usize,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    warp::path!("kv" / String)
+        .and(warp::put())
+        .and(warp::query::<PutQuery>())
+        .and(warp::header::optional::<String>("content-type"))
+        .and(filters::body(store.max_bytes))
+        .and_then(
+            move |key: String, query: PutQuery, content_type: Option<String>, body: Bytes| {
+                let store = store.clone();
+                async move {
+                    let ttl = match query.ttl_seconds {
+                        Some(secs) if !(1..=MAX_TTL_SECONDS).contains(&secs) => {
+                            return Ok(error(
+                                StatusCode::BAD_REQUEST,
+                                format!("ttl_seconds must be between 1 and {MAX_TTL_SECONDS}"),
+                            ));
+                        }
+                        ttl => ttl.map(Duration::from_secs),
+                    };
+                    let value = parse_value(content_type.as_deref(), &body, max_json_depth)?;
+                    let response = match store.put(key, value, body.len() as u64, ttl) {
+                        Ok((entry, true)) => {
+                            warp::reply::with_status(reply::json(&entry), StatusCode::CREATED)
+                                .into_response()
+                        }
+                        Ok((entry, false)) => reply::json(&entry).into_response(),
+                        Err(Full::Entries(max)) => error(
+                            StatusCode::INSUFFICIENT_STORAGE,
+                            format!("store is full: at most {max} entries"),
+                        ),
+                        Err(Full::Bytes(max)) => error(
+                            StatusCode::INSUFFICIENT_STORAGE,
+                            format!("store is full: at most {max} bytes"),
+                        ),
+                    };
+                    Ok::<_, Rejection>(response)
+                }
+            },
+        )
+}
+
+#[utoipa::path(
+    delete,
+    operation_id = "kv_delete",
+    path = "/kv/{key}",
+    tag = "app",
+    params(("key" = String, Path, description = "Key to remove")),
+    responses(
+        (status = 204, description = "Removed"),
+        (status = 404, description = "No such key on this pod, or it expired", body = ErrorResponse),
+    )
+)]
+/// `DELETE /kv/{key}`.
+pub fn delete_route(
+    store: Arc<Store>,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    warp::path!("kv" / String)
+        .and(warp::delete())
+        .map(move |key: String| {
+            if store.delete(&key) {
+                StatusCode::NO_CONTENT.into_response()
+            } else {
+                not_found()
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store(max_entries: usize, max_bytes: u64) -> Store {
+        Store::new(
+            max_entries,
+            max_bytes,
+            Duration::from_secs(30),
+            Hostname::Static(Arc::from("pod-a")),
+            Metrics::new(None, Arc::default()),
+        )
+    }
+
+    fn text(value: &str) -> (Value, u64) {
+        (Value::String(value.to_string()), value.len() as u64)
+    }
+
+    #[test]
+    fn put_get_delete_round_trip() {
+        let store = store(10, 1024);
+        let (value, len) = text("world");
+        let (entry, created) = store.put("hello".to_string(), value, len, None).unwrap();
+        assert!(created);
+        assert_eq!(entry.size_bytes, 10);
+        assert_eq!(store.get("hello").unwrap().stored_by, "pod-a");
+
+        let (value, len) = text("again");
+        assert!(!store.put("hello".to_string(), value, len, None).unwrap().1);
+        assert!(store.delete("hello"));
+        assert!(store.get("hello").is_none());
+        assert!(!store.delete("hello"));
+    }
+
+    #[test]
+    fn caps_entries_and_bytes() {
+        let store = store(2, 16);
+        for key in ["a", "b"] {
+            let (value, len) = text("1");
+            store.put(key.to_string(), value, len, None).unwrap();
+        }
+        let (value, len) = text("1");
+        assert_eq!(
+            store.put("c".to_string(), value, len, None).err(),
+            Some(Full::Entries(2))
+        );
+        // Replacing an existing key needs no extra entry, only bytes.
+        let (value, len) = text("0123456789abcdef");
+        assert_eq!(
+            store.put("a".to_string(), value, len, None).err(),
+            Some(Full::Bytes(16))
+        );
+        let (value, len) = text("0123456789");
+        assert!(store.put("a".to_string(), value, len, None).is_ok());
+    }
+
+    #[test]
+    fn expired_entries_are_gone_and_free_their_room() {
+        let store = store(1, 1024);
+        let (value, len) = text("soon gone");
+        store
+            .put("a".to_string(), value, len, Some(Duration::ZERO))
+            .unwrap();
+        assert!(store.list(10, None).keys.is_empty());
+        assert!(store.get("a").is_none());
+        assert!(store.read().entries.is_empty());
+
+        let (value, len) = text("x");
+        store
+            .put("b".to_string(), value, len, Some(Duration::ZERO))
+            .unwrap();
+        let (value, len) = text("y");
+        assert!(store.put("c".to_string(), value, len, None).unwrap().1);
+    }
+
+    #[test]
+    fn lists_in_pages() {
+        let store = store(10, 1024);
+        for key in ["c", "a", "b"] {
+            let (value, len) = text(key);
+            store.put(key.to_string(), value, len, None).unwrap();
+        }
+        let first = store.list(2, None);
+        assert_eq!(first.keys, ["a", "b"]);
+        let second = store.list(2, first.next_cursor.as_deref());
+        assert_eq!(second.keys, ["c"]);
+        assert!(second.next_cursor.is_none());
+    }
+
+    #[tokio::test]
+    async fn put_stores_json_and_text_bodies() {
+        let store = Arc::new(store(10, 1024));
+        let filter = put_route(store.clone(), filters::DEFAULT_MAX_JSON_DEPTH)
+            .recover(crate::handle_rejection);
+
+        let response = warp::test::request()
+            .method("PUT")
+            .path("/kv/json?ttl_seconds=60")
+            .header("content-type", "application/json")
+            .body(r#"{"n": 1}"#)
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let entry = store.get("json").unwrap();
+        assert_eq!(entry.value, serde_json::json!({"n": 1}));
+        assert!(entry.expires_at.is_some());
+
+        warp::test::request()
+            .method("PUT")
+            .path("/kv/text")
+            .body("plain")
+            .reply(&filter)
+            .await;
+        assert_eq!(store.get("text").unwrap().value, "plain");
+
+        let response = warp::test::request()
+            .method("PUT")
+            .path("/kv/bad")
+            .header("content-type", "application/json")
+            .body("{")
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+}
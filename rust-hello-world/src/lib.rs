@@ -0,0 +1,545 @@
+//! The service as a library: `State` holds what outlives a request and
+//! `routes` composes the whole route tree from it, so tests can drive it
+//! with `warp::test` without binding a port. The binary only loads the
+//! config, starts background tasks and serves.
+
+mod access_log;
+mod admin;
+mod body_limit;
+mod cache;
+mod cache_control;
+mod chain;
+mod chaos;
+mod conditional;
+mod client_ip;
+pub mod config;
+mod cors;
+mod dependencies;
+mod downward;
+mod drain;
+mod environment;
+mod feature_flags;
+mod filters;
+mod fs;
+mod hostname;
+mod kv;
+mod lifecycle;
+pub mod logging;
+mod maintenance;
+mod metrics;
+mod metrics_push;
+mod mirror;
+mod openapi;
+mod podinfo;
+mod probes;
+mod profiling;
+mod readiness;
+mod redact;
+pub mod reload;
+mod reply;
+mod resources;
+mod response_headers;
+mod selfping;
+pub mod server;
+mod shutdown;
+pub mod state;
+mod tasks;
+mod timezone;
+pub mod tls;
+mod trailing_slash;
+mod transfer;
+mod upload;
+mod variant;
+mod version;
+mod ws;
+
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::Duration;
+use chrono_tz::Tz;
+use percent_encoding::percent_decode_str;
+use warp::http::header::LOCATION;
+use warp::http::StatusCode;
+use warp::filters::BoxedFilter;
+use warp::{Filter, Rejection, Reply};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use body_limit::BodyLimit;
+use cache::TimedCache;
+use cache_control::CacheControl;
+use client_ip::{ClientInfo, ProxyTrust};
+use config::Config;
+use cors::CorsPolicy;
+use downward::DownwardFile;
+use response_headers::PodHeaders;
+pub use state::State;
+use trailing_slash::TrailingSlash;
+use version::Version;
+
+#[derive(Serialize, ToSchema)]
+struct Response {
+    message: String,
+    hostname: String,
+    /// Same as `timestamp_utc`, kept for older clients.
+    timestamp: String,
+    timestamp_utc: String,
+    /// `timestamp_utc` in `timezone`.
+    timestamp_local: String,
+    /// `?tz=`, else `TIMEZONE`, else `UTC`.
+    timezone: String,
+    /// Requests `/` and `/hello/{name}` have answered on this pod, this one
+    /// included.
+    request_count: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    variant: Option<String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    labels: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct ErrorResponse {
+    error: String,
+}
+
+async fn handle_rejection(err: Rejection) -> Result<warp::reply::Response, Infallible> {
+    if let Some(trailing_slash::Redirect(location)) = err.find() {
+        return Ok(warp::reply::with_header(
+            StatusCode::PERMANENT_REDIRECT,
+            LOCATION,
+            location.as_str(),
+        )
+        .into_response());
+    }
+    if let Some(unknown) = err.find::<timezone::UnknownTimezone>() {
+        return Ok(
+            warp::reply::with_status(reply::json(unknown), StatusCode::BAD_REQUEST)
+                .into_response(),
+        );
+    }
+    if let Some(too_large) = err.find::<body_limit::PayloadTooLarge>() {
+        return Ok(warp::reply::with_status(
+            reply::json(too_large),
+            StatusCode::PAYLOAD_TOO_LARGE,
+        )
+        .into_response());
+    }
+
+    let (status, error) = if err.is_not_found() {
+        (StatusCode::NOT_FOUND, "not found".to_string())
+    } else if err.find::<maintenance::UnderMaintenance>().is_some() {
+        (StatusCode::SERVICE_UNAVAILABLE, "service is under maintenance".to_string())
+    } else if let Some(upload::UnsupportedType(content_type)) = err.find() {
+        (
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            format!("content type {} is not allowed", content_type),
+        )
+    } else if err.find::<warp::reject::LengthRequired>().is_some() {
+        (StatusCode::LENGTH_REQUIRED, "content-length required".to_string())
+    } else if err.find::<admin::Unauthorized>().is_some() {
+        (StatusCode::UNAUTHORIZED, "invalid or missing admin token".to_string())
+    } else if let Some(invalid) = err.find::<warp::reject::InvalidQuery>() {
+        (StatusCode::BAD_REQUEST, invalid.to_string())
+    } else if let Some(filters::InvalidBody(reason)) = err.find() {
+        (StatusCode::BAD_REQUEST, format!("invalid request body: {}", reason))
+    } else if err.find::<drain::Draining>().is_some() {
+        (StatusCode::SERVICE_UNAVAILABLE, "draining".to_string())
+    } else if err.find::<warp::reject::MethodNotAllowed>().is_some() {
+        (StatusCode::METHOD_NOT_ALLOWED, "method not allowed".to_string())
+    } else {
+        tracing::warn!(rejection = ?err, "unhandled rejection");
+        (StatusCode::INTERNAL_SERVER_ERROR, "internal server error".to_string())
+    };
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&ErrorResponse { error }),
+        status,
+    )
+    .into_response())
+}
+
+/// Layers every listener shares.
+#[derive(Clone)]
+struct Layers {
+    cors: Option<Arc<CorsPolicy>>,
+    pod_headers: Arc<PodHeaders>,
+    proxy_trust: Arc<ProxyTrust>,
+    body_limit: Arc<BodyLimit>,
+    trailing_slash: TrailingSlash,
+    sampling: Arc<access_log::Sampling>,
+}
+
+/// Applies the shared layers: `TRAILING_SLASH`, the body size limit,
+/// rejection recovery, CORS, pod identity headers and the sampled access
+/// log.
+fn finish(
+    routes: BoxedFilter<(warp::reply::Response,)>,
+    layers: Layers,
+) -> impl Filter<Extract = (warp::reply::Response,), Error = Infallible> + Clone {
+    let Layers {
+        cors,
+        pod_headers,
+        proxy_trust,
+        body_limit,
+        trailing_slash,
+        sampling,
+    } = layers;
+    let routes = trailing_slash::check(trailing_slash)
+        .and(body_limit::check(body_limit))
+        .and(routes)
+        .recover(handle_rejection);
+    let routes = cors::wrap(routes, cors);
+    let routes = response_headers::wrap(routes, pod_headers);
+    access_log::wrap(routes, proxy_trust, sampling)
+}
+
+/// The composed route trees, each with every shared layer applied.
+pub struct Routes<F> {
+    /// Served on `PORT`.
+    pub app: F,
+    /// Served on `ADMIN_PORT`. `None` when it is unset and the admin routes
+    /// are part of `app`.
+    pub admin: Option<F>,
+}
+
+/// Builds every route from `config` and `state`.
+pub fn routes(
+    config: &Config,
+    state: &State,
+) -> Routes<impl Filter<Extract = (warp::reply::Response,), Error = Infallible> + Clone> {
+    let State {
+        hostname,
+        live,
+        lifecycle,
+        shutdown,
+        maintenance,
+        drain,
+        upstream,
+        hello_requests,
+        metrics,
+        max_json_depth,
+        kv,
+        resources,
+        sampling,
+        self_ping,
+        mirror,
+        metrics_push: _,
+    } = state.clone();
+    let proxy_trust = Arc::new(ProxyTrust::parse(config.trust_proxy.as_deref()));
+    let pod_headers = Arc::new(PodHeaders::new(&hostname.get(), config));
+    let admin_token: Option<Arc<str>> = config.admin_token.as_deref().map(Arc::from);
+
+    let labels_file = config.labels_file.as_deref().map(DownwardFile::new).map(Arc::new);
+    let annotations_file = config
+        .annotations_file
+        .as_deref()
+        .map(DownwardFile::new)
+        .map(Arc::new);
+
+    let cache_control = CacheControl::from_config(config);
+    let zone = timezone::from_config(config);
+
+    let hello = {
+        let live = live.clone();
+        let hostname = hostname.clone();
+        let labels_file = labels_file.clone();
+        let label_keys = config.response_label_allowlist.clone();
+        let jitter = chaos::Jitter::from_config(config);
+        let errors = chaos::ErrorInjection::from_config(config);
+        let variant = config.variant.clone();
+        let metrics = metrics.clone();
+        // Everything in the body but the timestamps and request count
+        // changes rarely, so a body younger than HELLO_CACHE_TTL_MS is sent
+        // again without re-serializing, the timestamps saying when it was
+        // built. The count is spliced into the cached bytes on every reply,
+        // so each caller sees its own number and the body agrees with
+        // `hello_requests_total`. Only bodies in the default zone are
+        // cached; `?tz=` always builds a fresh one.
+        let cache = Some(config.hello_cache_ttl_ms)
+            .filter(|ms| *ms > 0)
+            .map(|ms| Arc::new(TimedCache::new(Duration::from_millis(ms))));
+        warp::path::end()
+            .and(maintenance::check(maintenance.clone()))
+            .and(chaos::jitter(jitter))
+            .and(chaos::errors(errors))
+            .and(timezone::query())
+            .and(metrics::count_request(hello_requests.clone()))
+            .map(move |tz: Option<Tz>, request_count: u64| {
+                let cache = cache.as_ref().filter(|_| tz.is_none());
+                let cached = cache.and_then(|cache| {
+                    let cached = cache.get();
+                    metrics.record_hello_cache(cached.is_some());
+                    cached
+                });
+                let template = cached.or_else(|| {
+                    let labels = match &labels_file {
+                        Some(file) if !label_keys.is_empty() => {
+                            let mut labels = file.read();
+                            labels.retain(|key, _| label_keys.contains(key));
+                            labels
+                        }
+                        _ => BTreeMap::new(),
+                    };
+                    let zone = tz.unwrap_or(zone);
+                    let now = chrono::Utc::now();
+                    let response = Response {
+                        message: live.read().unwrap_or_else(|e| e.into_inner()).greeting.clone(),
+                        hostname: hostname.get(),
+                        timestamp: now.to_rfc3339(),
+                        timestamp_utc: now.to_rfc3339(),
+                        timestamp_local: timezone::local(now, zone),
+                        timezone: zone.name().to_string(),
+                        request_count: 0,
+                        variant: variant.clone(),
+                        labels,
+                    };
+                    let template = reply::json(&response)
+                        .bytes()
+                        .and_then(|body| reply::Template::new(body, "request_count"));
+                    if let (Some(cache), Some(template)) = (cache, &template) {
+                        cache.put(template.clone());
+                    }
+                    template
+                });
+                match template {
+                    Some(template) => template.render(request_count).into_response(),
+                    None => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+                }
+            })
+            .recover(chaos::recover_injected)
+            .unify()
+            .map(cache_control::set(cache_control.root.clone()))
+    };
+
+    let hello_name = {
+        let hostname = hostname.clone();
+        let variant = config.variant.clone();
+        warp::path!("hello" / String)
+            .and(warp::get())
+            .and(maintenance::check(maintenance.clone()))
+            .and(timezone::query())
+            .and(metrics::count_request(hello_requests))
+            .map(move |name: String, tz: Option<Tz>, request_count: u64| {
+                let name = percent_decode_str(&name).decode_utf8_lossy();
+                let zone = tz.unwrap_or(zone);
+                let now = chrono::Utc::now();
+                reply::json(&Response {
+                    message: format!("Hello, {}!", name),
+                    hostname: hostname.get(),
+                    timestamp: now.to_rfc3339(),
+                    timestamp_utc: now.to_rfc3339(),
+                    timestamp_local: timezone::local(now, zone),
+                    timezone: zone.name().to_string(),
+                    request_count,
+                    variant: variant.clone(),
+                    labels: BTreeMap::new(),
+                })
+                .into_response()
+            })
+            .map(cache_control::set(cache_control.root.clone()))
+    };
+
+    let health = warp::path("health")
+        .map(|| warp::reply::with_status("OK", warp::http::StatusCode::OK));
+
+    let version = {
+        let version = Arc::new(Version::new());
+        warp::path("version")
+            .and(warp::path::end())
+            .and(warp::get())
+            .and(conditional::conditions())
+            .map(move |conditions| version.reply(conditions))
+            .map(cache_control::set(cache_control.version.clone()))
+    };
+
+    let dependencies = Arc::new(dependencies::Dependencies::from_config(config));
+    let uploads = Arc::new(upload::Uploads::from_config(config));
+    let transfers = Arc::new(transfer::Transfers::from_config(config));
+    let chain = Arc::new(chain::Chain::from_config(config, hostname.clone()));
+    let body_limit = BodyLimit::from_config(config)
+        .with_override("/upload", uploads.max_bytes.max(transfers.max_bytes));
+
+    let whoami = warp::path("whoami")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(client_ip::client_info(proxy_trust.clone()))
+        .map(|info: ClientInfo| reply::json(&info));
+
+    let labels = {
+        let tagged = Arc::new(conditional::Tagged::default());
+        warp::path("labels")
+            .and(warp::path::end())
+            .and(warp::get())
+            .and(filters::enabled(labels_file.is_some()))
+            .and(conditional::conditions())
+            .map(move |conditions| {
+                let labels = labels_file.as_ref().map(|f| f.read()).unwrap_or_default();
+                tagged.reply(reply::json(&labels), &conditions)
+            })
+            .map(cache_control::set(cache_control.data.clone()))
+    };
+
+    let podinfo = podinfo::route(config, live.clone(), hostname.clone())
+        .map(cache_control::set(cache_control.data.clone()));
+
+    let static_files = warp::path("static")
+        .and(warp::get())
+        .and(filters::enabled(config.static_dir.is_some()))
+        .and(warp::fs::dir(config.static_dir.clone().unwrap_or_default()))
+        .map(Reply::into_response)
+        .map(cache_control::set(cache_control.static_files.clone()));
+
+    let annotations = warp::path("annotations")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(filters::enabled(annotations_file.is_some()))
+        .map(move || {
+            reply::json(&annotations_file.as_ref().map(|f| f.read()).unwrap_or_default())
+        });
+
+    let debug_config = {
+        let live = live.clone();
+        warp::path!("debug" / "config")
+            .and(warp::get())
+            .and(filters::enabled(config.enable_debug_endpoints))
+            .and(admin::require_token(admin_token.clone()))
+            .map(move || {
+                let current = live.read().unwrap_or_else(|e| e.into_inner());
+                reply::json(&current.redacted(config::DEBUG_REDACTED))
+            })
+    };
+
+    // Absent unless ADMIN_TOKEN is set: URLs such as MIRROR_URL and
+    // METRICS_PUSH_URL can carry credentials the field-name redaction misses.
+    let config_route = {
+        let live = live.clone();
+        warp::path("config")
+            .and(warp::path::end())
+            .and(warp::get())
+            .and(admin::protected(admin_token.clone()))
+            .map(move || {
+                let current = live.read().unwrap_or_else(|e| e.into_inner());
+                reply::json(&current.redacted(config::REDACTED))
+            })
+    };
+
+    // Everything a load balancer would send traffic to. While drained these
+    // all answer 503; the probes, docs and admin routes never check.
+    let traffic_routes = metrics.instrument("hello", hello)
+        .or(metrics.instrument("hello_name", hello_name))
+        .or(metrics.instrument("version", version))
+        .or(metrics.instrument("whoami", whoami))
+        .or(metrics.instrument("labels", labels))
+        .or(metrics.instrument("podinfo", podinfo))
+        .or(metrics.instrument("annotations", annotations))
+        .or(metrics.instrument("static", static_files))
+        .or(metrics.instrument(
+            "color",
+            variant::route(config.variant.as_deref().map(Arc::from), hostname.clone()),
+        ))
+        .or(metrics.instrument("chain", chain::route(chain)))
+        .or(metrics.instrument("kv_list", kv::list_route(kv.clone())))
+        .or(metrics.instrument("kv", kv::get_route(kv.clone())))
+        .or(metrics.instrument("kv", kv::put_route(kv.clone(), max_json_depth)))
+        .or(metrics.instrument("kv", kv::delete_route(kv)))
+        .or(metrics.instrument("ws", ws::route(hostname, metrics.clone())))
+        .or(metrics.instrument("upload", upload::route(uploads)))
+        .or(metrics.instrument("upload", transfer::upload_route(transfers.clone())))
+        .or(metrics.instrument("download", transfer::download_route(transfers)));
+    let app_routes = drain::check(drain.clone())
+        .and(traffic_routes)
+        .or(metrics.instrument("health", health))
+        .or(metrics.instrument("startupz", probes::startupz(lifecycle.clone())))
+        .or(metrics.instrument("readyz", probes::readyz(lifecycle.clone(), upstream)))
+        .or(metrics.instrument("healthz", probes::healthz(lifecycle.clone())))
+        .or(metrics.instrument("healthz", probes::healthz_live(lifecycle.clone())))
+        .or(metrics.instrument("dependencies", dependencies::route(dependencies)))
+        .or(metrics.instrument("selfping", selfping::route(self_ping.clone())))
+        .or(metrics.instrument("stats", resources::route(resources)))
+        .or(metrics.instrument("openapi", openapi::spec()))
+        .or(metrics.instrument("docs", openapi::docs()))
+        .map(Reply::into_response)
+        .boxed();
+    let admin_routes = metrics::route(metrics.clone())
+        .or(metrics.instrument(
+            "metrics_reset",
+            metrics::reset_route(metrics.clone(), admin_token.clone()),
+        ))
+        .or(metrics.instrument("config", config_route))
+        .or(metrics.instrument(
+            "config_logging",
+            access_log::update_route(sampling.clone(), live, max_json_depth, admin_token.clone()),
+        ))
+        .or(metrics.instrument("mirror_stats", mirror::stats_route(mirror.clone())))
+        .or(metrics.instrument(
+            "mirror",
+            mirror::update_route(mirror.clone(), max_json_depth, admin_token.clone()),
+        ))
+        .or(metrics.instrument("debug_config", debug_config))
+        .or(metrics.instrument(
+            "debug_tasks",
+            tasks::route(config.enable_debug_endpoints, admin_token.clone()),
+        ))
+        .or(metrics.instrument(
+            "debug_pprof_profile",
+            profiling::route(config.enable_profiling, admin_token.clone()),
+        ))
+        .or(metrics.instrument(
+            "shutdown",
+            shutdown::route(shutdown.clone(), admin_token.clone()),
+        ))
+        .or(metrics.instrument("flags", feature_flags::route(admin_token.clone())))
+        .or(metrics.instrument(
+            "env",
+            environment::route(
+                Arc::new(redact::RedactPatterns::new(&config.env_redact_patterns)),
+                admin_token.clone(),
+            ),
+        ))
+        .or(metrics.instrument(
+            "fs",
+            fs::route(Arc::new(fs::FsBrowser::from_config(config)), admin_token.clone()),
+        ))
+        .or(metrics.instrument("drain", drain::route(drain.clone(), admin_token.clone())))
+        .or(metrics.instrument(
+            "undrain",
+            drain::undrain_route(drain, admin_token.clone()),
+        ))
+        .or(metrics.instrument(
+            "admin_maintenance",
+            maintenance::route(maintenance.clone(), max_json_depth, admin_token.clone()),
+        ))
+        .or(metrics.instrument("admin_crash", chaos::crash(max_json_depth, admin_token.clone())))
+        .or(metrics.instrument("admin_panic", chaos::panic(admin_token.clone())))
+        .or(metrics.instrument(
+            "admin_unhealthy",
+            chaos::unhealthy(lifecycle.clone(), max_json_depth, admin_token.clone()),
+        ))
+        .or(metrics.instrument(
+            "admin_healthy",
+            chaos::healthy(lifecycle.clone(), admin_token.clone()),
+        ))
+        .map(Reply::into_response)
+        .boxed();
+
+    let layers = Layers {
+        cors: CorsPolicy::from_config(config).map(Arc::new),
+        pod_headers,
+        proxy_trust,
+        body_limit: Arc::new(body_limit),
+        trailing_slash: TrailingSlash::from_config(config),
+        sampling,
+    };
+
+    match config.admin_port {
+        Some(_) => Routes {
+            app: finish(app_routes, layers.clone()),
+            admin: Some(finish(admin_routes, layers)),
+        },
+        None => Routes {
+            app: finish(app_routes.or(admin_routes).unify().boxed(), layers),
+            admin: None,
+        },
+    }
+}
@@ -0,0 +1,243 @@
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// Where the process is in its life, as seen by the kubelet probes.
+///
+/// `Starting` lasts for the warmup window, `Ready` until shutdown begins,
+/// and `Draining` is terminal.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Phase {
+    Starting,
+    Ready,
+    Draining,
+}
+
+impl Phase {
+    fn from_u8(value: u8) -> Self {
+        match value {
+            0 => Phase::Starting,
+            1 => Phase::Ready,
+            _ => Phase::Draining,
+        }
+    }
+}
+
+/// Manual liveness failure set through `/admin/unhealthy`, independent of
+/// the phase so it can be cleared again.
+#[derive(Clone, Copy, Debug)]
+enum LivenessOverride {
+    None,
+    Until(Instant),
+    Indefinite,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Progress {
+    pub phase: Phase,
+    pub percent_complete: f64,
+    pub remaining_seconds: f64,
+}
+
+/// Single source of truth for `/startupz`, `/readyz` and `/healthz`, shared
+/// with the shutdown path.
+pub struct Lifecycle {
+    phase: AtomicU8,
+    started_at: Instant,
+    warmup: Duration,
+    liveness: Mutex<LivenessOverride>,
+    /// Set by the resource sampler while memory stays over
+    /// `HEALTH_MEM_THRESHOLD_PERCENT`.
+    memory_pressure: AtomicBool,
+}
+
+impl Lifecycle {
+    pub fn new(warmup: Duration) -> Arc<Self> {
+        let phase = if warmup.is_zero() {
+            Phase::Ready
+        } else {
+            Phase::Starting
+        };
+        Arc::new(Self {
+            phase: AtomicU8::new(phase as u8),
+            started_at: Instant::now(),
+            warmup,
+            liveness: Mutex::new(LivenessOverride::None),
+            memory_pressure: AtomicBool::new(false),
+        })
+    }
+
+    pub fn phase(&self) -> Phase {
+        Phase::from_u8(self.phase.load(Ordering::Acquire))
+    }
+
+    /// Starting -> Ready. Returns false if warmup already ended or the
+    /// process is draining, which must never be undone.
+    pub fn mark_ready(&self) -> bool {
+        self.phase
+            .compare_exchange(
+                Phase::Starting as u8,
+                Phase::Ready as u8,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            )
+            .is_ok()
+    }
+
+    /// Any phase -> Draining.
+    pub fn begin_draining(&self) {
+        self.phase.store(Phase::Draining as u8, Ordering::Release);
+    }
+
+    pub fn is_started(&self) -> bool {
+        self.phase() != Phase::Starting
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.phase() == Phase::Ready
+    }
+
+    /// Fails `/healthz` for `duration`, or until `clear_unhealthy` when
+    /// `None`.
+    pub fn force_unhealthy(&self, duration: Option<Duration>) {
+        let value = match duration {
+            Some(duration) => LivenessOverride::Until(Instant::now() + duration),
+            None => LivenessOverride::Indefinite,
+        };
+        *self.liveness.lock().unwrap_or_else(|e| e.into_inner()) = value;
+    }
+
+    pub fn clear_unhealthy(&self) {
+        *self.liveness.lock().unwrap_or_else(|e| e.into_inner()) = LivenessOverride::None;
+    }
+
+    pub fn set_memory_pressure(&self, under_pressure: bool) {
+        self.memory_pressure.store(under_pressure, Ordering::Release);
+    }
+
+    pub fn is_live(&self) -> bool {
+        if self.memory_pressure.load(Ordering::Acquire) {
+            return false;
+        }
+        match *self.liveness.lock().unwrap_or_else(|e| e.into_inner()) {
+            LivenessOverride::None => true,
+            LivenessOverride::Until(until) => Instant::now() >= until,
+            LivenessOverride::Indefinite => false,
+        }
+    }
+
+    pub fn progress(&self) -> Progress {
+        let phase = self.phase();
+        if phase != Phase::Starting || self.warmup.is_zero() {
+            return Progress {
+                phase,
+                percent_complete: 100.0,
+                remaining_seconds: 0.0,
+            };
+        }
+        let elapsed = self.started_at.elapsed().min(self.warmup);
+        Progress {
+            phase,
+            percent_complete: elapsed.as_secs_f64() / self.warmup.as_secs_f64() * 100.0,
+            remaining_seconds: (self.warmup - elapsed).as_secs_f64(),
+        }
+    }
+
+    /// Runs the warmup window and then flips to `Ready`. Work that should
+    /// happen before taking traffic (connection pools, caches) belongs here.
+    pub fn spawn_warmup(self: &Arc<Self>) {
+        if self.warmup.is_zero() {
+            return;
+        }
+        let lifecycle = self.clone();
+        tokio::spawn(async move {
+            tracing::info!(seconds = lifecycle.warmup.as_secs_f64(), "warming up");
+            tokio::time::sleep(lifecycle.warmup).await;
+            if lifecycle.mark_ready() {
+                tracing::info!("warmup complete, ready for traffic");
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zero_warmup_starts_ready() {
+        let lifecycle = Lifecycle::new(Duration::ZERO);
+        assert_eq!(lifecycle.phase(), Phase::Ready);
+        assert!(lifecycle.is_started());
+        assert!(lifecycle.is_ready());
+        assert_eq!(lifecycle.progress().percent_complete, 100.0);
+    }
+
+    #[test]
+    fn warmup_starts_in_starting() {
+        let lifecycle = Lifecycle::new(Duration::from_secs(60));
+        assert_eq!(lifecycle.phase(), Phase::Starting);
+        assert!(!lifecycle.is_started());
+        assert!(!lifecycle.is_ready());
+
+        let progress = lifecycle.progress();
+        assert!(progress.percent_complete < 100.0);
+        assert!(progress.remaining_seconds > 0.0);
+    }
+
+    #[test]
+    fn starting_to_ready() {
+        let lifecycle = Lifecycle::new(Duration::from_secs(60));
+        assert!(lifecycle.mark_ready());
+        assert_eq!(lifecycle.phase(), Phase::Ready);
+        assert!(!lifecycle.mark_ready(), "second transition is a no-op");
+    }
+
+    #[test]
+    fn ready_to_draining() {
+        let lifecycle = Lifecycle::new(Duration::ZERO);
+        lifecycle.begin_draining();
+        assert_eq!(lifecycle.phase(), Phase::Draining);
+        assert!(lifecycle.is_started());
+        assert!(!lifecycle.is_ready());
+    }
+
+    #[test]
+    fn starting_to_draining_is_not_undone_by_warmup() {
+        let lifecycle = Lifecycle::new(Duration::from_secs(60));
+        lifecycle.begin_draining();
+        assert!(!lifecycle.mark_ready());
+        assert_eq!(lifecycle.phase(), Phase::Draining);
+    }
+
+    #[test]
+    fn forced_unhealthy_expires_or_clears() {
+        let lifecycle = Lifecycle::new(Duration::ZERO);
+        assert!(lifecycle.is_live());
+
+        lifecycle.force_unhealthy(Some(Duration::ZERO));
+        assert!(lifecycle.is_live(), "zero duration has already expired");
+
+        lifecycle.force_unhealthy(None);
+        assert!(!lifecycle.is_live());
+        assert!(lifecycle.is_ready(), "readiness is not affected");
+
+        lifecycle.clear_unhealthy();
+        assert!(lifecycle.is_live());
+    }
+
+    #[test]
+    fn memory_pressure_fails_liveness_independently() {
+        let lifecycle = Lifecycle::new(Duration::ZERO);
+        lifecycle.set_memory_pressure(true);
+        assert!(!lifecycle.is_live());
+        lifecycle.clear_unhealthy();
+        assert!(!lifecycle.is_live(), "only the sampler clears pressure");
+        lifecycle.set_memory_pressure(false);
+        assert!(lifecycle.is_live());
+    }
+}
@@ -0,0 +1,408 @@
+use std::fmt::{self, Write as _};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use chrono::{SecondsFormat, Utc};
+use tracing::field::{Field, Visit};
+use tracing::{Event, Subscriber};
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_subscriber::filter::{filter_fn, EnvFilter};
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields, MakeWriter};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{reload, Layer, Registry};
+
+use crate::config::{env_parse, env_string};
+
+pub const DEFAULT_LEVEL: &str = "info";
+const DEFAULT_MAX_MB: u64 = 100;
+const DEFAULT_KEEP: usize = 5;
+
+/// Installs the global subscriber: everything at `LOG_LEVEL` to stdout in
+/// `LOG_FORMAT`, and with `ACCESS_LOG_PATH` set, the `access` lines as JSON
+/// to that file too.
+///
+/// Runs before `Config` exists so that config parsing can already log, which
+/// is why it reads its own variables. Hold the returned guard until
+/// shutdown; dropping it flushes lines still queued for the file.
+pub fn init() -> Option<WorkerGuard> {
+    let (filter, handle) = reload::Layer::new(
+        EnvFilter::try_from_env("LOG_LEVEL").unwrap_or_else(|_| EnvFilter::new(DEFAULT_LEVEL)),
+    );
+    let _ = LEVEL.set(handle);
+    let format = match env_string("LOG_FORMAT").map(|value| value.parse::<LogFormat>()) {
+        Some(Ok(format)) => format,
+        Some(Err(err)) => {
+            eprintln!("{}, using json", err);
+            LogFormat::Json
+        }
+        None => LogFormat::Json,
+    };
+    let stdout = format.layer(io::stdout).with_filter(filter);
+
+    let (file, guard) = match env_string("ACCESS_LOG_PATH") {
+        Some(path) => {
+            let max_bytes = env_parse("ACCESS_LOG_MAX_MB").unwrap_or(DEFAULT_MAX_MB) * 1024 * 1024;
+            let keep = env_parse("ACCESS_LOG_KEEP").unwrap_or(DEFAULT_KEEP);
+            match RotatingFile::open(PathBuf::from(&path), max_bytes, keep) {
+                Ok(writer) => {
+                    let (writer, guard) = tracing_appender::non_blocking(writer);
+                    let layer = tracing_subscriber::fmt::layer()
+                        .json()
+                        .with_current_span(false)
+                        .with_span_list(false)
+                        .with_writer(writer)
+                        .with_filter(filter_fn(|meta| meta.target() == "access"));
+                    (Some(layer), Some(guard))
+                }
+                Err(err) => {
+                    eprintln!("cannot open ACCESS_LOG_PATH {}: {}", path, err);
+                    (None, None)
+                }
+            }
+        }
+        None => (None, None),
+    };
+
+    tracing_subscriber::registry()
+        .with(stdout)
+        .with(file)
+        .init();
+    guard
+}
+
+/// How stdout lines are written. Every format carries the same fields, so
+/// the access lines have `method`, `path`, `status`, `latency_ms` and
+/// `request_id` whichever collector reads them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LogFormat {
+    Json,
+    Logfmt,
+    /// The human-readable `tracing` format, for local runs.
+    Pretty,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "json" => Ok(Self::Json),
+            "logfmt" => Ok(Self::Logfmt),
+            "pretty" => Ok(Self::Pretty),
+            other => Err(format!(
+                "invalid LOG_FORMAT {:?} (expected json, logfmt or pretty)",
+                other
+            )),
+        }
+    }
+}
+
+impl LogFormat {
+    fn layer<S, W>(self, writer: W) -> Box<dyn Layer<S> + Send + Sync>
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+        W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+    {
+        let layer = tracing_subscriber::fmt::layer().with_writer(writer);
+        match self {
+            Self::Json => layer
+                .json()
+                .with_current_span(false)
+                .with_span_list(false)
+                .boxed(),
+            Self::Logfmt => layer.event_format(Logfmt).boxed(),
+            Self::Pretty => layer.boxed(),
+        }
+    }
+}
+
+/// `ts=... level=info target=access msg=... key=value ...`, one event per
+/// line. Span fields are left out, as they are in the JSON lines.
+struct Logfmt;
+
+impl<S, N> FormatEvent<S, N> for Logfmt
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        _ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let meta = event.metadata();
+        let mut line = format!(
+            "ts={} level={} target={}",
+            Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true),
+            meta.level().as_str().to_ascii_lowercase(),
+            logfmt_value(meta.target())
+        );
+        event.record(&mut LogfmtFields(&mut line));
+        writeln!(writer, "{}", line)
+    }
+}
+
+struct LogfmtFields<'a>(&'a mut String);
+
+impl Visit for LogfmtFields<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        let name = match field.name() {
+            "message" => "msg",
+            name => name,
+        };
+        let _ = write!(self.0, " {}={}", name, logfmt_value(value));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.record_str(field, &format!("{:?}", value));
+    }
+}
+
+/// Quotes `value` when it is empty or has spaces, `=`, quotes or control
+/// characters in it.
+fn logfmt_value(value: &str) -> String {
+    let plain = !value.is_empty()
+        && !value
+            .chars()
+            .any(|c| c == ' ' || c == '=' || c == '"' || c == '\\' || c.is_control());
+    if plain {
+        return value.to_string();
+    }
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Swaps the stdout filter installed by `init`, for config reloads.
+static LEVEL: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+/// Replaces the stdout filter with `directives` (`LOG_LEVEL` syntax, e.g.
+/// `debug` or `info,rust_hello_world=trace`).
+pub fn set_level(directives: &str) -> Result<(), String> {
+    let filter = EnvFilter::try_new(directives).map_err(|err| err.to_string())?;
+    let handle = LEVEL.get().ok_or("logging is not initialised")?;
+    handle.reload(filter).map_err(|err| err.to_string())?;
+    tracing::callsite::rebuild_interest_cache();
+    Ok(())
+}
+
+/// Appends to `path` and, once it would grow past `max_bytes`, shifts it to
+/// `path.1` (and `path.1` to `path.2`, ...), keeping `keep` old files.
+pub struct RotatingFile {
+    path: PathBuf,
+    max_bytes: u64,
+    keep: usize,
+    file: File,
+    written: u64,
+}
+
+impl RotatingFile {
+    pub fn open(path: PathBuf, max_bytes: u64, keep: usize) -> io::Result<Self> {
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let written = file.metadata()?.len();
+        Ok(Self {
+            path,
+            max_bytes,
+            keep,
+            file,
+            written,
+        })
+    }
+
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", n));
+        name.into()
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.keep == 0 {
+            self.file = File::create(&self.path)?;
+        } else {
+            remove_if_exists(&self.rotated(self.keep))?;
+            for n in (1..self.keep).rev() {
+                rename_if_exists(&self.rotated(n), &self.rotated(n + 1))?;
+            }
+            fs::rename(&self.path, self.rotated(1))?;
+            self.file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?;
+        }
+        self.written = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.written > 0 && self.written + buf.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+        let n = self.file.write(buf)?;
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+fn rename_if_exists(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::rename(from, to) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let dir = std::env::temp_dir().join(format!("rotating-{}-{}", name, nanos));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn capture(format: LogFormat) -> String {
+        let buf = Arc::new(Mutex::new(Vec::new()));
+        let writer = {
+            let buf = buf.clone();
+            move || Captured(buf.clone())
+        };
+        let subscriber = tracing_subscriber::registry().with(format.layer(writer));
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!(
+                target: "access",
+                method = %"GET",
+                path = "/hello/a b",
+                status = 200u16,
+                latency_ms = 1.5,
+                request_id = "req-1",
+            );
+        });
+        let bytes = buf.lock().unwrap().clone();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn every_format_carries_the_request_fields() {
+        for format in [LogFormat::Json, LogFormat::Logfmt, LogFormat::Pretty] {
+            let line = capture(format);
+            for field in ["method", "path", "status", "latency_ms", "request_id"] {
+                assert!(
+                    line.contains(field),
+                    "{:?} lacks {}: {}",
+                    format,
+                    field,
+                    line
+                );
+            }
+            assert!(line.contains("req-1"), "{:?}: {}", format, line);
+        }
+    }
+
+    #[test]
+    fn logfmt_quotes_only_when_needed() {
+        let line = capture(LogFormat::Logfmt);
+        assert!(line.starts_with("ts="));
+        assert!(line.contains(
+            " level=info target=access method=GET path=\"/hello/a b\" status=200 latency_ms=1.5 request_id=req-1\n"
+        ));
+        assert_eq!(logfmt_value(""), "\"\"");
+        assert_eq!(logfmt_value("a=\"b\"\n"), "\"a=\\\"b\\\"\\n\"");
+    }
+
+    #[test]
+    fn parses_log_format() {
+        assert_eq!("JSON".parse(), Ok(LogFormat::Json));
+        assert_eq!("logfmt".parse(), Ok(LogFormat::Logfmt));
+        assert_eq!("pretty".parse(), Ok(LogFormat::Pretty));
+        assert!("xml".parse::<LogFormat>().is_err());
+    }
+
+    #[test]
+    fn rotates_by_size_and_keeps_n_files() {
+        let dir = scratch_dir("keep");
+        let path = dir.join("access.log");
+        let mut file = RotatingFile::open(path.clone(), 10, 2).unwrap();
+        for line in ["aaaaaaaa\n", "bbbbbbbb\n", "cccccccc\n", "dddddddd\n"] {
+            file.write_all(line.as_bytes()).unwrap();
+        }
+        file.flush().unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "dddddddd\n");
+        assert_eq!(
+            fs::read_to_string(dir.join("access.log.1")).unwrap(),
+            "cccccccc\n"
+        );
+        assert_eq!(
+            fs::read_to_string(dir.join("access.log.2")).unwrap(),
+            "bbbbbbbb\n"
+        );
+        assert!(!dir.join("access.log.3").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn appends_to_an_existing_file() {
+        let dir = scratch_dir("append");
+        let path = dir.join("access.log");
+        fs::write(&path, "old\n").unwrap();
+        let mut file = RotatingFile::open(path.clone(), 1024, 1).unwrap();
+        file.write_all(b"new\n").unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "old\nnew\n");
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,42 +1,105 @@
-use std::env;
-use warp::Filter;
-use serde::Serialize;
-
-#[derive(Serialize)]
-struct Response {
-    message: String,
-    hostname: String,
-    timestamp: String,
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::Duration;
+
+use rust_hello_world::{logging, reload, server, tls, Routes, State};
+
+/// Binds `addr` or exits with status 1, so a crash-looping pod ends its log
+/// with the reason rather than a panic backtrace.
+async fn bind_or_exit(
+    addr: SocketAddr,
+    keepalive: &server::Keepalive,
+    retry_for: Duration,
+) -> warp::hyper::server::conn::AddrIncoming {
+    match server::bind_tcp_retrying(addr, keepalive, retry_for).await {
+        Ok(incoming) => incoming,
+        Err(err) if err.kind() == std::io::ErrorKind::AddrInUse => {
+            tracing::error!("port {} already in use", addr.port());
+            std::process::exit(1);
+        }
+        Err(err) => {
+            tracing::error!(error = %err, "failed to bind {}", addr);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// The value, or exits with status 1 after logging `err`, for startup
+/// misconfigurations that should end the log the same way as `bind_or_exit`.
+fn or_exit<T>(result: Result<T, String>) -> T {
+    result.unwrap_or_else(|err| {
+        tracing::error!("{}", err);
+        std::process::exit(1);
+    })
 }
 
 #[tokio::main]
 async fn main() {
-    let hostname = gethostname::gethostname()
-        .into_string()
-        .unwrap_or_else(|_| "unknown".to_string());
-
-    let hello = warp::path::end()
-        .map(move || {
-            let response = Response {
-                message: "Hello World from Rust! 🦀".to_string(),
-                hostname: hostname.clone(),
-                timestamp: chrono::Utc::now().to_rfc3339(),
-            };
-            warp::reply::json(&response)
-        });
-
-    let health = warp::path("health")
-        .map(|| warp::reply::with_status("OK", warp::http::StatusCode::OK));
-
-    let routes = hello.or(health);
-
-    let port = env::var("PORT")
-        .unwrap_or_else(|_| "8080".to_string())
-        .parse::<u16>()
-        .unwrap_or(8080);
-
-    println!("Starting Rust server on port {}", port);
-    warp::serve(routes)
-        .run(([0, 0, 0, 0], port))
-        .await;
+    let access_log_guard = logging::init();
+
+    let config = reload::load();
+    if let Some(level) = config.log_level.as_deref() {
+        if let Err(err) = logging::set_level(level) {
+            tracing::warn!(level, error = %err, "ignoring invalid LOG_LEVEL");
+        }
+    }
+    let state = State::new(&config);
+    state.spawn();
+    let Routes { app, admin } = rust_hello_world::routes(&config, &state);
+    let shutdown = &state.shutdown;
+
+    let keepalive = server::Keepalive::from_config(&config);
+    let port_retry = Duration::from_secs(config.port_retry_seconds);
+    let addr = or_exit(server::bind_address(config.bind_addr.as_deref(), config.port));
+
+    // With ADMIN_PORT set, /metrics, /admin/* and /debug/* move off the
+    // application port so only the latter needs to go through the ingress.
+    let admin_server = match (config.admin_port, admin) {
+        (Some(admin_port), Some(routes)) => {
+            let addr = SocketAddr::new(addr.ip(), admin_port);
+            let incoming = bind_or_exit(addr, &keepalive, port_retry).await;
+
+            tracing::info!("Starting admin server on {}", incoming.local_addr());
+            Some(server::serve(incoming, routes, Default::default(), shutdown.wait()))
+        }
+        _ => None,
+    };
+    let admin_server = async move {
+        if let Some(admin_server) = admin_server {
+            admin_server.await;
+        }
+    };
+
+    let options = server::ServeOptions {
+        mirror: Some(state.mirror.clone()),
+        tls: or_exit(tls::acceptor(&config)),
+        ..server::ServeOptions::from_config(&config)
+    };
+    let app_server = async {
+        if let Some(path) = config.unix_socket_path.as_deref() {
+            let path = Path::new(path);
+            let listener = or_exit(
+                server::bind_unix(path)
+                    .map_err(|err| format!("failed to bind {}: {}", path.display(), err)),
+            );
+
+            tracing::info!("Starting Rust server on unix socket {}", path.display());
+            state.self_ping.spawn(shutdown);
+            server::serve_unix(listener, app, options, shutdown.wait()).await;
+            return;
+        }
+
+        let incoming = bind_or_exit(addr, &keepalive, port_retry).await;
+
+        tracing::info!("Starting Rust server on {}", incoming.local_addr());
+        state.self_ping.spawn(shutdown);
+        server::serve(incoming, app, options, shutdown.wait()).await;
+    };
+
+    tokio::join!(app_server, admin_server);
+    if let Some(push) = &state.metrics_push {
+        push.finish().await;
+    }
+    tracing::info!("shutdown complete");
+    drop(access_log_guard);
 }
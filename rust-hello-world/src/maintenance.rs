@@ -0,0 +1,108 @@
+//! Maintenance mode: `/` and `/hello/{name}` answer 503 while every probe
+//! keeps its normal behaviour.
+//!
+//! Only those two greeting routes are affected. `/health`, `/healthz` and
+//! `/startupz` stay green, so the kubelet will not restart the pod, and
+//! `/readyz` is untouched too, so the pod stays in Service endpoints and
+//! clients get the maintenance message rather than connection errors. To
+//! take the pod out of rotation instead, use `POST /drain` (every
+//! application route answers 503, undone with `POST /undrain`) or
+//! `POST /shutdown`, which drains readiness and stops the process.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use warp::reject::Reject;
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+use crate::{admin, filters, reply};
+
+#[derive(Debug)]
+pub struct UnderMaintenance;
+
+impl Reject for UnderMaintenance {}
+
+/// Starts from `MAINTENANCE_MODE` and is flipped at runtime through
+/// `POST /admin/maintenance`.
+pub struct Maintenance {
+    enabled: AtomicBool,
+}
+
+impl Maintenance {
+    pub fn new(enabled: bool) -> Arc<Self> {
+        if enabled {
+            tracing::warn!("starting in maintenance mode");
+        }
+        Arc::new(Self {
+            enabled: AtomicBool::new(enabled),
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Acquire)
+    }
+
+    pub fn set(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Release);
+    }
+}
+
+/// Rejects with `UnderMaintenance` while maintenance mode is on.
+pub fn check(
+    maintenance: Arc<Maintenance>,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::any()
+        .and_then(move || {
+            let enabled = maintenance.is_enabled();
+            async move {
+                if enabled {
+                    Err(warp::reject::custom(UnderMaintenance))
+                } else {
+                    Ok(())
+                }
+            }
+        })
+        .untuple_one()
+}
+
+#[derive(Deserialize, Serialize, ToSchema)]
+pub(crate) struct MaintenanceState {
+    enabled: bool,
+}
+
+#[utoipa::path(
+    post,
+    operation_id = "admin_maintenance",
+    path = "/admin/maintenance",
+    tag = "admin",
+    request_body = MaintenanceState,
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "Maintenance mode after the change; only `/` and `/hello/{name}` are affected", body = MaintenanceState),
+        (status = 400, description = "Malformed body", body = ErrorResponse),
+        (status = 401, description = "Missing or wrong X-Admin-Token", body = ErrorResponse),
+    )
+)]
+/// `POST /admin/maintenance` with `{"enabled": bool}`. Absent unless
+/// `ADMIN_TOKEN` is set.
+pub fn route(
+    maintenance: Arc<Maintenance>,
+    max_json_depth: usize,
+    admin_token: Option<Arc<str>>,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    warp::path!("admin" / "maintenance")
+        .and(warp::post())
+        .and(admin::protected(admin_token))
+        .and(filters::json::<MaintenanceState>(max_json_depth))
+        .map(move |state: MaintenanceState| {
+            maintenance.set(state.enabled);
+            tracing::warn!(enabled = state.enabled, "maintenance mode changed");
+            reply::json(&MaintenanceState {
+                enabled: maintenance.is_enabled(),
+            })
+            .into_response()
+        })
+}
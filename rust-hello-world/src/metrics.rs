@@ -0,0 +1,556 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use prometheus::core::{Collector, Desc};
+use prometheus::proto::{self, MetricFamily, MetricType};
+use prometheus::{
+    Encoder, Gauge, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts, Registry,
+    TextEncoder,
+};
+use serde::Serialize;
+use utoipa::ToSchema;
+use warp::http::header::CONTENT_TYPE;
+use warp::http::Method;
+use warp::hyper::body::HttpBody;
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+use crate::{admin, reply};
+
+/// Every collector a `Metrics` exports, registered in a registry of their
+/// own so `/metrics/reset` can replace the whole set at once.
+struct Collectors {
+    registry: Registry,
+    request_duration: HistogramVec,
+    response_body_bytes: HistogramVec,
+    mirror_requests: IntCounterVec,
+    mirror_errors: IntCounter,
+    mirror_dropped: IntCounterVec,
+    metrics_push_failures: IntCounter,
+    access_log_sampled_out: IntCounter,
+    websocket_connections: IntGauge,
+    self_ping_duration: HistogramVec,
+    hello_cache_hits: IntCounter,
+    hello_cache_misses: IntCounter,
+    kv_entries: IntGauge,
+    kv_bytes: IntGauge,
+    memory_usage_bytes: IntGauge,
+    memory_limit_bytes: IntGauge,
+    memory_utilization_percent: Gauge,
+    cpu_usage_seconds: Gauge,
+    cpu_throttled_periods: IntGauge,
+}
+
+/// A counter whose value lives in an atomic someone else owns, so
+/// `/metrics` reports exactly what the owner reads from it.
+struct SharedCounter {
+    desc: Desc,
+    value: Arc<AtomicU64>,
+}
+
+impl SharedCounter {
+    fn new(name: &str, help: &str, value: Arc<AtomicU64>) -> Self {
+        let desc = Desc::new(name.to_string(), help.to_string(), Vec::new(), HashMap::new())
+            .expect("valid counter description");
+        Self { desc, value }
+    }
+}
+
+impl Collector for SharedCounter {
+    fn desc(&self) -> Vec<&Desc> {
+        vec![&self.desc]
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let mut counter = proto::Counter::default();
+        counter.set_value(self.value.load(Ordering::Relaxed) as f64);
+        let mut metric = proto::Metric::default();
+        metric.set_counter(counter);
+        let mut family = MetricFamily::default();
+        family.set_name(self.desc.fq_name.clone());
+        family.set_help(self.desc.help.clone());
+        family.set_field_type(MetricType::COUNTER);
+        family.set_metric(vec![metric].into());
+        vec![family]
+    }
+}
+
+impl Collectors {
+    /// `variant` is attached as a constant label to every metric so
+    /// dashboards can split stable from canary.
+    fn new(variant: Option<&str>, hello_requests: &Arc<AtomicU64>) -> Self {
+        let labels =
+            variant.map(|variant| HashMap::from([("variant".to_string(), variant.to_string())]));
+        let registry = Registry::new_custom(None, labels).expect("valid metrics registry");
+        let request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "HTTP request latency in seconds",
+            ),
+            &["route", "method", "status"],
+        )
+        .expect("create http_request_duration_seconds");
+        let response_body_bytes = HistogramVec::new(
+            HistogramOpts::new(
+                "response_body_bytes",
+                "Size of HTTP response bodies in bytes",
+            )
+            .buckets(vec![64.0, 256.0, 1024.0, 4096.0, 16384.0, 65536.0]),
+            &["route"],
+        )
+        .expect("create response_body_bytes");
+        let mirror_requests = IntCounterVec::new(
+            Opts::new(
+                "mirror_requests_total",
+                "Shadow requests to MIRROR_TARGET by outcome: success, failure or timeout",
+            ),
+            &["outcome"],
+        )
+        .expect("create mirror_requests_total");
+        let mirror_errors = IntCounter::new(
+            "mirror_errors_total",
+            "Shadow requests to MIRROR_TARGET that failed or timed out",
+        )
+        .expect("create mirror_errors_total");
+        let mirror_dropped = IntCounterVec::new(
+            Opts::new(
+                "mirror_dropped_total",
+                "Sampled requests not mirrored, by reason: rate_limited or queue_full",
+            ),
+            &["reason"],
+        )
+        .expect("create mirror_dropped_total");
+        let metrics_push_failures = IntCounter::new(
+            "metrics_push_failures_total",
+            "Pushes to METRICS_PUSH_URL that failed",
+        )
+        .expect("create metrics_push_failures_total");
+        let access_log_sampled_out = IntCounter::new(
+            "access_log_sampled_out_total",
+            "Requests left out of the access log by ACCESS_LOG_SAMPLE_RATE",
+        )
+        .expect("create access_log_sampled_out_total");
+        let websocket_connections = IntGauge::new(
+            "websocket_connections_active",
+            "WebSocket connections currently open on /ws",
+        )
+        .expect("create websocket_connections_active");
+        let self_ping_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "self_ping_duration_seconds",
+                "Latency of background GETs to SELF_PING_TARGETS",
+            ),
+            &["target", "status"],
+        )
+        .expect("create self_ping_duration_seconds");
+        let hello_cache_hits = IntCounter::new(
+            "hello_cache_hits_total",
+            "Responses to / served from the cached body",
+        )
+        .expect("create hello_cache_hits_total");
+        let hello_cache_misses = IntCounter::new(
+            "hello_cache_misses_total",
+            "Responses to / that had to be serialized",
+        )
+        .expect("create hello_cache_misses_total");
+        let kv_entries = IntGauge::new("kv_entries", "Keys held in the /kv store")
+            .expect("create kv_entries");
+        let kv_bytes = IntGauge::new(
+            "kv_bytes",
+            "Key and value bytes held in the /kv store",
+        )
+        .expect("create kv_bytes");
+        let memory_usage_bytes = IntGauge::new(
+            "resource_memory_usage_bytes",
+            "Memory in use by the container (cgroup v2) or process (RSS)",
+        )
+        .expect("create resource_memory_usage_bytes");
+        let memory_limit_bytes = IntGauge::new(
+            "resource_memory_limit_bytes",
+            "cgroup v2 memory.max; 0 when there is no limit",
+        )
+        .expect("create resource_memory_limit_bytes");
+        let memory_utilization_percent = Gauge::new(
+            "resource_memory_utilization_percent",
+            "Memory usage as a percentage of the container limit",
+        )
+        .expect("create resource_memory_utilization_percent");
+        let cpu_usage_seconds = Gauge::new(
+            "resource_cpu_usage_seconds",
+            "CPU time used by the container since it started, from cpu.stat",
+        )
+        .expect("create resource_cpu_usage_seconds");
+        let cpu_throttled_periods = IntGauge::new(
+            "resource_cpu_throttled_periods",
+            "CFS periods in which the container was throttled, from cpu.stat",
+        )
+        .expect("create resource_cpu_throttled_periods");
+
+        registry
+            .register(Box::new(request_duration.clone()))
+            .expect("register http_request_duration_seconds");
+        registry
+            .register(Box::new(response_body_bytes.clone()))
+            .expect("register response_body_bytes");
+        registry
+            .register(Box::new(mirror_requests.clone()))
+            .expect("register mirror_requests_total");
+        registry
+            .register(Box::new(mirror_errors.clone()))
+            .expect("register mirror_errors_total");
+        registry
+            .register(Box::new(mirror_dropped.clone()))
+            .expect("register mirror_dropped_total");
+        registry
+            .register(Box::new(metrics_push_failures.clone()))
+            .expect("register metrics_push_failures_total");
+        registry
+            .register(Box::new(access_log_sampled_out.clone()))
+            .expect("register access_log_sampled_out_total");
+        registry
+            .register(Box::new(websocket_connections.clone()))
+            .expect("register websocket_connections_active");
+        registry
+            .register(Box::new(self_ping_duration.clone()))
+            .expect("register self_ping_duration_seconds");
+        registry
+            .register(Box::new(hello_cache_hits.clone()))
+            .expect("register hello_cache_hits_total");
+        registry
+            .register(Box::new(hello_cache_misses.clone()))
+            .expect("register hello_cache_misses_total");
+        registry
+            .register(Box::new(kv_entries.clone()))
+            .expect("register kv_entries");
+        registry
+            .register(Box::new(kv_bytes.clone()))
+            .expect("register kv_bytes");
+        registry
+            .register(Box::new(memory_usage_bytes.clone()))
+            .expect("register resource_memory_usage_bytes");
+        registry
+            .register(Box::new(memory_limit_bytes.clone()))
+            .expect("register resource_memory_limit_bytes");
+        registry
+            .register(Box::new(memory_utilization_percent.clone()))
+            .expect("register resource_memory_utilization_percent");
+        registry
+            .register(Box::new(cpu_usage_seconds.clone()))
+            .expect("register resource_cpu_usage_seconds");
+        registry
+            .register(Box::new(cpu_throttled_periods.clone()))
+            .expect("register resource_cpu_throttled_periods");
+        registry
+            .register(Box::new(SharedCounter::new(
+                "hello_requests_total",
+                "Requests answered by / and /hello/{name}; the same count as request_count in their bodies",
+                hello_requests.clone(),
+            )))
+            .expect("register hello_requests_total");
+
+        Self {
+            registry,
+            request_duration,
+            response_body_bytes,
+            mirror_requests,
+            mirror_errors,
+            mirror_dropped,
+            metrics_push_failures,
+            access_log_sampled_out,
+            websocket_connections,
+            self_ping_duration,
+            hello_cache_hits,
+            hello_cache_misses,
+            kv_entries,
+            kv_bytes,
+            memory_usage_bytes,
+            memory_limit_bytes,
+            memory_utilization_percent,
+            cpu_usage_seconds,
+            cpu_throttled_periods,
+        }
+    }
+}
+
+/// The metrics of one `State`: its collectors, labelled with its `VARIANT`,
+/// and the count of `/` and `/hello/{name}` behind `hello_requests_total`.
+/// Clones share both, so a reset through one is seen by all of them.
+#[derive(Clone)]
+pub struct Metrics {
+    variant: Option<Arc<str>>,
+    hello_requests: Arc<AtomicU64>,
+    collectors: Arc<RwLock<Arc<Collectors>>>,
+}
+
+impl Metrics {
+    pub fn new(variant: Option<&str>, hello_requests: Arc<AtomicU64>) -> Self {
+        let collectors = Collectors::new(variant, &hello_requests);
+        Self {
+            variant: variant.map(Arc::from),
+            hello_requests,
+            collectors: Arc::new(RwLock::new(Arc::new(collectors))),
+        }
+    }
+
+    fn current(&self) -> Arc<Collectors> {
+        self.collectors.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Swaps in a fresh registry with zeroed collectors and returns how many
+    /// metric families the old one had. The count of `/` and
+    /// `/hello/{name}` is zeroed too, so their bodies keep agreeing with
+    /// `hello_requests_total`. Observations racing with the swap land in
+    /// whichever set they loaded, so none are half-applied.
+    fn reset(&self) -> usize {
+        self.hello_requests.store(0, Ordering::Relaxed);
+        let fresh = Arc::new(Collectors::new(self.variant.as_deref(), &self.hello_requests));
+        let old = std::mem::replace(
+            &mut *self.collectors.write().unwrap_or_else(|e| e.into_inner()),
+            fresh,
+        );
+        old.registry.gather().len()
+    }
+
+    pub fn record_mirror_outcome(&self, outcome: &str) {
+        self.current()
+            .mirror_requests
+            .with_label_values(&[outcome])
+            .inc();
+    }
+
+    pub fn record_mirror_error(&self) {
+        self.current().mirror_errors.inc();
+    }
+
+    pub fn record_mirror_drop(&self, reason: &str) {
+        self.current()
+            .mirror_dropped
+            .with_label_values(&[reason])
+            .inc();
+    }
+
+    pub fn record_metrics_push_failure(&self) {
+        self.current().metrics_push_failures.inc();
+    }
+
+    pub fn record_access_log_sampled_out(&self) {
+        self.current().access_log_sampled_out.inc();
+    }
+
+    pub fn record_hello_cache(&self, hit: bool) {
+        let collectors = self.current();
+        if hit {
+            collectors.hello_cache_hits.inc();
+        } else {
+            collectors.hello_cache_misses.inc();
+        }
+    }
+
+    /// Sets both gauges outright rather than adjusting them, so they are
+    /// right again after the next write even if `/metrics/reset` zeroed
+    /// them.
+    pub fn set_kv_usage(&self, entries: usize, bytes: u64) {
+        let collectors = self.current();
+        collectors.kv_entries.set(entries as i64);
+        collectors.kv_bytes.set(bytes as i64);
+    }
+
+    /// Mirrors the latest resource sample. Values a source cannot provide
+    /// are left at zero.
+    pub fn set_resource_usage(
+        &self,
+        memory_bytes: u64,
+        memory_limit: Option<u64>,
+        utilization_percent: Option<f64>,
+        cpu_seconds: Option<f64>,
+        throttled_periods: Option<u64>,
+    ) {
+        let collectors = self.current();
+        collectors.memory_usage_bytes.set(memory_bytes as i64);
+        collectors
+            .memory_limit_bytes
+            .set(memory_limit.unwrap_or(0) as i64);
+        collectors
+            .memory_utilization_percent
+            .set(utilization_percent.unwrap_or(0.0));
+        collectors.cpu_usage_seconds.set(cpu_seconds.unwrap_or(0.0));
+        collectors
+            .cpu_throttled_periods
+            .set(throttled_periods.unwrap_or(0) as i64);
+    }
+
+    /// `status` is the HTTP status code, or `error` when no response came
+    /// back.
+    pub fn record_self_ping(&self, target: &str, status: &str, elapsed: Duration) {
+        self.current()
+            .self_ping_duration
+            .with_label_values(&[target, status])
+            .observe(elapsed.as_secs_f64());
+    }
+
+    /// Counts an open WebSocket connection until the returned guard is
+    /// dropped. The guard keeps the gauge it incremented, so a reset in
+    /// between never drives the fresh one negative.
+    pub fn websocket_connected(&self) -> ConnectionGuard {
+        let gauge = self.current().websocket_connections.clone();
+        gauge.inc();
+        ConnectionGuard(gauge)
+    }
+
+    /// Records latency and response body size for every request `filter`
+    /// answers, labelled with `route`. Bodies without an exact length
+    /// (streams) only contribute to the latency histogram.
+    pub fn instrument<F, R>(
+        &self,
+        route: &'static str,
+        filter: F,
+    ) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone
+    where
+        F: Filter<Extract = (R,), Error = Rejection> + Clone + Send + Sync + 'static,
+        R: Reply,
+    {
+        let metrics = self.clone();
+        warp::any()
+            .map(Instant::now)
+            .and(warp::method())
+            .and(filter)
+            .map(move |start: Instant, method: Method, reply: R| {
+                let response = reply.into_response();
+                let collectors = metrics.current();
+                collectors
+                    .request_duration
+                    .with_label_values(&[route, method.as_str(), response.status().as_str()])
+                    .observe(start.elapsed().as_secs_f64());
+                if let Some(len) = response.body().size_hint().exact() {
+                    collectors
+                        .response_body_bytes
+                        .with_label_values(&[route])
+                        .observe(len as f64);
+                }
+                response
+            })
+    }
+
+    /// Every metric in the text exposition format, and its content type.
+    /// What `/metrics` serves and `METRICS_PUSH_URL` is sent.
+    pub fn encode(&self) -> (Vec<u8>, String) {
+        let encoder = TextEncoder::new();
+        let mut buffer = Vec::new();
+        if let Err(err) = encoder.encode(&self.current().registry.gather(), &mut buffer) {
+            tracing::error!(error = %err, "failed to encode metrics");
+        }
+        (buffer, encoder.format_type().to_string())
+    }
+}
+
+pub struct ConnectionGuard(IntGauge);
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
+
+/// Counts the request in `counter` and extracts the new total. Wraps
+/// around instead of panicking should it ever reach `u64::MAX`.
+pub fn count_request(
+    counter: Arc<AtomicU64>,
+) -> impl Filter<Extract = (u64,), Error = Infallible> + Clone {
+    warp::any().map(move || counter.fetch_add(1, Ordering::Relaxed).wrapping_add(1))
+}
+
+#[utoipa::path(
+    get,
+    operation_id = "metrics",
+    path = "/metrics",
+    tag = "admin",
+    responses((status = 200, description = "Prometheus text exposition format", body = String, content_type = "text/plain"))
+)]
+/// `GET /metrics` in the Prometheus text exposition format.
+pub fn route(metrics: Metrics) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    warp::path("metrics")
+        .and(warp::path::end())
+        .and(warp::get())
+        .map(move || {
+            let (body, format) = metrics.encode();
+            warp::reply::with_header(body, CONTENT_TYPE, format).into_response()
+        })
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct ResetResponse {
+    reset_families: usize,
+}
+
+#[utoipa::path(
+    post,
+    operation_id = "metrics_reset",
+    path = "/metrics/reset",
+    tag = "admin",
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "All metrics zeroed", body = ResetResponse),
+        (status = 401, description = "Missing or wrong X-Admin-Token", body = ErrorResponse),
+    )
+)]
+/// `POST /metrics/reset`: zeroes every metric so test runs sharing a pod
+/// start from a clean slate. Absent unless `ADMIN_TOKEN` is set.
+pub fn reset_route(
+    metrics: Metrics,
+    admin_token: Option<Arc<str>>,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    warp::path!("metrics" / "reset")
+        .and(warp::post())
+        .and(admin::protected(admin_token))
+        .map(move || {
+            let reset_families = metrics.reset();
+            tracing::warn!(reset_families, "metrics reset via /metrics/reset");
+            reply::json(&ResetResponse { reset_families }).into_response()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn concurrent_requests_are_all_counted_once() {
+        const TASKS: u64 = 32;
+        const REQUESTS_PER_TASK: u64 = 50;
+
+        let counter = Arc::new(AtomicU64::new(0));
+        let filter = warp::path::end()
+            .and(count_request(counter.clone()))
+            .map(|count: u64| reply::json(&serde_json::json!({ "request_count": count })));
+        let tasks: Vec<_> = (0..TASKS)
+            .map(|_| {
+                let filter = filter.clone();
+                tokio::spawn(async move {
+                    let mut seen = Vec::new();
+                    for _ in 0..REQUESTS_PER_TASK {
+                        let response = warp::test::request().path("/").reply(&filter).await;
+                        let body: serde_json::Value =
+                            serde_json::from_slice(response.body()).unwrap();
+                        seen.push(body["request_count"].as_u64().expect("a JSON number"));
+                    }
+                    seen
+                })
+            })
+            .collect();
+        let mut seen = Vec::new();
+        for task in tasks {
+            seen.extend(task.await.unwrap());
+        }
+
+        let total = TASKS * REQUESTS_PER_TASK;
+        assert_eq!(counter.load(Ordering::Relaxed), total);
+        seen.sort_unstable();
+        assert_eq!(seen, (1..=total).collect::<Vec<_>>(), "every count handed out once");
+
+        let families = SharedCounter::new("hello_requests_total", "test", counter).collect();
+        assert_eq!(families[0].get_metric()[0].get_counter().get_value(), total as f64);
+    }
+}
@@ -0,0 +1,238 @@
+//! Push mode for nodes Prometheus cannot scrape: with `METRICS_PUSH_URL`
+//! set, the registry `/metrics` serves is also PUT to a Pushgateway every
+//! `METRICS_PUSH_INTERVAL_SECONDS`, grouped by `job` (`METRICS_PUSH_JOB`)
+//! and `instance` (the hostname). `/metrics` keeps working either way.
+//!
+//! After the servers have drained on shutdown, one last push records the
+//! final values. With `METRICS_PUSH_DELETE_ON_SHUTDOWN` the group is
+//! deleted instead, so a stopped pod's values do not linger on the
+//! gateway.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use backoff::backoff::Backoff;
+use backoff::ExponentialBackoff;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use warp::http::header::CONTENT_TYPE;
+
+use crate::config::Config;
+use crate::hostname::Hostname;
+use crate::metrics::Metrics;
+use crate::shutdown::Shutdown;
+
+const PUSH_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+pub struct MetricsPush {
+    url: String,
+    job: String,
+    hostname: Hostname,
+    interval: Duration,
+    delete_on_shutdown: bool,
+    client: reqwest::Client,
+    metrics: Metrics,
+}
+
+impl MetricsPush {
+    pub fn from_config(config: &Config, hostname: Hostname, metrics: Metrics) -> Option<Arc<Self>> {
+        let url = config
+            .metrics_push_url
+            .as_deref()?
+            .trim_end_matches('/')
+            .to_string();
+        let client = match reqwest::Client::builder().timeout(PUSH_TIMEOUT).build() {
+            Ok(client) => client,
+            Err(err) => {
+                tracing::error!(error = %err, "cannot build metrics push client, push disabled");
+                return None;
+            }
+        };
+        let push = Self {
+            url,
+            job: config.metrics_push_job.clone(),
+            hostname,
+            interval: Duration::from_secs(config.metrics_push_interval_seconds.max(1)),
+            delete_on_shutdown: config.metrics_push_delete_on_shutdown,
+            client,
+            metrics,
+        };
+        tracing::info!(url = %push.group_url(), "pushing metrics");
+        Some(Arc::new(push))
+    }
+
+    /// `<METRICS_PUSH_URL>/metrics/job/<job>/instance/<hostname>`. Read on
+    /// every push, since the hostname can be refreshed.
+    fn group_url(&self) -> String {
+        format!(
+            "{}/metrics/{}/{}",
+            self.url,
+            label("job", &self.job),
+            label("instance", &self.hostname.get())
+        )
+    }
+
+    /// Pushes every interval until shutdown, backing off after failures.
+    /// The last push is left to `finish`, once the servers have drained.
+    pub fn spawn(self: &Arc<Self>, shutdown: &Shutdown) {
+        let push = self.clone();
+        let stop = shutdown.wait();
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = push.run() => {}
+                _ = stop => {}
+            }
+        });
+    }
+
+    async fn run(&self) {
+        let mut backoff = ExponentialBackoff {
+            initial_interval: self.interval,
+            current_interval: self.interval,
+            max_interval: MAX_BACKOFF.max(self.interval),
+            multiplier: 2.0,
+            randomization_factor: 0.0,
+            max_elapsed_time: None,
+            ..ExponentialBackoff::default()
+        };
+        let mut delay = self.interval;
+        loop {
+            tokio::time::sleep(delay).await;
+            delay = match self.push().await {
+                Ok(()) => {
+                    backoff.reset();
+                    self.interval
+                }
+                Err(err) => {
+                    let delay = backoff.next_backoff().unwrap_or(MAX_BACKOFF);
+                    tracing::warn!(
+                        error = %err,
+                        retry_in_secs = delay.as_secs_f64(),
+                        "metrics push failed"
+                    );
+                    delay
+                }
+            };
+        }
+    }
+
+    /// The shutdown push, or the group's deletion with
+    /// `METRICS_PUSH_DELETE_ON_SHUTDOWN`.
+    pub async fn finish(&self) {
+        let (action, result) = if self.delete_on_shutdown {
+            ("delete", self.delete().await)
+        } else {
+            ("push", self.push().await)
+        };
+        match result {
+            Ok(()) => tracing::info!("final metrics {} done", action),
+            Err(err) => tracing::warn!(error = %err, "final metrics {} failed", action),
+        }
+    }
+
+    async fn push(&self) -> Result<(), String> {
+        let (body, format) = self.metrics.encode();
+        let result = self
+            .client
+            .put(self.group_url())
+            .header(CONTENT_TYPE.as_str(), format)
+            .body(body)
+            .send()
+            .await;
+        check(result).inspect_err(|_| self.metrics.record_metrics_push_failure())
+    }
+
+    async fn delete(&self) -> Result<(), String> {
+        check(self.client.delete(self.group_url()).send().await)
+    }
+}
+
+fn check(result: reqwest::Result<reqwest::Response>) -> Result<(), String> {
+    match result {
+        Ok(response) if response.status().is_success() => Ok(()),
+        Ok(response) => Err(format!("gateway answered {}", response.status())),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+/// One grouping label as a URL path pair. Values that cannot sit in a path
+/// segment as-is use the gateway's `@base64` form, where `=` is empty.
+fn label(name: &str, value: &str) -> String {
+    let plain = !value.is_empty()
+        && value
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"-._~".contains(&b));
+    if plain {
+        return format!("{}/{}", name, value);
+    }
+    match URL_SAFE_NO_PAD.encode(value) {
+        encoded if encoded.is_empty() => format!("{}@base64/=", name),
+        encoded => format!("{}@base64/{}", name, encoded),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use warp::Filter;
+
+    use super::*;
+
+    #[test]
+    fn labels_fall_back_to_base64() {
+        assert_eq!(
+            label("instance", "pod-7d9f.local"),
+            "instance/pod-7d9f.local"
+        );
+        assert_eq!(label("job", "a/b"), "job@base64/YS9i");
+        assert_eq!(label("job", ""), "job@base64/=");
+    }
+
+    #[tokio::test]
+    async fn pushes_the_registry_and_deletes_on_finish() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let gateway = {
+            let seen = seen.clone();
+            warp::method()
+                .and(warp::path::full())
+                .and(warp::body::bytes())
+                .map(
+                    move |method, path: warp::path::FullPath, body: warp::hyper::body::Bytes| {
+                        seen.lock()
+                            .unwrap()
+                            .push((method, path.as_str().to_string(), body));
+                        warp::reply()
+                    },
+                )
+        };
+        let (addr, server) = warp::serve(gateway).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        let mut config = Config::from_env();
+        config.metrics_push_url = Some(format!("http://{}/", addr));
+        config.metrics_push_job = "hello".to_string();
+        config.metrics_push_delete_on_shutdown = true;
+        let push = MetricsPush::from_config(
+            &config,
+            Hostname::Static(Arc::from("pod-1")),
+            Metrics::new(None, Arc::default()),
+        )
+        .unwrap();
+
+        push.push().await.unwrap();
+        push.finish().await;
+
+        let seen = seen.lock().unwrap();
+        let (method, path, body) = &seen[0];
+        assert_eq!(
+            (method.as_str(), path.as_str()),
+            ("PUT", "/metrics/job/hello/instance/pod-1")
+        );
+        assert!(std::str::from_utf8(body)
+            .unwrap()
+            .contains("metrics_push_failures_total"));
+        assert_eq!(seen[1].0.as_str(), "DELETE");
+    }
+}
@@ -0,0 +1,536 @@
+//! Traffic shadowing: application requests are replayed against
+//! `MIRROR_TARGET` after the real response is ready, on their own tasks, so
+//! the mirror can neither slow down nor change what the client sees.
+//!
+//! A slow or dead target never makes us hold more: at most
+//! `MIRROR_MAX_IN_FLIGHT` replays run at once and at most
+//! `MIRROR_MAX_PER_SECOND` start each second. Anything over either limit is
+//! dropped and counted. The target and sample percentage can be changed at
+//! runtime through `PUT /mirror`, including turning mirroring on when
+//! `MIRROR_TARGET` was unset at startup.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Semaphore;
+use utoipa::ToSchema;
+use warp::http::{HeaderMap, Method, StatusCode, Uri};
+use warp::hyper::body::Bytes;
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+use crate::config::Config;
+use crate::metrics::Metrics;
+use crate::{admin, filters, reply, ErrorResponse};
+
+const MIRROR_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Headers that describe the inbound hop rather than the request, which
+/// reqwest sets for the mirror connection itself.
+const SKIPPED_HEADERS: &[&str] = &[
+    "host",
+    "content-length",
+    "connection",
+    "keep-alive",
+    "transfer-encoding",
+    "upgrade",
+    "te",
+    "trailer",
+    "proxy-connection",
+];
+
+/// Probe and scrape traffic says nothing about the new version and would
+/// drown out real requests.
+const SKIPPED_PATHS: &[&str] = &[
+    "/health",
+    "/healthz",
+    "/healthz/dependencies",
+    "/readyz",
+    "/startupz",
+    "/metrics",
+    "/mirror/stats",
+];
+
+/// A token bucket holding up to one second's worth of requests. A rate of
+/// zero means unlimited.
+struct RateLimit {
+    per_second: f64,
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl RateLimit {
+    fn new(per_second: u32, now: Instant) -> Self {
+        Self {
+            per_second: per_second as f64,
+            tokens: per_second as f64,
+            refilled: now,
+        }
+    }
+
+    fn try_take(&mut self, now: Instant) -> bool {
+        if self.per_second == 0.0 {
+            return true;
+        }
+        let elapsed = now.saturating_duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.per_second).min(self.per_second);
+        self.refilled = now;
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Outcome {
+    Success,
+    Failure,
+    Timeout,
+}
+
+/// Since startup; unlike the Prometheus counters in `metrics`,
+/// `/metrics/reset` leaves these alone.
+struct Counts {
+    mirrored: AtomicU64,
+    succeeded: AtomicU64,
+    failed: AtomicU64,
+    timed_out: AtomicU64,
+    dropped: AtomicU64,
+    metrics: Metrics,
+}
+
+impl Counts {
+    fn new(metrics: Metrics) -> Self {
+        Self {
+            mirrored: AtomicU64::new(0),
+            succeeded: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+            timed_out: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            metrics,
+        }
+    }
+
+    fn record(&self, outcome: Outcome) {
+        let (counter, label) = match outcome {
+            Outcome::Success => (&self.succeeded, "success"),
+            Outcome::Failure => (&self.failed, "failure"),
+            Outcome::Timeout => (&self.timed_out, "timeout"),
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        self.metrics.record_mirror_outcome(label);
+        if !matches!(outcome, Outcome::Success) {
+            self.metrics.record_mirror_error();
+        }
+    }
+
+    fn drop_request(&self, reason: &'static str) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+        self.metrics.record_mirror_drop(reason);
+    }
+}
+
+/// What `PUT /mirror` changes.
+#[derive(Clone, Serialize, ToSchema)]
+pub(crate) struct MirrorSettings {
+    /// `None` while mirroring is off.
+    target: Option<String>,
+    sample_percent: f64,
+}
+
+pub struct Mirror {
+    settings: RwLock<MirrorSettings>,
+    client: Option<reqwest::Client>,
+    in_flight: Arc<Semaphore>,
+    max_in_flight: usize,
+    rate: Mutex<RateLimit>,
+    rng: Mutex<StdRng>,
+    counts: Arc<Counts>,
+}
+
+impl Mirror {
+    /// Always built, so `PUT /mirror` can turn mirroring on later.
+    pub fn from_config(config: &Config, metrics: Metrics) -> Arc<Self> {
+        let client = match reqwest::Client::builder().timeout(MIRROR_TIMEOUT).build() {
+            Ok(client) => Some(client),
+            Err(err) => {
+                tracing::error!(error = %err, "cannot build mirror client, mirrored requests will fail");
+                None
+            }
+        };
+        let mut sample_percent = config.mirror_sample_percent;
+        if !(0.0..=100.0).contains(&sample_percent) {
+            tracing::warn!(sample_percent, "MIRROR_SAMPLE_PERCENT is outside 0-100, clamping");
+            sample_percent = sample_percent.clamp(0.0, 100.0);
+        }
+        let mirror = Self::new(
+            client,
+            config.mirror_url.as_deref(),
+            sample_percent,
+            config.mirror_max_in_flight,
+            config.mirror_max_per_second,
+            metrics,
+        );
+        if let Some(target) = &mirror.settings().target {
+            tracing::info!(url = %target, sample_percent, "mirroring requests");
+        }
+        Arc::new(mirror)
+    }
+
+    fn new(
+        client: Option<reqwest::Client>,
+        target: Option<&str>,
+        sample_percent: f64,
+        max_in_flight: usize,
+        max_per_second: u32,
+        metrics: Metrics,
+    ) -> Self {
+        let max_in_flight = max_in_flight.max(1);
+        Self {
+            settings: RwLock::new(MirrorSettings {
+                target: target.map(|target| target.trim_end_matches('/').to_string()),
+                sample_percent,
+            }),
+            client,
+            in_flight: Arc::new(Semaphore::new(max_in_flight)),
+            max_in_flight,
+            rate: Mutex::new(RateLimit::new(max_per_second, Instant::now())),
+            rng: Mutex::new(StdRng::from_entropy()),
+            counts: Arc::new(Counts::new(metrics)),
+        }
+    }
+
+    fn settings(&self) -> MirrorSettings {
+        self.settings.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Whether this request should be replayed at all. Checked before the
+    /// body is buffered, so requests left out of the sample cost nothing.
+    pub fn wants(&self, uri: &Uri, headers: &HeaderMap) -> bool {
+        // Admin requests are never replayed: the shadow must not be shut
+        // down or crashed along with us, nor see our admin token.
+        if headers.contains_key("x-admin-token") || SKIPPED_PATHS.contains(&uri.path()) {
+            return false;
+        }
+        let sample_percent = {
+            let settings = self.settings.read().unwrap_or_else(|e| e.into_inner());
+            if settings.target.is_none() {
+                return false;
+            }
+            settings.sample_percent
+        };
+        if sample_percent >= 100.0 {
+            return true;
+        }
+        let mut rng = self.rng.lock().unwrap_or_else(|e| e.into_inner());
+        rng.gen_bool(sample_percent / 100.0)
+    }
+
+    /// Replays the request against the mirror in the background and warns
+    /// if its status differs from `real`. Past the rate limit or the
+    /// in-flight cap the request is dropped, never queued.
+    pub fn send(
+        &self,
+        method: &Method,
+        uri: &Uri,
+        headers: &HeaderMap,
+        body: Bytes,
+        real: StatusCode,
+    ) {
+        let Some(target) = self.settings().target else {
+            return;
+        };
+        let method = match reqwest::Method::from_bytes(method.as_str().as_bytes()) {
+            Ok(method) => method,
+            Err(_) => return,
+        };
+        let admitted = self
+            .rate
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .try_take(Instant::now());
+        if !admitted {
+            self.counts.drop_request("rate_limited");
+            return;
+        }
+        let Ok(permit) = self.in_flight.clone().try_acquire_owned() else {
+            self.counts.drop_request("queue_full");
+            return;
+        };
+        self.counts.mirrored.fetch_add(1, Ordering::Relaxed);
+        let Some(client) = &self.client else {
+            self.counts.record(Outcome::Failure);
+            return;
+        };
+
+        let path = uri.path_and_query().map_or("/", |p| p.as_str()).to_string();
+        let url = format!("{}{}", target, path);
+        let mut request = client.request(method.clone(), &url).body(body);
+        for (name, value) in headers {
+            if !SKIPPED_HEADERS.contains(&name.as_str()) {
+                request = request.header(name.as_str(), value.as_bytes());
+            }
+        }
+
+        let counts = self.counts.clone();
+        tokio::spawn(async move {
+            let _permit = permit;
+            let outcome = match request.send().await {
+                Ok(response) => {
+                    if response.status().as_u16() != real.as_u16() {
+                        tracing::warn!(
+                            method = %method,
+                            path,
+                            real = real.as_u16(),
+                            mirror = response.status().as_u16(),
+                            "mirror status differs"
+                        );
+                    }
+                    Outcome::Success
+                }
+                Err(err) if err.is_timeout() => {
+                    tracing::warn!(method = %method, path, "mirror request timed out");
+                    Outcome::Timeout
+                }
+                Err(err) => {
+                    tracing::warn!(method = %method, path, error = %err, "mirror request failed");
+                    Outcome::Failure
+                }
+            };
+            counts.record(outcome);
+        });
+    }
+
+    fn stats(&self) -> MirrorStats {
+        let MirrorSettings {
+            target,
+            sample_percent,
+        } = self.settings();
+        let count = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        MirrorStats {
+            target,
+            sample_percent,
+            mirrored: count(&self.counts.mirrored),
+            succeeded: count(&self.counts.succeeded),
+            failed: count(&self.counts.failed) + count(&self.counts.timed_out),
+            timed_out: count(&self.counts.timed_out),
+            dropped: count(&self.counts.dropped),
+            in_flight: self.max_in_flight - self.in_flight.available_permits(),
+            max_in_flight: self.max_in_flight,
+        }
+    }
+
+    /// Applies whichever fields `update` sets. An empty `target` turns
+    /// mirroring off.
+    fn update(&self, update: MirrorUpdate) -> Result<MirrorSettings, String> {
+        let target = match update.target.as_deref() {
+            None => None,
+            Some("") => Some(None),
+            Some(target) => Some(Some(parse_target(target)?)),
+        };
+        if let Some(percent) = update.sample_percent {
+            if !(0.0..=100.0).contains(&percent) {
+                return Err("sample_percent must be between 0 and 100".to_string());
+            }
+        }
+
+        let mut settings = self.settings.write().unwrap_or_else(|e| e.into_inner());
+        if let Some(target) = target {
+            settings.target = target;
+        }
+        if let Some(percent) = update.sample_percent {
+            settings.sample_percent = percent;
+        }
+        Ok(settings.clone())
+    }
+}
+
+/// An absolute http(s) URL, without the trailing slash request paths are
+/// appended to.
+fn parse_target(target: &str) -> Result<String, String> {
+    let url = reqwest::Url::parse(target).map_err(|err| format!("invalid target: {}", err))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err("target must be an http or https URL".to_string());
+    }
+    Ok(target.trim_end_matches('/').to_string())
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct MirrorStats {
+    target: Option<String>,
+    sample_percent: f64,
+    /// Replays started.
+    mirrored: u64,
+    /// Replays that got a response, whatever its status.
+    succeeded: u64,
+    /// Replays that got no response, timeouts included.
+    failed: u64,
+    timed_out: u64,
+    /// Sampled requests left out by the rate limit or the in-flight cap.
+    dropped: u64,
+    in_flight: usize,
+    max_in_flight: usize,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct MirrorUpdate {
+    /// New base URL; an empty string turns mirroring off.
+    target: Option<String>,
+    /// Share of requests replayed, `0`–`100`.
+    sample_percent: Option<f64>,
+}
+
+#[utoipa::path(
+    get,
+    operation_id = "mirror_stats",
+    path = "/mirror/stats",
+    tag = "ops",
+    responses(
+        (status = 200, description = "Mirror settings and counts since startup", body = MirrorStats),
+    )
+)]
+/// `GET /mirror/stats`.
+pub fn stats_route(
+    mirror: Arc<Mirror>,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    warp::path!("mirror" / "stats")
+        .and(warp::get())
+        .map(move || reply::json(&mirror.stats()).into_response())
+}
+
+#[utoipa::path(
+    put,
+    operation_id = "mirror",
+    path = "/mirror",
+    tag = "admin",
+    request_body = MirrorUpdate,
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "The settings now in effect", body = MirrorSettings),
+        (status = 400, description = "Malformed body, bad target or sample_percent out of range", body = ErrorResponse),
+        (status = 401, description = "Missing or wrong X-Admin-Token", body = ErrorResponse),
+    )
+)]
+/// `PUT /mirror`. Absent unless `ADMIN_TOKEN` is set.
+pub fn update_route(
+    mirror: Arc<Mirror>,
+    max_json_depth: usize,
+    admin_token: Option<Arc<str>>,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    warp::path("mirror")
+        .and(warp::path::end())
+        .and(warp::put())
+        .and(admin::protected(admin_token))
+        .and(filters::json::<MirrorUpdate>(max_json_depth))
+        .map(move |update: MirrorUpdate| match mirror.update(update) {
+            Ok(settings) => {
+                tracing::warn!(
+                    target = settings.target.as_deref().unwrap_or("none"),
+                    sample_percent = settings.sample_percent,
+                    "mirror changed via /mirror"
+                );
+                reply::json(&settings).into_response()
+            }
+            Err(error) => warp::reply::with_status(
+                reply::json(&ErrorResponse { error }),
+                StatusCode::BAD_REQUEST,
+            )
+            .into_response(),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mirror(target: Option<&str>, sample_percent: f64) -> Mirror {
+        Mirror::new(
+            None,
+            target,
+            sample_percent,
+            2,
+            0,
+            Metrics::new(None, Arc::default()),
+        )
+    }
+
+    #[test]
+    fn rate_limit_refills_over_time() {
+        let start = Instant::now();
+        let mut rate = RateLimit::new(2, start);
+        assert!(rate.try_take(start));
+        assert!(rate.try_take(start));
+        assert!(!rate.try_take(start));
+        assert!(rate.try_take(start + Duration::from_millis(500)));
+        assert!(!rate.try_take(start + Duration::from_millis(500)));
+
+        let mut unlimited = RateLimit::new(0, start);
+        assert!((0..1000).all(|_| unlimited.try_take(start)));
+    }
+
+    #[test]
+    fn wants_needs_a_target_and_skips_admin_and_probe_traffic() {
+        let headers = HeaderMap::new();
+        let root = Uri::from_static("/");
+        assert!(!mirror(None, 100.0).wants(&root, &headers));
+
+        let mirror = mirror(Some("http://shadow/"), 100.0);
+        assert!(mirror.wants(&root, &headers));
+        assert!(!mirror.wants(&Uri::from_static("/healthz"), &headers));
+        let mut admin = HeaderMap::new();
+        admin.insert("x-admin-token", "secret".parse().unwrap());
+        assert!(!mirror.wants(&root, &admin));
+    }
+
+    #[test]
+    fn zero_sample_percent_mirrors_nothing() {
+        let mirror = mirror(Some("http://shadow"), 0.0);
+        assert!((0..100).all(|_| !mirror.wants(&Uri::from_static("/"), &HeaderMap::new())));
+    }
+
+    #[tokio::test]
+    async fn drops_past_the_in_flight_cap() {
+        let mirror = mirror(Some("http://shadow"), 100.0);
+        let _held = mirror.in_flight.clone().try_acquire_many_owned(2).unwrap();
+        let uri = Uri::from_static("/");
+        mirror.send(&Method::GET, &uri, &HeaderMap::new(), Bytes::new(), StatusCode::OK);
+
+        let stats = mirror.stats();
+        assert_eq!((stats.mirrored, stats.dropped, stats.in_flight), (0, 1, 2));
+    }
+
+    #[tokio::test]
+    async fn update_changes_target_and_sample_percent() {
+        let mirror = Arc::new(mirror(None, 100.0));
+        let route = update_route(
+            mirror.clone(),
+            filters::DEFAULT_MAX_JSON_DEPTH,
+            Some(Arc::from("secret")),
+        );
+        let put = |body: &'static str| {
+            warp::test::request()
+                .method("PUT")
+                .path("/mirror")
+                .header("x-admin-token", "secret")
+                .body(body)
+                .reply(&route)
+        };
+
+        let res = put(r#"{"target":"http://shadow:8080/","sample_percent":25}"#).await;
+        assert_eq!(res.status(), 200);
+        assert_eq!(res.body(), r#"{"target":"http://shadow:8080","sample_percent":25.0}"#);
+
+        assert_eq!(put(r#"{"sample_percent":101}"#).await.status(), 400);
+        assert_eq!(put(r#"{"target":"ftp://shadow"}"#).await.status(), 400);
+
+        let res = put(r#"{"target":""}"#).await;
+        assert_eq!(res.body(), r#"{"target":null,"sample_percent":25.0}"#);
+        assert!(!mirror.wants(&Uri::from_static("/"), &HeaderMap::new()));
+    }
+}
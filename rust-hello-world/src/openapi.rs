@@ -0,0 +1,345 @@
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+use warp::http::header::CONTENT_TYPE;
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+use crate::reply;
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "rust-hello-world"),
+    paths(
+        paths::hello,
+        paths::hello_name,
+        paths::health,
+        paths::version,
+        paths::whoami,
+        paths::labels,
+        paths::annotations,
+        paths::static_files,
+        paths::config,
+        crate::access_log::update_route,
+        paths::debug_config,
+        paths::openapi_json,
+        paths::docs,
+        crate::probes::startupz,
+        crate::probes::readyz,
+        crate::probes::healthz,
+        crate::probes::healthz_live,
+        crate::dependencies::route,
+        crate::selfping::route,
+        crate::resources::route,
+        crate::metrics::route,
+        crate::metrics::reset_route,
+        crate::shutdown::route,
+        crate::chaos::crash,
+        crate::chaos::panic,
+        crate::chaos::unhealthy,
+        crate::chaos::healthy,
+        crate::fs::route,
+        crate::tasks::route,
+        crate::profiling::route,
+        crate::upload::route,
+        crate::transfer::download_route,
+        crate::chain::route,
+        crate::podinfo::route,
+        crate::kv::list_route,
+        crate::kv::get_route,
+        crate::kv::put_route,
+        crate::kv::delete_route,
+        crate::ws::route,
+        crate::variant::route,
+        crate::environment::route,
+        crate::feature_flags::route,
+        crate::maintenance::route,
+        crate::drain::route,
+        crate::drain::undrain_route,
+        crate::mirror::stats_route,
+        crate::mirror::update_route,
+    ),
+    components(schemas(
+        crate::Response,
+        crate::ErrorResponse,
+        crate::timezone::UnknownTimezone,
+        crate::client_ip::ClientInfo,
+        crate::version::VersionInfo,
+        crate::podinfo::PodInfo,
+        crate::lifecycle::Phase,
+        crate::lifecycle::Progress,
+        crate::probes::ProbeStatus,
+        crate::metrics::ResetResponse,
+        crate::shutdown::ShutdownResponse,
+        crate::chaos::CrashRequest,
+        crate::chaos::CrashResponse,
+        crate::chaos::PanicResponse,
+        crate::chaos::UnhealthyRequest,
+        crate::chaos::LivenessResponse,
+        crate::maintenance::MaintenanceState,
+        crate::drain::DrainState,
+        crate::mirror::MirrorStats,
+        crate::mirror::MirrorSettings,
+        crate::mirror::MirrorUpdate,
+        crate::access_log::LoggingSettings,
+        crate::access_log::LoggingUpdate,
+        crate::tasks::TaskStats,
+        crate::tasks::WorkerStats,
+        crate::upload::StoredFile,
+        crate::dependencies::DependencyReport,
+        crate::dependencies::DependencyStatus,
+        crate::selfping::PingResult,
+        crate::resources::Stats,
+        crate::resources::Sample,
+        crate::resources::PressureState,
+        crate::chain::ChainHop,
+        crate::chain::ChainError,
+        crate::kv::KvEntry,
+        crate::kv::KvPage,
+        crate::fs::Listing,
+        crate::fs::Entry,
+    )),
+    modifiers(&AdminToken)
+)]
+struct ApiDoc;
+
+struct AdminToken;
+
+impl Modify for AdminToken {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "admin_token",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("X-Admin-Token"))),
+        );
+    }
+}
+
+/// Routes whose handlers are closures in `main` have no function to hang
+/// `#[utoipa::path]` on, so they are described here instead.
+#[allow(dead_code)]
+mod paths {
+    #[utoipa::path(
+        get,
+        path = "/",
+        tag = "app",
+        params(
+            ("tz" = Option<String>, Query, description = "IANA zone for `timestamp_local`, overriding TIMEZONE"),
+        ),
+        responses(
+            (status = 200, description = "Greeting from this pod", body = Response),
+            (status = 400, description = "`tz` is not an IANA zone name", body = UnknownTimezone),
+            (status = 500, description = "Failed on purpose by VARIANT_ERROR_RATE", body = ErrorResponse),
+            (status = 503, description = "Maintenance mode is on", body = ErrorResponse),
+        )
+    )]
+    fn hello() {}
+
+    #[utoipa::path(
+        get,
+        path = "/hello/{name}",
+        tag = "app",
+        params(
+            ("name" = String, Path, description = "Who to greet"),
+            ("tz" = Option<String>, Query, description = "IANA zone for `timestamp_local`, overriding TIMEZONE"),
+        ),
+        responses(
+            (status = 200, description = "Greeting for `name` from this pod", body = Response),
+            (status = 400, description = "`tz` is not an IANA zone name", body = UnknownTimezone),
+            (status = 503, description = "Maintenance mode is on", body = ErrorResponse),
+        )
+    )]
+    fn hello_name() {}
+
+    #[utoipa::path(
+        get,
+        path = "/health",
+        tag = "probes",
+        responses((status = 200, description = "Always `OK`", body = String, content_type = "text/plain"))
+    )]
+    fn health() {}
+
+    #[utoipa::path(
+        get,
+        path = "/version",
+        tag = "app",
+        params(
+            ("If-None-Match" = Option<String>, Header, description = "ETag from a previous response"),
+            ("If-Modified-Since" = Option<String>, Header, description = "Last-Modified from a previous response"),
+        ),
+        responses(
+            (status = 200, description = "Build information", body = VersionInfo),
+            (status = 304, description = "The client's copy is current"),
+        )
+    )]
+    fn version() {}
+
+    #[utoipa::path(
+        get,
+        path = "/whoami",
+        tag = "app",
+        responses((status = 200, description = "How this request's client was resolved", body = ClientInfo))
+    )]
+    fn whoami() {}
+
+    #[utoipa::path(
+        get,
+        path = "/labels",
+        tag = "app",
+        params(
+            ("If-None-Match" = Option<String>, Header, description = "ETag from a previous response"),
+            ("If-Modified-Since" = Option<String>, Header, description = "Last-Modified from a previous response"),
+        ),
+        responses(
+            (status = 200, description = "Pod labels from the downward API", body = BTreeMap<String, String>),
+            (status = 304, description = "The client's copy is current"),
+            (status = 404, description = "LABELS_FILE not configured", body = ErrorResponse),
+        )
+    )]
+    fn labels() {}
+
+    #[utoipa::path(
+        get,
+        path = "/annotations",
+        tag = "app",
+        responses(
+            (status = 200, description = "Pod annotations from the downward API", body = BTreeMap<String, String>),
+            (status = 404, description = "ANNOTATIONS_FILE not configured", body = ErrorResponse),
+        )
+    )]
+    fn annotations() {}
+
+    #[utoipa::path(
+        get,
+        path = "/static/{path}",
+        tag = "app",
+        params(("path" = String, Path, description = "File under STATIC_DIR")),
+        responses(
+            (status = 200, description = "The file, cacheable for STATIC_CACHE_MAX_AGE seconds", body = Vec<u8>, content_type = "application/octet-stream"),
+            (status = 404, description = "No such file, or STATIC_DIR not configured", body = ErrorResponse),
+        )
+    )]
+    fn static_files() {}
+
+    #[utoipa::path(
+        get,
+        path = "/config",
+        tag = "admin",
+        security(("admin_token" = [])),
+        responses(
+            (status = 200, description = "Effective configuration with secrets redacted", body = Object),
+            (status = 401, description = "Missing or wrong X-Admin-Token", body = ErrorResponse),
+        )
+    )]
+    fn config() {}
+
+    #[utoipa::path(
+        get,
+        path = "/debug/config",
+        tag = "admin",
+        security((), ("admin_token" = [])),
+        responses(
+            (status = 200, description = "Effective configuration with secrets redacted", body = Object),
+            (status = 401, description = "Missing or wrong X-Admin-Token", body = ErrorResponse),
+            (status = 404, description = "ENABLE_DEBUG_ENDPOINTS is off", body = ErrorResponse),
+        )
+    )]
+    fn debug_config() {}
+
+    #[utoipa::path(
+        get,
+        path = "/openapi.json",
+        tag = "docs",
+        responses((status = 200, description = "This document", body = Object))
+    )]
+    fn openapi_json() {}
+
+    #[utoipa::path(
+        get,
+        path = "/docs",
+        tag = "docs",
+        responses((status = 200, description = "Swagger UI", body = String, content_type = "text/html"))
+    )]
+    fn docs() {}
+}
+
+const SWAGGER_UI: &str = r##"<!DOCTYPE html>
+<html lang="en">
+<head>
+  <meta charset="utf-8">
+  <title>rust-hello-world API</title>
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css">
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+  <script>
+    window.ui = SwaggerUIBundle({ url: "/openapi.json", dom_id: "#swagger-ui" });
+  </script>
+</body>
+</html>
+"##;
+
+/// `GET /openapi.json`. The document is built once; it only changes with
+/// the code.
+pub fn spec() -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    let body = reply::json(&ApiDoc::openapi());
+    warp::path("openapi.json")
+        .and(warp::path::end())
+        .and(warp::get())
+        .map(move || body.clone().into_response())
+}
+
+/// `GET /docs`: Swagger UI (loaded from unpkg) pointed at `/openapi.json`.
+pub fn docs() -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    warp::path("docs")
+        .and(warp::path::end())
+        .and(warp::get())
+        .map(|| {
+            warp::reply::with_header(SWAGGER_UI, CONTENT_TYPE, "text/html; charset=utf-8")
+                .into_response()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn documents_core_routes_as_openapi_3_0() {
+        let spec = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        assert!(spec["openapi"].as_str().unwrap().starts_with("3.0."));
+        for (path, schema) in [
+            ("/", "#/components/schemas/Response"),
+            ("/version", "#/components/schemas/VersionInfo"),
+        ] {
+            let body = &spec["paths"][path]["get"]["responses"]["200"]["content"];
+            assert_eq!(body["application/json"]["schema"]["$ref"], schema, "{path}");
+        }
+        assert!(spec["paths"]["/health"]["get"]["responses"]["200"].is_object());
+    }
+
+    #[test]
+    fn every_schema_reference_resolves() {
+        let spec = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        let text = spec.to_string();
+        let schemas = spec["components"]["schemas"].as_object().unwrap();
+        for reference in text.split("\"#/components/schemas/").skip(1) {
+            let name = &reference[..reference.find('"').unwrap()];
+            assert!(schemas.contains_key(name), "missing schema {name}");
+        }
+    }
+
+    #[test]
+    fn operation_ids_are_unique() {
+        let spec = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        let mut seen = HashSet::new();
+        for item in spec["paths"].as_object().unwrap().values() {
+            for operation in item.as_object().unwrap().values() {
+                let id = operation["operationId"].as_str().unwrap().to_string();
+                assert!(seen.insert(id.clone()), "duplicate operationId {id}");
+            }
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use std::sync::Arc;
+
+use serde::Serialize;
+use utoipa::ToSchema;
+use warp::reply::Response;
+use warp::{Filter, Rejection};
+
+use crate::conditional::{self, Conditions, Tagged};
+use crate::config::Config;
+use crate::hostname::Hostname;
+use crate::reload::LiveConfig;
+use crate::reply;
+
+/// Who is answering. Only a config reload or a hostname refresh changes
+/// it, so it carries an ETag like `/version`.
+#[derive(Serialize, ToSchema)]
+pub(crate) struct PodInfo {
+    hostname: String,
+    message: String,
+    version: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    namespace: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    node: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    variant: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/podinfo",
+    tag = "app",
+    params(
+        ("If-None-Match" = Option<String>, Header, description = "ETag from a previous response"),
+        ("If-Modified-Since" = Option<String>, Header, description = "Last-Modified from a previous response"),
+    ),
+    responses(
+        (status = 200, description = "Pod identity and the current greeting", body = PodInfo),
+        (status = 304, description = "The client's copy is current"),
+    )
+)]
+/// `GET /podinfo`.
+pub fn route(
+    config: &Config,
+    live: LiveConfig,
+    hostname: Hostname,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    let namespace = config.pod_namespace.clone();
+    let node = config.node_name.clone();
+    let variant = config.variant.clone();
+    let tagged = Arc::new(Tagged::default());
+    warp::path("podinfo")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(conditional::conditions())
+        .map(move |conditions: Conditions| {
+            let info = PodInfo {
+                hostname: hostname.get(),
+                message: live
+                    .read()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .greeting
+                    .clone(),
+                version: env!("CARGO_PKG_VERSION"),
+                namespace: namespace.clone(),
+                node: node.clone(),
+                variant: variant.clone(),
+            };
+            tagged.reply(reply::json(&info), &conditions)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::RwLock;
+
+    use warp::http::header::ETAG;
+
+    use super::*;
+    use crate::maintenance::Maintenance;
+    use crate::reload;
+
+    #[tokio::test]
+    async fn etag_changes_after_a_reload() {
+        let config = Config::from_env();
+        let live: LiveConfig = Arc::new(RwLock::new(config.clone()));
+        let route = route(&config, live.clone(), Hostname::from_config(&config));
+
+        let before = warp::test::request().path("/podinfo").reply(&route).await;
+        let etag = before.headers()[ETAG].clone();
+        let res = warp::test::request()
+            .path("/podinfo")
+            .header("if-none-match", etag.clone())
+            .reply(&route)
+            .await;
+        assert_eq!(res.status(), 304);
+        assert!(res.body().is_empty());
+
+        let fresh = Config {
+            greeting: "Reloaded".to_string(),
+            ..config
+        };
+        reload::reload(&live, &Maintenance::new(false), fresh);
+
+        let res = warp::test::request()
+            .path("/podinfo")
+            .header("if-none-match", etag.clone())
+            .reply(&route)
+            .await;
+        assert_eq!(res.status(), 200);
+        assert_ne!(res.headers()[ETAG], etag);
+        let body: serde_json::Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(body["message"], "Reloaded");
+    }
+}
@@ -0,0 +1,141 @@
+use std::sync::Arc;
+
+use serde::Serialize;
+use utoipa::ToSchema;
+use warp::http::StatusCode;
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+use crate::lifecycle::{Lifecycle, Phase};
+use crate::readiness::UpstreamCheck;
+use crate::reply;
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct ProbeStatus {
+    status: Phase,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    upstream_healthy: Option<bool>,
+}
+
+fn probe(status: StatusCode, body: &impl Serialize) -> Response {
+    warp::reply::with_status(reply::json(body), status).into_response()
+}
+
+#[utoipa::path(
+    get,
+    path = "/startupz",
+    tag = "probes",
+    responses(
+        (status = 200, description = "Warmup finished", body = Progress),
+        (status = 503, description = "Still warming up", body = Progress),
+    )
+)]
+/// `GET /startupz`: 503 with warmup progress until warmup ends, then 200
+/// for the rest of the process lifetime (including while draining).
+pub fn startupz(
+    lifecycle: Arc<Lifecycle>,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    warp::path("startupz")
+        .and(warp::path::end())
+        .and(warp::get())
+        .map(move || {
+            let progress = lifecycle.progress();
+            if lifecycle.is_started() {
+                probe(StatusCode::OK, &progress)
+            } else {
+                probe(StatusCode::SERVICE_UNAVAILABLE, &progress)
+            }
+        })
+}
+
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    tag = "probes",
+    responses(
+        (status = 200, description = "Ready for traffic", body = ProbeStatus),
+        (status = 503, description = "Warming up, draining or upstream down", body = ProbeStatus),
+    )
+)]
+/// `GET /readyz`: 200 only in `Ready` (and with a healthy upstream when
+/// one is configured), so pods leave Service endpoints both during warmup
+/// and while draining.
+pub fn readyz(
+    lifecycle: Arc<Lifecycle>,
+    upstream: Option<Arc<UpstreamCheck>>,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    warp::path("readyz")
+        .and(warp::path::end())
+        .and(warp::get())
+        .map(move || {
+            let upstream_healthy = upstream.as_ref().map(|u| u.is_healthy());
+            let status = if lifecycle.is_ready() && upstream_healthy != Some(false) {
+                StatusCode::OK
+            } else {
+                StatusCode::SERVICE_UNAVAILABLE
+            };
+            probe(
+                status,
+                &ProbeStatus {
+                    status: lifecycle.phase(),
+                    upstream_healthy,
+                },
+            )
+        })
+}
+
+#[utoipa::path(
+    get,
+    path = "/healthz",
+    tag = "probes",
+    responses(
+        (status = 200, description = "Alive", body = ProbeStatus),
+        (status = 503, description = "Forced unhealthy via /admin/unhealthy, or memory over HEALTH_MEM_THRESHOLD_PERCENT", body = ProbeStatus),
+    )
+)]
+/// `GET /healthz`: passes in every phase so the kubelet never restarts a
+/// pod that is merely warming up or draining. Only a manual
+/// `/admin/unhealthy` or sustained memory pressure (see `resources`) makes
+/// it fail.
+pub fn healthz(
+    lifecycle: Arc<Lifecycle>,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    warp::path("healthz")
+        .and(warp::path::end())
+        .and(warp::get())
+        .map(move || liveness(&lifecycle))
+}
+
+#[utoipa::path(
+    get,
+    path = "/healthz/live",
+    tag = "probes",
+    responses(
+        (status = 200, description = "Alive", body = ProbeStatus),
+        (status = 503, description = "Forced unhealthy via /admin/unhealthy, or memory over HEALTH_MEM_THRESHOLD_PERCENT", body = ProbeStatus),
+    )
+)]
+/// `GET /healthz/live`: the same check as `/healthz`, and like it stays up
+/// while the pod drains.
+pub fn healthz_live(
+    lifecycle: Arc<Lifecycle>,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    warp::path!("healthz" / "live")
+        .and(warp::get())
+        .map(move || liveness(&lifecycle))
+}
+
+fn liveness(lifecycle: &Lifecycle) -> Response {
+    let status = if lifecycle.is_live() {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    probe(
+        status,
+        &ProbeStatus {
+            status: lifecycle.phase(),
+            upstream_healthy: None,
+        },
+    )
+}
@@ -0,0 +1,206 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use pprof::protos::Message;
+use serde::Deserialize;
+use utoipa::IntoParams;
+use warp::http::header::{HeaderValue, CONTENT_DISPOSITION, CONTENT_TYPE};
+use warp::http::StatusCode;
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+use crate::{admin, filters, reply, ErrorResponse};
+
+/// Go's `net/http/pprof` default, so `go tool pprof` users get what they
+/// expect.
+const DEFAULT_SECONDS: u64 = 30;
+
+const MAX_SECONDS: u64 = 60;
+
+/// Slightly off 100 Hz so sampling does not lock step with timers that
+/// fire on round intervals.
+const FREQUENCY_HZ: i32 = 99;
+
+/// Frames from these libraries are unwound unreliably and only add noise.
+const BLOCKLIST: &[&str] = &["libc", "libgcc", "pthread", "vdso"];
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+enum Format {
+    /// Uncompressed `profile.proto`, readable by `go tool pprof`.
+    #[default]
+    Pprof,
+    Flamegraph,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ProfileQuery {
+    /// How long to sample, 1 to 60; 30 when absent.
+    seconds: Option<u64>,
+    /// `pprof` (default) or `flamegraph` for an SVG.
+    #[param(value_type = Option<String>)]
+    format: Option<Format>,
+}
+
+/// Only one profile at a time: the sampler is process-wide, and two
+/// overlapping runs would each see the other's overhead.
+#[derive(Clone, Default)]
+struct Profiler {
+    running: Arc<AtomicBool>,
+}
+
+/// Clears `running` however the profile ends, including the client
+/// hanging up and hyper dropping the handler mid-sleep.
+struct Running(Arc<AtomicBool>);
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
+impl Profiler {
+    fn start(&self) -> Option<Running> {
+        self.running
+            .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+            .ok()
+            .map(|_| Running(self.running.clone()))
+    }
+
+    async fn profile(&self, query: ProfileQuery) -> Response {
+        let seconds = query.seconds.unwrap_or(DEFAULT_SECONDS);
+        if !(1..=MAX_SECONDS).contains(&seconds) {
+            return error(
+                StatusCode::BAD_REQUEST,
+                format!("seconds must be between 1 and {MAX_SECONDS}"),
+            );
+        }
+        let Some(_running) = self.start() else {
+            return error(
+                StatusCode::CONFLICT,
+                "a profile is already running".to_string(),
+            );
+        };
+        let guard = match pprof::ProfilerGuardBuilder::default()
+            .frequency(FREQUENCY_HZ)
+            .blocklist(BLOCKLIST)
+            .build()
+        {
+            Ok(guard) => guard,
+            Err(err) => return failed(err),
+        };
+        tracing::info!(seconds, "cpu profile started");
+        tokio::time::sleep(Duration::from_secs(seconds)).await;
+        let report = match guard.report().build() {
+            Ok(report) => report,
+            Err(err) => return failed(err),
+        };
+        drop(guard);
+        tracing::info!(seconds, "cpu profile finished");
+
+        let (body, content_type, filename) = match query.format.unwrap_or_default() {
+            Format::Pprof => match report.pprof() {
+                Ok(profile) => (
+                    profile.encode_to_vec(),
+                    "application/octet-stream",
+                    "profile.pb",
+                ),
+                Err(err) => return failed(err),
+            },
+            Format::Flamegraph => {
+                let mut svg = Vec::new();
+                if let Err(err) = report.flamegraph(&mut svg) {
+                    return failed(err);
+                }
+                (svg, "image/svg+xml", "flamegraph.svg")
+            }
+        };
+        let mut response = Response::new(body.into());
+        let headers = response.headers_mut();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+        if let Ok(value) = HeaderValue::from_str(&format!("attachment; filename=\"{filename}\"")) {
+            headers.insert(CONTENT_DISPOSITION, value);
+        }
+        response
+    }
+}
+
+fn failed(err: pprof::Error) -> Response {
+    tracing::warn!(error = %err, "cpu profile failed");
+    error(StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
+}
+
+fn error(status: StatusCode, error: String) -> Response {
+    warp::reply::with_status(reply::json(&ErrorResponse { error }), status).into_response()
+}
+
+#[utoipa::path(
+    get,
+    operation_id = "debug_pprof_profile",
+    path = "/debug/pprof/profile",
+    tag = "admin",
+    security((), ("admin_token" = [])),
+    params(ProfileQuery),
+    responses(
+        (status = 200, description = "CPU profile as profile.proto or a flamegraph SVG", body = String, content_type = "application/octet-stream"),
+        (status = 400, description = "seconds outside 1..=60, or an unknown format", body = ErrorResponse),
+        (status = 401, description = "Missing or wrong X-Admin-Token", body = ErrorResponse),
+        (status = 404, description = "ENABLE_PROFILING is off", body = ErrorResponse),
+        (status = 409, description = "Another profile is still running", body = ErrorResponse),
+    )
+)]
+/// `GET /debug/pprof/profile?seconds=N`: samples every thread's stack for
+/// N seconds and answers with the result, for profiling a pod in place.
+pub fn route(
+    enabled: bool,
+    admin_token: Option<Arc<str>>,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    let profiler = Profiler::default();
+    warp::path!("debug" / "pprof" / "profile")
+        .and(warp::get())
+        .and(filters::enabled(enabled))
+        .and(admin::require_token(admin_token))
+        .and(warp::query::<ProfileQuery>())
+        .then(move |query| {
+            let profiler = profiler.clone();
+            async move { profiler.profile(query).await }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn only_one_profile_runs_at_a_time() {
+        let profiler = Profiler::default();
+        let first = profiler.start();
+        assert!(first.is_some());
+        assert!(profiler.start().is_none());
+        drop(first);
+        assert!(profiler.start().is_some());
+    }
+
+    #[tokio::test]
+    async fn seconds_are_bounded() {
+        let filter = route(true, None);
+        for path in [
+            "/debug/pprof/profile?seconds=0",
+            "/debug/pprof/profile?seconds=61",
+        ] {
+            let response = warp::test::request().path(path).reply(&filter).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        }
+    }
+
+    #[tokio::test]
+    async fn disabled_is_not_found() {
+        let res = warp::test::request()
+            .path("/debug/pprof/profile?seconds=1")
+            .filter(&route(false, None))
+            .await;
+        assert!(res.is_err_and(|err| err.is_not_found()));
+    }
+}
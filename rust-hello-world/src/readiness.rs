@@ -0,0 +1,169 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use backoff::backoff::Backoff;
+use backoff::ExponentialBackoff;
+
+use crate::config::Config;
+
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+enum Outcome {
+    Healthy,
+    /// 4xx: retrying sooner will not help, so no backoff.
+    Permanent(u16),
+    /// Connection refused, timeouts, 5xx: back off before the next try.
+    Transient(String),
+}
+
+/// Polls `UPSTREAM_URL` and gates `/readyz` on the result.
+///
+/// Healthy checks run every `UPSTREAM_CHECK_INTERVAL_SECS`. Transient
+/// failures double the wait up to `UPSTREAM_MAX_BACKOFF_SECS`, and the
+/// first success resets it to the base interval.
+pub struct UpstreamCheck {
+    url: String,
+    interval: Duration,
+    max_backoff: Duration,
+    healthy: AtomicBool,
+}
+
+impl UpstreamCheck {
+    pub fn from_config(config: &Config) -> Option<Arc<Self>> {
+        let url = config.upstream_url.clone()?;
+        Some(Arc::new(Self {
+            url,
+            interval: Duration::from_secs(config.upstream_check_interval_secs),
+            max_backoff: Duration::from_secs(config.upstream_max_backoff_secs),
+            healthy: AtomicBool::new(false),
+        }))
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.healthy.load(Ordering::Acquire)
+    }
+
+    pub fn spawn(self: &Arc<Self>) {
+        let check = self.clone();
+        tokio::spawn(async move { check.run().await });
+    }
+
+    async fn run(&self) {
+        let client = match reqwest::Client::builder().timeout(CHECK_TIMEOUT).build() {
+            Ok(client) => client,
+            Err(err) => {
+                tracing::error!(error = %err, "cannot build upstream check client");
+                return;
+            }
+        };
+        let mut backoff = self.backoff();
+        loop {
+            let outcome = self.check(&client).await;
+            let delay = self.record(outcome, &mut backoff);
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    fn backoff(&self) -> ExponentialBackoff {
+        ExponentialBackoff {
+            initial_interval: self.interval,
+            current_interval: self.interval,
+            max_interval: self.max_backoff.max(self.interval),
+            multiplier: 2.0,
+            randomization_factor: 0.0,
+            max_elapsed_time: None,
+            ..ExponentialBackoff::default()
+        }
+    }
+
+    /// Updates readiness from one check and returns how long to wait
+    /// before the next.
+    fn record(&self, outcome: Outcome, backoff: &mut ExponentialBackoff) -> Duration {
+        match outcome {
+            Outcome::Healthy => {
+                if !self.healthy.swap(true, Ordering::AcqRel) {
+                    tracing::info!(url = %self.url, "upstream is healthy");
+                }
+                backoff.reset();
+                self.interval
+            }
+            Outcome::Permanent(status) => {
+                self.healthy.store(false, Ordering::Release);
+                tracing::warn!(url = %self.url, status, "upstream check failed permanently");
+                self.interval
+            }
+            Outcome::Transient(error) => {
+                self.healthy.store(false, Ordering::Release);
+                let delay = backoff.next_backoff().unwrap_or(self.max_backoff);
+                tracing::warn!(
+                    url = %self.url,
+                    error,
+                    retry_in_secs = delay.as_secs_f64(),
+                    "upstream check failed"
+                );
+                delay
+            }
+        }
+    }
+
+    async fn check(&self, client: &reqwest::Client) -> Outcome {
+        match client.get(&self.url).send().await {
+            Ok(response) => {
+                let status = response.status();
+                if status.is_client_error() {
+                    Outcome::Permanent(status.as_u16())
+                } else if status.is_server_error() {
+                    Outcome::Transient(format!("status {}", status.as_u16()))
+                } else {
+                    Outcome::Healthy
+                }
+            }
+            Err(err) => Outcome::Transient(err.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backs_off_until_healthy_then_resets() {
+        let config = Config {
+            upstream_url: Some("http://upstream".to_string()),
+            upstream_check_interval_secs: 1,
+            upstream_max_backoff_secs: 5,
+            ..Config::from_env()
+        };
+        let check = UpstreamCheck::from_config(&config).unwrap();
+        let mut backoff = check.backoff();
+        let failure = || Outcome::Transient("connection refused".to_string());
+        // Fails four times, then comes up: a checker scripted step by step.
+        let delays: Vec<u64> = (0..4)
+            .map(|_| check.record(failure(), &mut backoff).as_secs())
+            .collect();
+        assert_eq!(
+            delays,
+            [1, 2, 4, 5],
+            "doubles up to UPSTREAM_MAX_BACKOFF_SECS"
+        );
+        assert!(!check.is_healthy());
+
+        let delay = check.record(Outcome::Healthy, &mut backoff);
+        assert_eq!(delay, Duration::from_secs(1));
+        assert!(check.is_healthy());
+
+        assert_eq!(
+            check.record(failure(), &mut backoff),
+            Duration::from_secs(1),
+            "reset"
+        );
+        assert!(!check.is_healthy());
+        assert_eq!(
+            check.record(Outcome::Permanent(404), &mut backoff),
+            Duration::from_secs(1),
+            "4xx does not back off"
+        );
+    }
+}
@@ -0,0 +1,142 @@
+/// Case-insensitive glob patterns (`*` for any run, `?` for one character)
+/// naming values that must never be shown, such as `*TOKEN*`.
+#[derive(Debug)]
+pub struct RedactPatterns {
+    patterns: Vec<Vec<char>>,
+}
+
+impl RedactPatterns {
+    pub fn new<S: AsRef<str>>(patterns: &[S]) -> Self {
+        Self {
+            patterns: patterns
+                .iter()
+                .map(|p| fold(p.as_ref().trim()))
+                .filter(|p| !p.is_empty())
+                .collect(),
+        }
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        let name = fold(name);
+        self.patterns.iter().any(|pattern| glob(pattern, &name))
+    }
+
+    /// `value` as it may be displayed for `name`: unchanged, or a
+    /// placeholder that only gives away its length.
+    pub fn apply(&self, name: &str, value: String) -> String {
+        if self.matches(name) {
+            format!("<redacted:len={}>", value.chars().count())
+        } else {
+            value
+        }
+    }
+}
+
+fn fold(s: &str) -> Vec<char> {
+    s.chars().flat_map(char::to_uppercase).collect()
+}
+
+/// Iterative wildcard match that backtracks to the most recent `*` only,
+/// so it is linear in practice and cannot blow up on adversarial names.
+fn glob(pattern: &[char], text: &[char]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((star_p, star_t)) => {
+                    p = star_p + 1;
+                    t = star_t + 1;
+                    star = Some((star_p, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn defaults() -> RedactPatterns {
+        RedactPatterns::new(&["*TOKEN*", "*SECRET*", "*PASSWORD*", "*KEY*"])
+    }
+
+    #[test]
+    fn matches_anywhere_in_the_name() {
+        let patterns = defaults();
+        for name in [
+            "ADMIN_TOKEN",
+            "TOKEN",
+            "AWS_SECRET_ACCESS_KEY",
+            "DB_PASSWORD_FILE",
+            "KEYCLOAK_URL",
+        ] {
+            assert!(patterns.matches(name), "{name}");
+        }
+        for name in ["PATH", "HOME", "OTEL_EXPORTER_OTLP_ENDPOINT", "TOKE"] {
+            assert!(!patterns.matches(name), "{name}");
+        }
+    }
+
+    #[test]
+    fn is_case_insensitive_both_ways() {
+        assert!(defaults().matches("github_token"));
+        assert!(defaults().matches("Api_Key"));
+        assert!(RedactPatterns::new(&["*secret*"]).matches("MY_SECRET"));
+    }
+
+    #[test]
+    fn anchors_without_stars() {
+        let patterns = RedactPatterns::new(&["TOKEN", "DB_*", "*_PASS"]);
+        assert!(patterns.matches("token"));
+        assert!(!patterns.matches("ADMIN_TOKEN"));
+        assert!(patterns.matches("DB_URL"));
+        assert!(!patterns.matches("MY_DB_URL"));
+        assert!(patterns.matches("SMTP_PASS"));
+        assert!(!patterns.matches("SMTP_PASSWORD"));
+    }
+
+    #[test]
+    fn question_mark_matches_one_character() {
+        let patterns = RedactPatterns::new(&["KEY?"]);
+        assert!(patterns.matches("KEY1"));
+        assert!(!patterns.matches("KEY"));
+        assert!(!patterns.matches("KEY12"));
+    }
+
+    #[test]
+    fn backtracks_past_partial_matches() {
+        let patterns = RedactPatterns::new(&["*TOKEN"]);
+        assert!(patterns.matches("TOKEN_TOKEN"));
+        assert!(patterns.matches("TOKTOKEN"));
+        assert!(!patterns.matches("TOKEN_X"));
+    }
+
+    #[test]
+    fn blank_patterns_match_nothing() {
+        let patterns = RedactPatterns::new(&["", "  "]);
+        assert!(!patterns.matches(""));
+        assert!(!patterns.matches("ANYTHING"));
+    }
+
+    #[test]
+    fn placeholder_reveals_only_length() {
+        let patterns = defaults();
+        assert_eq!(
+            patterns.apply("ADMIN_TOKEN", "hunter2".to_string()),
+            "<redacted:len=7>"
+        );
+        assert_eq!(patterns.apply("HOME", "/root".to_string()), "/root");
+    }
+}
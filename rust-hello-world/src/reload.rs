@@ -0,0 +1,179 @@
+//! `SIGHUP` reload of the few settings that can change without a restart.
+//!
+//! Reloaded: `GREETING`, `MAINTENANCE_MODE` and `LOG_LEVEL`. A running
+//! process never sees its environment change, so these are read from
+//! `CONFIG_FILE` (`KEY=VALUE` lines, such as a mounted ConfigMap) first and
+//! the environment second, both at startup and on every `SIGHUP`.
+//!
+//! Everything else is reload-ignored: ports, `BIND_ADDR`, TLS and mTLS
+//! files, the admin token and every other listener or background-task
+//! setting take effect only on restart. Such keys in `CONFIG_FILE` are
+//! logged as ignored.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, RwLock};
+
+use chrono::{DateTime, Utc};
+
+use tokio::signal::unix::{signal, SignalKind};
+
+use crate::config::{self, Config};
+use crate::logging;
+use crate::maintenance::Maintenance;
+
+const RELOADABLE: &[&str] = &["GREETING", "MAINTENANCE_MODE", "LOG_LEVEL"];
+
+/// The current config. Handlers read it per request; only `SIGHUP`
+/// writes it.
+pub type LiveConfig = Arc<RwLock<Config>>;
+
+/// Unix seconds of the last `load`, at startup or on `SIGHUP`.
+static LOADED_AT: AtomicI64 = AtomicI64::new(0);
+
+/// When the config was last loaded. Responses built from it report this
+/// as their `Last-Modified` at the earliest.
+pub fn loaded_at() -> DateTime<Utc> {
+    DateTime::from_timestamp(LOADED_AT.load(Ordering::Relaxed), 0).unwrap_or_default()
+}
+
+/// Reads `Config` from the environment, then lets `CONFIG_FILE` override
+/// the reloadable settings.
+pub fn load() -> Config {
+    LOADED_AT.store(Utc::now().timestamp(), Ordering::Relaxed);
+    let mut config = Config::from_env();
+    if let Some(path) = config.config_file.clone() {
+        match read_file(&path) {
+            Ok(values) => apply(&mut config, &values),
+            Err(err) => tracing::warn!(path, error = %err, "cannot read CONFIG_FILE"),
+        }
+    }
+    config
+}
+
+fn read_file(path: &str) -> std::io::Result<BTreeMap<String, String>> {
+    Ok(parse(&std::fs::read_to_string(path)?))
+}
+
+/// `KEY=VALUE` per line; blank lines and `#` comments are skipped and
+/// surrounding quotes are dropped.
+fn parse(contents: &str) -> BTreeMap<String, String> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| {
+            let value = value.trim();
+            let value = value
+                .strip_prefix('"')
+                .and_then(|v| v.strip_suffix('"'))
+                .unwrap_or(value);
+            (key.trim().to_string(), value.to_string())
+        })
+        .collect()
+}
+
+fn apply(config: &mut Config, values: &BTreeMap<String, String>) {
+    let ignored: Vec<&str> = values
+        .keys()
+        .map(String::as_str)
+        .filter(|key| !RELOADABLE.contains(key))
+        .collect();
+    if !ignored.is_empty() {
+        tracing::warn!(
+            ?ignored,
+            "CONFIG_FILE only sets GREETING, MAINTENANCE_MODE and LOG_LEVEL"
+        );
+    }
+    if let Some(greeting) = values.get("GREETING").filter(|v| !v.is_empty()) {
+        config.greeting = greeting.clone();
+    }
+    if let Some(maintenance) = values.get("MAINTENANCE_MODE") {
+        config.maintenance_mode = config::parse_flag(maintenance);
+    }
+    if let Some(level) = values.get("LOG_LEVEL").filter(|v| !v.is_empty()) {
+        config.log_level = Some(level.clone());
+    }
+}
+
+/// Re-runs `load` on every `SIGHUP` and applies what changed.
+pub fn listen_for_sighup(live: LiveConfig, maintenance: Arc<Maintenance>) {
+    tokio::spawn(async move {
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(hangup) => hangup,
+            Err(err) => {
+                tracing::error!(error = %err, "cannot install SIGHUP handler");
+                return;
+            }
+        };
+        while hangup.recv().await.is_some() {
+            reload(&live, &maintenance, load());
+        }
+    });
+}
+
+pub(crate) fn reload(live: &LiveConfig, maintenance: &Maintenance, fresh: Config) {
+    let mut config = live.write().unwrap_or_else(|e| e.into_inner());
+    let mut changed = Vec::new();
+
+    if config.greeting != fresh.greeting {
+        config.greeting = fresh.greeting;
+        changed.push("greeting");
+    }
+    // Only a changed value is applied, so a reload for some other setting
+    // does not undo a toggle made through /admin/maintenance.
+    if config.maintenance_mode != fresh.maintenance_mode {
+        config.maintenance_mode = fresh.maintenance_mode;
+        maintenance.set(fresh.maintenance_mode);
+        changed.push("maintenance_mode");
+    }
+    if config.log_level != fresh.log_level {
+        let level = fresh.log_level.as_deref().unwrap_or(logging::DEFAULT_LEVEL);
+        match logging::set_level(level) {
+            Ok(()) => {
+                config.log_level = fresh.log_level;
+                changed.push("log_level");
+            }
+            Err(err) => tracing::warn!(level, error = %err, "keeping the old LOG_LEVEL"),
+        }
+    }
+
+    tracing::info!(?changed, "reloaded config on SIGHUP");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_env_file_lines() {
+        let values =
+            parse("# comment\n\nGREETING=\"Hi there\"\n MAINTENANCE_MODE = on\nnot a pair\n");
+        assert_eq!(values.get("GREETING").map(String::as_str), Some("Hi there"));
+        assert_eq!(
+            values.get("MAINTENANCE_MODE").map(String::as_str),
+            Some("on")
+        );
+        assert_eq!(values.len(), 2);
+    }
+
+    #[test]
+    fn reload_applies_only_the_reloadable_subset() {
+        let live: LiveConfig = Arc::new(RwLock::new(Config::from_env()));
+        let maintenance = Maintenance::new(false);
+        let port = live.read().unwrap().port;
+        let mut fresh = Config::from_env();
+        fresh.greeting = "Howdy".to_string();
+        fresh.maintenance_mode = true;
+        fresh.port = port.wrapping_add(1);
+
+        reload(&live, &maintenance, fresh);
+
+        let config = live.read().unwrap();
+        assert_eq!(config.greeting, "Howdy");
+        assert!(config.maintenance_mode);
+        assert!(maintenance.is_enabled());
+        assert_eq!(config.port, port);
+    }
+}
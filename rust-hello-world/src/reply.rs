@@ -0,0 +1,111 @@
+use serde::Serialize;
+use warp::http::header::{HeaderValue, CONTENT_TYPE};
+use warp::http::StatusCode;
+use warp::hyper::body::Bytes;
+use warp::reply::Response;
+use warp::Reply;
+
+/// JSON reply whose body is serialized into `Bytes` up front, so the
+/// response carries an exact length that the metrics middleware can record
+/// without re-reading the body.
+#[derive(Clone)]
+pub struct JsonBody {
+    body: Result<Bytes, ()>,
+}
+
+pub fn json<T: Serialize>(value: &T) -> JsonBody {
+    JsonBody {
+        body: serde_json::to_vec(value).map(Bytes::from).map_err(|err| {
+            tracing::error!(error = %err, "failed to serialize JSON reply");
+        }),
+    }
+}
+
+impl JsonBody {
+    /// The serialized body, for callers that keep it around.
+    pub fn bytes(&self) -> Option<Bytes> {
+        self.body.as_ref().ok().cloned()
+    }
+}
+
+/// A body that is already serialized JSON.
+impl From<Bytes> for JsonBody {
+    fn from(body: Bytes) -> Self {
+        Self { body: Ok(body) }
+    }
+}
+
+/// A serialized body split around one numeric field, so a cached body can
+/// go out again with a new value there without serializing the rest.
+#[derive(Clone)]
+pub struct Template {
+    prefix: Bytes,
+    suffix: Bytes,
+}
+
+impl Template {
+    /// Splits `body` at the first `"field":0`. Strings in JSON cannot hold
+    /// an unescaped quote, so that is the field itself as long as it comes
+    /// before any nested object with a key of the same name.
+    pub fn new(body: Bytes, field: &str) -> Option<Self> {
+        let needle = format!("\"{}\":0", field);
+        let start = body
+            .windows(needle.len())
+            .position(|window| window == needle.as_bytes())?
+            + needle.len()
+            - 1;
+        Some(Self {
+            prefix: body.slice(..start),
+            suffix: body.slice(start + 1..),
+        })
+    }
+
+    pub fn render(&self, value: u64) -> JsonBody {
+        let value = value.to_string();
+        let mut body = Vec::with_capacity(self.prefix.len() + value.len() + self.suffix.len());
+        body.extend_from_slice(&self.prefix);
+        body.extend_from_slice(value.as_bytes());
+        body.extend_from_slice(&self.suffix);
+        Bytes::from(body).into()
+    }
+}
+
+impl Reply for JsonBody {
+    fn into_response(self) -> Response {
+        match self.body {
+            Ok(body) => {
+                let mut response = Response::new(body.into());
+                response
+                    .headers_mut()
+                    .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+                response
+            }
+            Err(()) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn template_renders_each_value_into_the_same_body() {
+        let body = json(&serde_json::json!({
+            "message": "\"count\":0",
+            "count": 0,
+            "labels": {"count": "0"},
+        }))
+        .bytes()
+        .unwrap();
+        let template = Template::new(body, "count").unwrap();
+        for value in [0, 7, u64::MAX] {
+            let rendered: serde_json::Value =
+                serde_json::from_slice(&template.render(value).bytes().unwrap()).unwrap();
+            assert_eq!(rendered["count"], value);
+            assert_eq!(rendered["message"], "\"count\":0");
+            assert_eq!(rendered["labels"]["count"], "0");
+        }
+        assert!(Template::new(Bytes::from_static(b"{}"), "count").is_none());
+    }
+}
@@ -0,0 +1,397 @@
+//! Container resource usage, sampled in the background and exposed on
+//! `/stats` and `/metrics`.
+//!
+//! Usage comes from cgroup v2 (`memory.current`, `memory.max`, `cpu.stat`)
+//! and falls back to the resident set in `/proc/self/statm`, which has no
+//! limit to compare against.
+//!
+//! With `HEALTH_MEM_THRESHOLD_PERCENT` set, `/healthz` fails once memory
+//! stays above that share of `memory.max` for
+//! `HEALTH_MEM_CONSECUTIVE_SAMPLES` samples in a row, and passes again once
+//! it drops `HEALTH_MEM_HYSTERESIS_PERCENT` points below the threshold. That
+//! needs a cgroup v2 memory limit; without one (cgroup v1, no limit, files
+//! missing) the check is disabled with a single warning at startup.
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use serde::Serialize;
+use utoipa::ToSchema;
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+use crate::config::Config;
+use crate::lifecycle::Lifecycle;
+use crate::metrics::Metrics;
+use crate::reply;
+use crate::shutdown::Shutdown;
+
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+const STATM: &str = "/proc/self/statm";
+
+/// `statm` counts pages. 4 KiB on x86-64 and on the arm64 kernels common
+/// in clusters.
+const PAGE_SIZE: u64 = 4096;
+
+enum Source {
+    /// The cgroup v2 directory holding `memory.current`.
+    CgroupV2(PathBuf),
+    Statm(PathBuf),
+}
+
+#[derive(Clone, Serialize, ToSchema)]
+pub(crate) struct Sample {
+    /// `cgroup_v2` or `statm`.
+    source: &'static str,
+    memory_usage_bytes: u64,
+    memory_limit_bytes: Option<u64>,
+    memory_utilization_percent: Option<f64>,
+    cpu_usage_seconds: Option<f64>,
+    cpu_throttled_periods: Option<u64>,
+    sampled_at: String,
+}
+
+#[derive(Clone, Serialize, ToSchema)]
+pub(crate) struct PressureState {
+    threshold_percent: f64,
+    recover_below_percent: f64,
+    consecutive_over: u32,
+    unhealthy: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct Stats {
+    /// Absent until the first sample, and when nothing can be sampled.
+    sample: Option<Sample>,
+    /// Absent unless memory-pressure liveness is active.
+    memory_pressure: Option<PressureState>,
+}
+
+impl Source {
+    fn detect(cgroup_root: &Path, statm: &Path) -> Option<Self> {
+        if cgroup_root.join("memory.current").is_file() {
+            Some(Source::CgroupV2(cgroup_root.to_path_buf()))
+        } else if statm.is_file() {
+            Some(Source::Statm(statm.to_path_buf()))
+        } else {
+            None
+        }
+    }
+
+    fn memory_limit(&self) -> Option<u64> {
+        match self {
+            Source::CgroupV2(root) => read_limit(root),
+            Source::Statm(_) => None,
+        }
+    }
+
+    fn read(&self) -> std::io::Result<Sample> {
+        let sampled_at = chrono::Utc::now().to_rfc3339();
+        match self {
+            Source::CgroupV2(root) => {
+                let usage = parse_u64(&std::fs::read_to_string(root.join("memory.current"))?)?;
+                let limit = read_limit(root);
+                let cpu = std::fs::read_to_string(root.join("cpu.stat"))
+                    .map(|stat| parse_cpu_stat(&stat))
+                    .unwrap_or_default();
+                Ok(Sample {
+                    source: "cgroup_v2",
+                    memory_usage_bytes: usage,
+                    memory_limit_bytes: limit,
+                    memory_utilization_percent: limit
+                        .filter(|limit| *limit > 0)
+                        .map(|limit| usage as f64 / limit as f64 * 100.0),
+                    cpu_usage_seconds: cpu.usage_usec.map(|usec| usec as f64 / 1e6),
+                    cpu_throttled_periods: cpu.nr_throttled,
+                    sampled_at,
+                })
+            }
+            Source::Statm(path) => {
+                let statm = std::fs::read_to_string(path)?;
+                let resident = statm.split_whitespace().nth(1).unwrap_or_default();
+                Ok(Sample {
+                    source: "statm",
+                    memory_usage_bytes: parse_u64(resident)?.saturating_mul(PAGE_SIZE),
+                    memory_limit_bytes: None,
+                    memory_utilization_percent: None,
+                    cpu_usage_seconds: None,
+                    cpu_throttled_periods: None,
+                    sampled_at,
+                })
+            }
+        }
+    }
+}
+
+/// `memory.max`, which is `max` when the container has no limit.
+fn read_limit(root: &Path) -> Option<u64> {
+    let limit = std::fs::read_to_string(root.join("memory.max")).ok()?;
+    parse_u64(&limit).ok()
+}
+
+fn parse_u64(value: &str) -> std::io::Result<u64> {
+    value
+        .trim()
+        .parse()
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))
+}
+
+#[derive(Default)]
+struct CpuStat {
+    usage_usec: Option<u64>,
+    nr_throttled: Option<u64>,
+}
+
+fn parse_cpu_stat(stat: &str) -> CpuStat {
+    let mut cpu = CpuStat::default();
+    for line in stat.lines() {
+        match line.split_once(' ') {
+            Some(("usage_usec", value)) => cpu.usage_usec = value.trim().parse().ok(),
+            Some(("nr_throttled", value)) => cpu.nr_throttled = value.trim().parse().ok(),
+            _ => {}
+        }
+    }
+    cpu
+}
+
+/// Hysteresis over memory utilization: unhealthy after `required` samples
+/// in a row above `threshold`, healthy again only below `recover_below`.
+struct Pressure {
+    threshold: f64,
+    recover_below: f64,
+    required: u32,
+    consecutive_over: u32,
+    unhealthy: bool,
+}
+
+impl Pressure {
+    fn new(threshold: f64, hysteresis: f64, required: u32) -> Self {
+        Self {
+            threshold,
+            recover_below: threshold - hysteresis.max(0.0),
+            required: required.max(1),
+            consecutive_over: 0,
+            unhealthy: false,
+        }
+    }
+
+    /// Feeds one utilization sample and returns whether liveness should
+    /// fail.
+    fn observe(&mut self, percent: f64) -> bool {
+        if percent > self.threshold {
+            self.consecutive_over = self.consecutive_over.saturating_add(1);
+            if self.consecutive_over >= self.required {
+                self.unhealthy = true;
+            }
+        } else {
+            self.consecutive_over = 0;
+            if percent < self.recover_below {
+                self.unhealthy = false;
+            }
+        }
+        self.unhealthy
+    }
+
+    fn state(&self) -> PressureState {
+        PressureState {
+            threshold_percent: self.threshold,
+            recover_below_percent: self.recover_below,
+            consecutive_over: self.consecutive_over,
+            unhealthy: self.unhealthy,
+        }
+    }
+}
+
+/// The background sampler and its latest result, from
+/// `RESOURCE_SAMPLE_INTERVAL_SECONDS` and the `HEALTH_MEM_*` settings.
+pub struct Resources {
+    source: Option<Source>,
+    interval: Duration,
+    latest: RwLock<Option<Sample>>,
+    pressure: Option<Mutex<Pressure>>,
+    lifecycle: Arc<Lifecycle>,
+    metrics: Metrics,
+}
+
+impl Resources {
+    pub fn from_config(config: &Config, lifecycle: Arc<Lifecycle>, metrics: Metrics) -> Arc<Self> {
+        let source = Source::detect(Path::new(CGROUP_ROOT), Path::new(STATM));
+        if source.is_none() {
+            tracing::warn!(
+                "neither cgroup v2 nor /proc/self/statm found; resource sampling disabled"
+            );
+        }
+        let pressure = config.health_mem_threshold_percent.and_then(|threshold| {
+            if source.as_ref().and_then(Source::memory_limit).is_none() {
+                tracing::warn!(
+                    threshold,
+                    "HEALTH_MEM_THRESHOLD_PERCENT needs a cgroup v2 memory limit; memory-pressure liveness disabled"
+                );
+                return None;
+            }
+            Some(Mutex::new(Pressure::new(
+                threshold,
+                config.health_mem_hysteresis_percent,
+                config.health_mem_consecutive_samples,
+            )))
+        });
+        Arc::new(Self {
+            source,
+            interval: Duration::from_secs(config.resource_sample_interval_seconds.max(1)),
+            latest: RwLock::new(None),
+            pressure,
+            lifecycle,
+            metrics,
+        })
+    }
+
+    /// Samples every `interval` until the next shutdown signal.
+    pub fn spawn(self: &Arc<Self>, shutdown: &Shutdown) {
+        if self.source.is_none() {
+            return;
+        }
+        let resources = self.clone();
+        let stop = shutdown.wait();
+        tokio::spawn(async move {
+            let sample = async {
+                let mut ticker = tokio::time::interval(resources.interval);
+                loop {
+                    ticker.tick().await;
+                    resources.sample();
+                }
+            };
+            tokio::select! {
+                _ = sample => {}
+                _ = stop => {}
+            }
+        });
+    }
+
+    /// A failed read keeps the previous sample; files that went away after
+    /// startup are not worth more than a debug line per interval.
+    fn sample(&self) {
+        let Some(source) = &self.source else {
+            return;
+        };
+        let sample = match source.read() {
+            Ok(sample) => sample,
+            Err(err) => {
+                tracing::debug!(error = %err, "resource sample failed");
+                return;
+            }
+        };
+        self.metrics.set_resource_usage(
+            sample.memory_usage_bytes,
+            sample.memory_limit_bytes,
+            sample.memory_utilization_percent,
+            sample.cpu_usage_seconds,
+            sample.cpu_throttled_periods,
+        );
+        if let (Some(pressure), Some(percent)) = (&self.pressure, sample.memory_utilization_percent)
+        {
+            let mut pressure = pressure.lock().unwrap_or_else(|e| e.into_inner());
+            let was_unhealthy = pressure.unhealthy;
+            let unhealthy = pressure.observe(percent);
+            if unhealthy != was_unhealthy {
+                if unhealthy {
+                    tracing::warn!(
+                        percent,
+                        threshold = pressure.threshold,
+                        "memory pressure, failing liveness"
+                    );
+                } else {
+                    tracing::info!(percent, "memory pressure cleared, liveness passing again");
+                }
+                self.lifecycle.set_memory_pressure(unhealthy);
+            }
+        }
+        *self.latest.write().unwrap_or_else(|e| e.into_inner()) = Some(sample);
+    }
+
+    fn stats(&self) -> Stats {
+        Stats {
+            sample: self
+                .latest
+                .read()
+                .unwrap_or_else(|e| e.into_inner())
+                .clone(),
+            memory_pressure: self
+                .pressure
+                .as_ref()
+                .map(|p| p.lock().unwrap_or_else(|e| e.into_inner()).state()),
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    operation_id = "stats",
+    path = "/stats",
+    tag = "app",
+    responses((status = 200, description = "Latest resource sample and memory-pressure state", body = Stats))
+)]
+/// `GET /stats`: what the resource sampler saw last.
+pub fn route(
+    resources: Arc<Resources>,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    warp::path("stats")
+        .and(warp::path::end())
+        .and(warp::get())
+        .map(move || reply::json(&resources.stats()).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pressure_needs_consecutive_samples_and_recovers_below_the_margin() {
+        let mut pressure = Pressure::new(90.0, 5.0, 3);
+        assert!(!pressure.observe(95.0));
+        assert!(!pressure.observe(95.0));
+        assert!(!pressure.observe(80.0), "a dip restarts the count");
+        for _ in 0..2 {
+            assert!(!pressure.observe(95.0));
+        }
+        assert!(pressure.observe(95.0));
+        assert!(pressure.observe(87.0), "still inside the hysteresis margin");
+        assert!(!pressure.observe(84.0));
+    }
+
+    #[test]
+    fn reads_cgroup_v2_files() {
+        let root = std::env::temp_dir().join(format!("cgroup-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("memory.current"), "250\n").unwrap();
+        std::fs::write(root.join("memory.max"), "1000\n").unwrap();
+        std::fs::write(
+            root.join("cpu.stat"),
+            "usage_usec 1500000\nuser_usec 1000000\nnr_periods 10\nnr_throttled 4\n",
+        )
+        .unwrap();
+
+        let source = Source::detect(&root, Path::new("/nonexistent")).unwrap();
+        let sample = source.read().unwrap();
+        assert_eq!(sample.source, "cgroup_v2");
+        assert_eq!(sample.memory_usage_bytes, 250);
+        assert_eq!(sample.memory_utilization_percent, Some(25.0));
+        assert_eq!(sample.cpu_usage_seconds, Some(1.5));
+        assert_eq!(sample.cpu_throttled_periods, Some(4));
+
+        std::fs::write(root.join("memory.max"), "max\n").unwrap();
+        assert!(source.memory_limit().is_none());
+        assert!(source.read().unwrap().memory_utilization_percent.is_none());
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn falls_back_to_statm() {
+        let source = Source::detect(Path::new("/nonexistent"), Path::new(STATM));
+        if let Some(source) = source {
+            let sample = source.read().unwrap();
+            assert_eq!(sample.source, "statm");
+            assert!(sample.memory_usage_bytes > 0);
+        }
+    }
+}
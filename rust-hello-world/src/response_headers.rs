@@ -0,0 +1,147 @@
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::Instant;
+
+use warp::http::header::{HeaderName, HeaderValue};
+use warp::{Filter, Reply};
+
+use crate::config::Config;
+
+/// Headers stamped onto every response so callers can tell which pod
+/// answered: `X-Served-By`, `X-Pod-Namespace` and `X-Node-Name` from the
+/// Downward API (skipped when unknown), `X-Variant` from `VARIANT` plus
+/// `EXTRA_RESPONSE_HEADERS`.
+pub struct PodHeaders {
+    headers: Vec<(HeaderName, HeaderValue)>,
+}
+
+impl PodHeaders {
+    pub fn new(hostname: &str, config: &Config) -> Self {
+        let mut headers = Vec::new();
+        let mut push = |name: &str, value: &str| match (
+            HeaderName::try_from(name),
+            HeaderValue::try_from(value),
+        ) {
+            (Ok(name), Ok(value)) => headers.push((name, value)),
+            _ => tracing::warn!(name, value, "skipping invalid response header"),
+        };
+
+        push("x-served-by", hostname);
+        if let Some(namespace) = &config.pod_namespace {
+            push("x-pod-namespace", namespace);
+        }
+        if let Some(node) = &config.node_name {
+            push("x-node-name", node);
+        }
+        if let Some(variant) = &config.variant {
+            push("x-variant", variant);
+        }
+        for entry in config
+            .extra_response_headers
+            .iter()
+            .flat_map(|v| v.split(','))
+        {
+            let entry = entry.trim();
+            if entry.is_empty() {
+                continue;
+            }
+            match entry.split_once('=') {
+                Some((name, value)) => push(name.trim(), value.trim()),
+                None => tracing::warn!(entry, "EXTRA_RESPONSE_HEADERS entry is not Name=Value"),
+            }
+        }
+
+        Self { headers }
+    }
+}
+
+/// Adds the pod headers and `X-Response-Time-Ms` to whatever `filter`
+/// produced. Headers a handler already set are left untouched.
+pub fn wrap<F, R>(
+    filter: F,
+    pod_headers: Arc<PodHeaders>,
+) -> impl Filter<Extract = (warp::reply::Response,), Error = Infallible> + Clone
+where
+    F: Filter<Extract = (R,), Error = Infallible> + Clone + Send + Sync + 'static,
+    R: Reply,
+{
+    warp::any()
+        .map(Instant::now)
+        .and(filter)
+        .map(move |start: Instant, reply: R| {
+            let mut response = reply.into_response();
+            let headers = response.headers_mut();
+            for (name, value) in &pod_headers.headers {
+                if !headers.contains_key(name) {
+                    headers.insert(name.clone(), value.clone());
+                }
+            }
+            let elapsed_ms = format!("{:.3}", start.elapsed().as_secs_f64() * 1000.0);
+            if let Ok(value) = HeaderValue::from_str(&elapsed_ms) {
+                headers
+                    .entry(HeaderName::from_static("x-response-time-ms"))
+                    .or_insert(value);
+            }
+            response
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(extra: &str) -> Config {
+        Config {
+            pod_namespace: Some("demo".to_string()),
+            node_name: None,
+            variant: None,
+            extra_response_headers: Some(extra.to_string()),
+            ..Config::from_env()
+        }
+    }
+
+    fn names(pod_headers: &PodHeaders) -> Vec<(&str, &str)> {
+        pod_headers
+            .headers
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.to_str().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn malformed_extra_headers_are_skipped() {
+        let pod_headers = PodHeaders::new(
+            "pod-1",
+            &config(" X-Team = core ,no-equals, bad name=1,X-Bad-Value=a\nb,,X-Empty=,X-Ok=yes"),
+        );
+        assert_eq!(
+            names(&pod_headers),
+            [
+                ("x-served-by", "pod-1"),
+                ("x-pod-namespace", "demo"),
+                ("x-team", "core"),
+                ("x-empty", ""),
+                ("x-ok", "yes"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn headers_the_handler_set_win() {
+        let pod_headers = Arc::new(PodHeaders::new("pod-1", &config("X-Team=core")));
+        let handler = warp::any().map(|| {
+            warp::reply::with_header(
+                warp::reply::with_header("ok", "x-served-by", "handler"),
+                "x-team",
+                "handler-team",
+            )
+        });
+        let res = warp::test::request()
+            .reply(&wrap(handler, pod_headers))
+            .await;
+        assert_eq!(res.headers()["x-served-by"], "handler");
+        assert_eq!(res.headers()["x-team"], "handler-team");
+        assert_eq!(res.headers()["x-pod-namespace"], "demo");
+        assert!(res.headers().contains_key("x-response-time-ms"));
+    }
+}
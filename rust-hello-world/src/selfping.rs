@@ -0,0 +1,254 @@
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use backoff::backoff::Backoff;
+use backoff::ExponentialBackoff;
+use serde::Serialize;
+use utoipa::ToSchema;
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+use crate::config::Config;
+use crate::metrics::Metrics;
+use crate::shutdown::Shutdown;
+use crate::{filters, reply};
+
+const PING_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Periodic GETs against `SELF_PING_TARGETS`, so the cluster network is
+/// exercised (and graphed) even when nobody is calling us.
+///
+/// Every target runs on its own schedule: `SELF_PING_INTERVAL_SECONDS`
+/// while it answers, doubling up to `SELF_PING_MAX_BACKOFF_SECONDS` while
+/// it keeps failing, so one dead service neither gets hammered nor delays
+/// the others.
+pub struct SelfPing {
+    targets: Vec<Target>,
+    interval: Duration,
+    max_backoff: Duration,
+    metrics: Metrics,
+}
+
+struct Target {
+    url: String,
+    last: RwLock<Option<PingResult>>,
+}
+
+#[derive(Clone, Serialize, ToSchema)]
+pub(crate) struct PingResult {
+    target: String,
+    ok: bool,
+    status: Option<u16>,
+    latency_ms: f64,
+    error: Option<String>,
+    consecutive_failures: u32,
+    checked_at: String,
+}
+
+impl SelfPing {
+    pub fn from_config(config: &Config, metrics: Metrics) -> Arc<Self> {
+        Arc::new(Self {
+            targets: config
+                .self_ping_targets
+                .iter()
+                .map(|url| Target {
+                    url: url.clone(),
+                    last: RwLock::new(None),
+                })
+                .collect(),
+            interval: Duration::from_secs(config.self_ping_interval_seconds.max(1)),
+            max_backoff: Duration::from_secs(config.self_ping_max_backoff_seconds),
+            metrics,
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.targets.is_empty()
+    }
+
+    /// Starts one pinger per target; each stops at the next shutdown
+    /// signal instead of holding the process open.
+    pub fn spawn(self: &Arc<Self>, shutdown: &Shutdown) {
+        if !self.is_enabled() {
+            return;
+        }
+        let client = match reqwest::Client::builder().timeout(PING_TIMEOUT).build() {
+            Ok(client) => client,
+            Err(err) => {
+                tracing::error!(error = %err, "cannot build self-ping client");
+                return;
+            }
+        };
+        for index in 0..self.targets.len() {
+            let pinger = self.clone();
+            let client = client.clone();
+            let stop = shutdown.wait();
+            tokio::spawn(async move {
+                tokio::select! {
+                    _ = pinger.run(index, &client) => {}
+                    _ = stop => {}
+                }
+            });
+        }
+    }
+
+    async fn run(&self, index: usize, client: &reqwest::Client) {
+        let target = &self.targets[index];
+        let mut backoff = ExponentialBackoff {
+            initial_interval: self.interval,
+            current_interval: self.interval,
+            max_interval: self.max_backoff.max(self.interval),
+            multiplier: 2.0,
+            randomization_factor: 0.0,
+            max_elapsed_time: None,
+            ..ExponentialBackoff::default()
+        };
+        let mut consecutive_failures = 0u32;
+
+        loop {
+            let result = ping(client, &target.url, consecutive_failures, &self.metrics).await;
+            let delay = if result.ok {
+                if consecutive_failures > 0 {
+                    tracing::info!(
+                        target_url = %target.url,
+                        after_failures = consecutive_failures,
+                        "self-ping target recovered"
+                    );
+                }
+                consecutive_failures = 0;
+                backoff.reset();
+                self.interval
+            } else {
+                consecutive_failures = result.consecutive_failures;
+                let delay = backoff.next_backoff().unwrap_or(self.max_backoff);
+                tracing::warn!(
+                    target_url = %target.url,
+                    status = result.status,
+                    error = result.error.as_deref(),
+                    consecutive_failures,
+                    retry_in_secs = delay.as_secs_f64(),
+                    "self-ping failed"
+                );
+                delay
+            };
+            *target.last.write().unwrap_or_else(|e| e.into_inner()) = Some(result);
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    /// The latest result per target, in `SELF_PING_TARGETS` order. Targets
+    /// not pinged yet are left out.
+    fn results(&self) -> Vec<PingResult> {
+        self.targets
+            .iter()
+            .filter_map(|target| {
+                target
+                    .last
+                    .read()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .clone()
+            })
+            .collect()
+    }
+}
+
+async fn ping(
+    client: &reqwest::Client,
+    url: &str,
+    failures_so_far: u32,
+    metrics: &Metrics,
+) -> PingResult {
+    let start = Instant::now();
+    let outcome = client.get(url).send().await;
+    let elapsed = start.elapsed();
+    let (status, error) = match outcome {
+        Ok(response)
+            if response.status().is_client_error() || response.status().is_server_error() =>
+        {
+            let status = response.status().as_u16();
+            (Some(status), Some(format!("status {}", status)))
+        }
+        Ok(response) => (Some(response.status().as_u16()), None),
+        Err(err) => (None, Some(err.to_string())),
+    };
+    metrics.record_self_ping(
+        url,
+        status
+            .map_or_else(|| "error".to_string(), |s| s.to_string())
+            .as_str(),
+        elapsed,
+    );
+    let ok = error.is_none();
+    PingResult {
+        target: url.to_string(),
+        ok,
+        status,
+        latency_ms: elapsed.as_secs_f64() * 1000.0,
+        error,
+        consecutive_failures: if ok { 0 } else { failures_so_far + 1 },
+        checked_at: chrono::Utc::now().to_rfc3339(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    operation_id = "selfping",
+    path = "/selfping",
+    tag = "app",
+    responses(
+        (status = 200, description = "Latest result for every target pinged so far", body = [PingResult]),
+        (status = 404, description = "SELF_PING_TARGETS is not set", body = ErrorResponse),
+    )
+)]
+/// `GET /selfping`: what the background pinger saw last. Absent unless
+/// `SELF_PING_TARGETS` is set.
+pub fn route(
+    self_ping: Arc<SelfPing>,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    warp::path("selfping")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(filters::enabled(self_ping.is_enabled()))
+        .map(move || reply::json(&self_ping.results()).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lifecycle::Lifecycle;
+
+    #[tokio::test]
+    async fn failing_target_counts_consecutive_failures() {
+        let closed = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", closed.local_addr().unwrap());
+        drop(closed);
+
+        let self_ping = Arc::new(SelfPing {
+            targets: vec![Target {
+                url: url.clone(),
+                last: RwLock::new(None),
+            }],
+            interval: Duration::from_millis(10),
+            max_backoff: Duration::from_millis(10),
+            metrics: Metrics::new(None, Arc::default()),
+        });
+        let shutdown = Shutdown::new(Lifecycle::new(Duration::ZERO));
+        self_ping.spawn(&shutdown);
+
+        let mut results = Vec::new();
+        for _ in 0..100 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            results = self_ping.results();
+            if results.first().is_some_and(|r| r.consecutive_failures >= 2) {
+                break;
+            }
+        }
+        let result = &results[0];
+        assert_eq!(result.target, url);
+        assert!(!result.ok);
+        assert!(result.status.is_none());
+        assert!(result.consecutive_failures >= 2);
+
+        shutdown.trigger();
+    }
+}
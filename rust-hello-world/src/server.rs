@@ -0,0 +1,579 @@
+use std::convert::Infallible;
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::os::unix::fs::FileTypeExt;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use socket2::{Domain, Protocol, Socket, TcpKeepalive, Type};
+use tokio::net::{TcpListener, UnixListener, UnixStream};
+use tokio::sync::{mpsc, Semaphore};
+use tokio_rustls::TlsAcceptor;
+use tokio_stream::wrappers::{ReceiverStream, UnixListenerStream};
+use warp::http::header::{HeaderName, HeaderValue, RETRY_AFTER};
+use warp::http::StatusCode;
+use warp::hyper::body::HttpBody;
+use warp::hyper::server::accept::{self, Accept};
+use warp::hyper::server::conn::{AddrIncoming, AddrStream};
+use warp::hyper::service::{make_service_fn, service_fn, Service};
+use warp::hyper::{Body, Request};
+use warp::reply::Response;
+use warp::{Filter, Reply};
+
+use crate::config::Config;
+use crate::mirror::Mirror;
+use crate::tls::TlsConnection;
+use crate::{reply, ErrorResponse};
+
+/// Requests only wait for other in-flight ones, so the next second is a
+/// reasonable time to come back.
+const RETRY_AFTER_SECS: &str = "1";
+
+/// A client that has not finished the TLS handshake by then is dropped, so
+/// idle sockets cannot pile up.
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Pause between attempts while `PORT_RETRY_SECONDS` waits for a port.
+const BIND_RETRY_DELAY: Duration = Duration::from_millis(250);
+
+/// Subject CN of the verified mTLS client certificate, for handlers that
+/// authorize on it.
+pub const CLIENT_CERT_SUBJECT: HeaderName = HeaderName::from_static("x-client-cert-subject");
+
+/// Socket address of the connection a request arrived on. Inserted as a
+/// request extension because warp cannot see it once we drive hyper
+/// ourselves.
+#[derive(Clone, Copy, Debug)]
+pub struct PeerAddr(pub SocketAddr);
+
+/// TCP keep-alive settings from `TCP_KEEPALIVE_SECS`,
+/// `TCP_KEEPALIVE_INTERVAL_SECS` and `TCP_KEEPALIVE_RETRIES`. When none are
+/// set the OS defaults are left alone.
+#[derive(Debug, Default)]
+pub struct Keepalive {
+    time: Option<Duration>,
+    interval: Option<Duration>,
+    retries: Option<u32>,
+}
+
+impl Keepalive {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            time: config.tcp_keepalive_secs.map(Duration::from_secs),
+            interval: config.tcp_keepalive_interval_secs.map(Duration::from_secs),
+            retries: config.tcp_keepalive_retries,
+        }
+    }
+
+    fn is_set(&self) -> bool {
+        self.time.is_some() || self.interval.is_some() || self.retries.is_some()
+    }
+
+    fn to_socket2(&self) -> TcpKeepalive {
+        let mut keepalive = TcpKeepalive::new();
+        if let Some(time) = self.time {
+            keepalive = keepalive.with_time(time);
+        }
+        if let Some(interval) = self.interval {
+            keepalive = keepalive.with_interval(interval);
+        }
+        if let Some(retries) = self.retries {
+            keepalive = keepalive.with_retries(retries);
+        }
+        keepalive
+    }
+}
+
+/// Turns `BIND_ADDR` into a socket address. Accepts a bare IP (`0.0.0.0`,
+/// `::`, `[::1]`), which gets `port`, or a full socket address
+/// (`[::1]:9000`), whose own port wins. Unset means `0.0.0.0`.
+pub fn bind_address(bind_addr: Option<&str>, port: u16) -> Result<SocketAddr, String> {
+    let value = match bind_addr.map(str::trim) {
+        None | Some("") => return Ok(SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), port)),
+        Some(value) => value,
+    };
+    if let Ok(addr) = value.parse::<SocketAddr>() {
+        return Ok(addr);
+    }
+    let ip = value
+        .strip_prefix('[')
+        .and_then(|v| v.strip_suffix(']'))
+        .unwrap_or(value);
+    ip.parse::<IpAddr>()
+        .map(|ip| SocketAddr::new(ip, port))
+        .map_err(|_| {
+            format!(
+                "BIND_ADDR {:?} is neither an IP address nor an IP:port socket address",
+                value
+            )
+        })
+}
+
+/// Binds the listening socket by hand so keep-alive can be configured on it.
+/// Accepted connections inherit the listener's keep-alive options on Linux;
+/// hyper re-applies them per connection for other platforms. IPv6 sockets
+/// are made dual-stack where the OS allows, so `::` also takes IPv4 clients.
+pub fn bind_tcp(addr: SocketAddr, keepalive: &Keepalive) -> io::Result<AddrIncoming> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    socket.set_reuse_address(true)?;
+    if addr.is_ipv6() {
+        if let Err(err) = socket.set_only_v6(false) {
+            tracing::warn!(error = %err, "could not enable dual-stack, serving IPv6 only");
+        }
+    }
+    if keepalive.is_set() {
+        socket.set_tcp_keepalive(&keepalive.to_socket2())?;
+        tracing::info!(?keepalive, "TCP keep-alive configured");
+    }
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+
+    let listener = TcpListener::from_std(socket.into())?;
+    let mut incoming = AddrIncoming::from_listener(listener).map_err(io::Error::other)?;
+    if keepalive.is_set() {
+        incoming
+            .set_keepalive(keepalive.time)
+            .set_keepalive_interval(keepalive.interval)
+            .set_keepalive_retries(keepalive.retries);
+    }
+    Ok(incoming)
+}
+
+/// `bind_tcp`, retried for up to `retry_for` while the address is in use,
+/// as it can be for a moment when a previous instance is still releasing it
+/// during a fast restart. Any other error is returned at once.
+pub async fn bind_tcp_retrying(
+    addr: SocketAddr,
+    keepalive: &Keepalive,
+    retry_for: Duration,
+) -> io::Result<AddrIncoming> {
+    let deadline = Instant::now() + retry_for;
+    loop {
+        match bind_tcp(addr, keepalive) {
+            Err(err) if err.kind() == io::ErrorKind::AddrInUse && Instant::now() < deadline => {
+                tracing::warn!(%addr, "address in use, retrying");
+                tokio::time::sleep(BIND_RETRY_DELAY).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Per-listener request handling that has to run outside warp: it needs
+/// the raw body or must answer before any filter runs.
+#[derive(Clone, Default)]
+pub struct ServeOptions {
+    /// Replay sampled requests against `MIRROR_TARGET`. Set by `main`,
+    /// which shares it with `/mirror`.
+    pub mirror: Option<Arc<Mirror>>,
+    /// `MAX_CONNECTIONS`: in-flight requests beyond this get a 503.
+    pub concurrency: Option<Arc<Semaphore>>,
+    /// Bodies are only buffered for the mirror up to `MAX_BODY_BYTES`.
+    pub max_mirror_body_bytes: u64,
+    /// Terminate TLS (and verify client certificates) on this listener.
+    pub tls: Option<TlsAcceptor>,
+}
+
+impl ServeOptions {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            mirror: None,
+            concurrency: config
+                .max_connections
+                .filter(|max| *max > 0)
+                .map(|max| Arc::new(Semaphore::new(max))),
+            max_mirror_body_bytes: config.max_body_bytes,
+            tls: None,
+        }
+    }
+}
+
+/// Serves `filter` on `incoming`, tagging each request with its `PeerAddr`,
+/// until `shutdown` resolves and in-flight requests finish.
+pub async fn serve<F>(
+    incoming: AddrIncoming,
+    filter: F,
+    options: ServeOptions,
+    shutdown: impl Future<Output = ()>,
+) where
+    F: Filter + Clone + Send + Sync + 'static,
+    F::Extract: Reply,
+{
+    if let Some(acceptor) = options.tls.clone() {
+        return serve_tls(incoming, acceptor, filter, options, shutdown).await;
+    }
+
+    let service = warp::service(filter);
+    let make_service = make_service_fn(move |conn: &AddrStream| {
+        let peer = PeerAddr(conn.remote_addr());
+        let service = service.clone();
+        let options = options.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |mut req: Request<Body>| {
+                tag(&mut req, Some(peer), None);
+                dispatch(service.clone(), options.clone(), req)
+            }))
+        }
+    });
+
+    if let Err(err) = warp::hyper::Server::builder(incoming)
+        .serve(make_service)
+        .with_graceful_shutdown(shutdown)
+        .await
+    {
+        tracing::error!(error = %err, "server error");
+    }
+}
+
+/// Like `serve`, but every connection goes through a TLS handshake first.
+/// Handshakes run on their own tasks so one slow client never holds up
+/// accepting the next; only finished handshakes reach hyper.
+async fn serve_tls<F>(
+    mut incoming: AddrIncoming,
+    acceptor: TlsAcceptor,
+    filter: F,
+    options: ServeOptions,
+    shutdown: impl Future<Output = ()>,
+) where
+    F: Filter + Clone + Send + Sync + 'static,
+    F::Extract: Reply,
+{
+    let (tx, rx) = mpsc::channel::<io::Result<TlsConnection>>(64);
+    tokio::spawn(async move {
+        loop {
+            let accept = std::future::poll_fn(|cx| Pin::new(&mut incoming).poll_accept(cx));
+            let stream = tokio::select! {
+                accepted = accept => {
+                    match accepted {
+                        Some(Ok(stream)) => stream,
+                        Some(Err(err)) => {
+                            tracing::debug!(error = %err, "accept failed");
+                            continue;
+                        }
+                        None => break,
+                    }
+                }
+                // hyper dropped the receiver: the server has shut down.
+                _ = tx.closed() => break,
+            };
+            let acceptor = acceptor.clone();
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                let peer = stream.remote_addr();
+                match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                    Ok(Ok(stream)) => {
+                        let _ = tx.send(Ok(TlsConnection::new(stream))).await;
+                    }
+                    Ok(Err(err)) => tracing::debug!(%peer, error = %err, "TLS handshake failed"),
+                    Err(_) => tracing::debug!(%peer, "TLS handshake timed out"),
+                }
+            });
+        }
+    });
+
+    let service = warp::service(filter);
+    let make_service = make_service_fn(move |conn: &TlsConnection| {
+        let peer = PeerAddr(conn.remote_addr());
+        let subject = conn.client_subject.as_deref().and_then(|subject| {
+            HeaderValue::from_str(subject)
+                .inspect_err(|_| {
+                    tracing::debug!(subject, "client certificate CN is not a valid header")
+                })
+                .ok()
+        });
+        let service = service.clone();
+        let options = options.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |mut req: Request<Body>| {
+                tag(&mut req, Some(peer), subject.as_ref());
+                dispatch(service.clone(), options.clone(), req)
+            }))
+        }
+    });
+
+    let incoming = accept::from_stream(ReceiverStream::new(rx));
+    if let Err(err) = warp::hyper::Server::builder(incoming)
+        .serve(make_service)
+        .with_graceful_shutdown(shutdown)
+        .await
+    {
+        tracing::error!(error = %err, "server error");
+    }
+}
+
+/// Attaches what the connection knows about the caller. A client-sent
+/// `X-Client-Cert-Subject` is always dropped first, so only a verified
+/// certificate can set it.
+fn tag(req: &mut Request<Body>, peer: Option<PeerAddr>, client_subject: Option<&HeaderValue>) {
+    let headers = req.headers_mut();
+    headers.remove(CLIENT_CERT_SUBJECT);
+    if let Some(subject) = client_subject {
+        headers.insert(CLIENT_CERT_SUBJECT, subject.clone());
+    }
+    if let Some(peer) = peer {
+        req.extensions_mut().insert(peer);
+    }
+}
+
+/// Hands `req` to `service` once a concurrency permit is available. The
+/// permit lives until the response is produced and is released by drop, so
+/// errors and panics give it back too. Mirroring needs the body twice, so
+/// only then is it buffered up front.
+async fn dispatch<S>(
+    mut service: S,
+    options: ServeOptions,
+    req: Request<Body>,
+) -> Result<Response, Infallible>
+where
+    S: Service<Request<Body>, Response = Response, Error = Infallible>,
+{
+    let _permit = match &options.concurrency {
+        Some(semaphore) => match semaphore.clone().try_acquire_owned() {
+            Ok(permit) => Some(permit),
+            Err(_) => return Ok(overloaded()),
+        },
+        None => None,
+    };
+
+    // Chunked or oversized bodies are not mirrored, so a large upload is
+    // never held in memory just to be replayed.
+    let mirror = options.mirror.filter(|mirror| {
+        mirror.wants(req.uri(), req.headers())
+            && req
+                .body()
+                .size_hint()
+                .upper()
+                .is_some_and(|len| len <= options.max_mirror_body_bytes)
+    });
+    let Some(mirror) = mirror else {
+        return service.call(req).await;
+    };
+
+    let (parts, body) = req.into_parts();
+    let body = match warp::hyper::body::to_bytes(body).await {
+        Ok(body) => body,
+        Err(err) => {
+            tracing::debug!(error = %err, "failed to read request body");
+            return Ok(StatusCode::BAD_REQUEST.into_response());
+        }
+    };
+    let (method, uri, headers) = (
+        parts.method.clone(),
+        parts.uri.clone(),
+        parts.headers.clone(),
+    );
+    let response = service
+        .call(Request::from_parts(parts, Body::from(body.clone())))
+        .await?;
+    mirror.send(&method, &uri, &headers, body, response.status());
+    Ok(response)
+}
+
+fn overloaded() -> Response {
+    tracing::debug!("MAX_CONNECTIONS reached, rejecting request");
+    let mut response = warp::reply::with_status(
+        reply::json(&ErrorResponse {
+            error: "too many concurrent requests".to_string(),
+        }),
+        StatusCode::SERVICE_UNAVAILABLE,
+    )
+    .into_response();
+    response
+        .headers_mut()
+        .insert(RETRY_AFTER, HeaderValue::from_static(RETRY_AFTER_SECS));
+    response
+}
+
+/// Binds a Unix domain socket at `path`, replacing a stale socket left
+/// behind by a previous run. Anything other than a socket at `path` is
+/// treated as an error rather than deleted.
+pub fn bind_unix(path: &Path) -> io::Result<UnixListener> {
+    match std::fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_socket() => {
+            tracing::info!(path = %path.display(), "removing stale unix socket");
+            std::fs::remove_file(path)?;
+        }
+        Ok(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} exists and is not a socket", path.display()),
+            ))
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err),
+    }
+    UnixListener::bind(path)
+}
+
+/// Serves `filter` on a Unix socket. There is no peer address here, so
+/// client IP resolution falls back to proxy headers only.
+pub async fn serve_unix<F>(
+    listener: UnixListener,
+    filter: F,
+    options: ServeOptions,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) where
+    F: Filter + Clone + Send + Sync + 'static,
+    F::Extract: Reply,
+{
+    let service = warp::service(filter);
+    let make_service = make_service_fn(move |_: &UnixStream| {
+        let service = service.clone();
+        let options = options.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |mut req: Request<Body>| {
+                tag(&mut req, None, None);
+                dispatch(service.clone(), options.clone(), req)
+            }))
+        }
+    });
+
+    let incoming = accept::from_stream(UnixListenerStream::new(listener));
+    if let Err(err) = warp::hyper::Server::builder(incoming)
+        .serve(make_service)
+        .with_graceful_shutdown(shutdown)
+        .await
+    {
+        tracing::error!(error = %err, "server error");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bind_address_defaults_to_all_ipv4() {
+        assert_eq!(
+            bind_address(None, 8080).unwrap(),
+            "0.0.0.0:8080".parse().unwrap()
+        );
+        assert_eq!(
+            bind_address(Some(" "), 8080).unwrap(),
+            "0.0.0.0:8080".parse().unwrap()
+        );
+    }
+
+    #[test]
+    fn bind_address_takes_port_for_bare_ips() {
+        assert_eq!(
+            bind_address(Some("127.0.0.1"), 80).unwrap(),
+            "127.0.0.1:80".parse().unwrap()
+        );
+        assert_eq!(
+            bind_address(Some("::"), 80).unwrap(),
+            "[::]:80".parse().unwrap()
+        );
+        assert_eq!(
+            bind_address(Some("[::1]"), 80).unwrap(),
+            "[::1]:80".parse().unwrap()
+        );
+    }
+
+    #[test]
+    fn bind_address_port_overrides_port() {
+        assert_eq!(
+            bind_address(Some("[::1]:9000"), 80).unwrap(),
+            "[::1]:9000".parse().unwrap()
+        );
+        assert_eq!(
+            bind_address(Some("10.0.0.1:0"), 80).unwrap(),
+            "10.0.0.1:0".parse().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn bind_retries_until_the_port_is_released() {
+        let taken = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = taken.local_addr().unwrap();
+        let keepalive = Keepalive::default();
+
+        let err = bind_tcp_retrying(addr, &keepalive, Duration::ZERO)
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            drop(taken);
+        });
+        let incoming = bind_tcp_retrying(addr, &keepalive, Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert_eq!(incoming.local_addr(), addr);
+    }
+
+    /// Serves `filter` with `MAX_CONNECTIONS=max` on an ephemeral port.
+    fn serve_capped<F>(filter: F, max: usize) -> (String, Arc<Semaphore>)
+    where
+        F: Filter + Clone + Send + Sync + 'static,
+        F::Extract: Reply,
+    {
+        let incoming = bind_tcp("127.0.0.1:0".parse().unwrap(), &Keepalive::default()).unwrap();
+        let url = format!("http://{}", incoming.local_addr());
+        let semaphore = Arc::new(Semaphore::new(max));
+        let options = ServeOptions {
+            concurrency: Some(semaphore.clone()),
+            ..ServeOptions::default()
+        };
+        tokio::spawn(serve(incoming, filter, options, std::future::pending()));
+        (url, semaphore)
+    }
+
+    #[tokio::test]
+    async fn permits_are_released_after_every_request() {
+        const MAX: usize = 3;
+        let (url, semaphore) = serve_capped(warp::path("ok").map(|| "ok"), MAX);
+        let client = reqwest::Client::new();
+        for n in 0..=MAX {
+            let res = client.get(format!("{}/ok", url)).send().await.unwrap();
+            assert_eq!(res.status(), 200, "request {}", n + 1);
+            // Rejected routes hand their permit back too.
+            let res = client.get(format!("{}/missing", url)).send().await.unwrap();
+            assert_eq!(res.status(), 404);
+        }
+        assert_eq!(semaphore.available_permits(), MAX);
+    }
+
+    #[tokio::test]
+    async fn requests_past_the_cap_get_a_503_until_one_finishes() {
+        let release = Arc::new(tokio::sync::Notify::new());
+        let slow = {
+            let release = release.clone();
+            warp::path("slow").then(move || {
+                let release = release.clone();
+                async move {
+                    release.notified().await;
+                    "done"
+                }
+            })
+        };
+        let (url, semaphore) = serve_capped(slow.or(warp::path("ok").map(|| "ok")), 1);
+        let client = reqwest::Client::new();
+
+        let held = tokio::spawn(client.get(format!("{}/slow", url)).send());
+        while semaphore.available_permits() > 0 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        let res = client.get(format!("{}/ok", url)).send().await.unwrap();
+        assert_eq!(res.status(), 503);
+        assert_eq!(res.headers()["retry-after"], RETRY_AFTER_SECS);
+
+        release.notify_one();
+        assert_eq!(held.await.unwrap().unwrap().status(), 200);
+        let res = client.get(format!("{}/ok", url)).send().await.unwrap();
+        assert_eq!(res.status(), 200);
+    }
+
+    #[test]
+    fn bind_address_rejects_garbage() {
+        for value in ["localhost", "::1:9000:x", "1.2.3.4:port", "[::1"] {
+            assert!(bind_address(Some(value), 80).is_err(), "{value}");
+        }
+    }
+}
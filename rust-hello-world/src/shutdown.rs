@@ -0,0 +1,125 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+use crate::admin;
+use crate::lifecycle::Lifecycle;
+use crate::reply;
+
+const DEFAULT_DRAIN_DELAY_MS: u64 = 5_000;
+const MAX_DRAIN_DELAY_MS: u64 = 30_000;
+
+/// Process-wide shutdown trigger. Every server holds a `wait()` future and
+/// stops accepting new connections once anything calls `trigger()`, which
+/// also moves the lifecycle to `Draining` so readiness fails first.
+#[derive(Clone)]
+pub struct Shutdown {
+    tx: Arc<watch::Sender<bool>>,
+    lifecycle: Arc<Lifecycle>,
+}
+
+impl Shutdown {
+    pub fn new(lifecycle: Arc<Lifecycle>) -> Self {
+        let (tx, _) = watch::channel(false);
+        Self {
+            tx: Arc::new(tx),
+            lifecycle,
+        }
+    }
+
+    pub fn trigger(&self) {
+        self.lifecycle.begin_draining();
+        self.tx.send_replace(true);
+    }
+
+    /// Drains immediately but keeps serving for `delay` so load balancers
+    /// notice the failing readiness probe before connections are refused.
+    pub fn schedule(&self, delay: Duration) {
+        self.lifecycle.begin_draining();
+        let shutdown = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            tracing::info!("drain delay elapsed, shutting down");
+            shutdown.trigger();
+        });
+    }
+
+    pub fn wait(&self) -> impl Future<Output = ()> + Send + 'static {
+        let mut rx = self.tx.subscribe();
+        async move {
+            let _ = rx.wait_for(|triggered| *triggered).await;
+        }
+    }
+
+    /// Triggers shutdown on SIGTERM (what the kubelet sends) or Ctrl-C.
+    pub fn listen_for_signals(&self) {
+        let shutdown = self.clone();
+        tokio::spawn(async move {
+            let mut sigterm = signal(SignalKind::terminate()).expect("install SIGTERM handler");
+            tokio::select! {
+                _ = sigterm.recv() => tracing::info!("received SIGTERM, shutting down"),
+                _ = tokio::signal::ctrl_c() => tracing::info!("received Ctrl-C, shutting down"),
+            }
+            shutdown.trigger();
+        });
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ShutdownQuery {
+    /// Milliseconds to keep serving while draining (default 5000, max 30000).
+    delay_ms: Option<u64>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct ShutdownResponse {
+    draining: bool,
+    delay_ms: u64,
+}
+
+#[utoipa::path(
+    post,
+    operation_id = "shutdown",
+    path = "/shutdown",
+    tag = "admin",
+    params(ShutdownQuery),
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "Draining started", body = ShutdownResponse),
+        (status = 401, description = "Missing or wrong X-Admin-Token", body = ErrorResponse),
+    )
+)]
+/// `POST /shutdown?delay_ms=N`: the same drain as SIGTERM, on demand.
+/// Replies before the delay starts. Absent unless `ADMIN_TOKEN` is set.
+pub fn route(
+    shutdown: Shutdown,
+    admin_token: Option<Arc<str>>,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    warp::path("shutdown")
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(admin::protected(admin_token))
+        .and(warp::query::<ShutdownQuery>())
+        .map(move |query: ShutdownQuery| {
+            let delay_ms = query
+                .delay_ms
+                .unwrap_or(DEFAULT_DRAIN_DELAY_MS)
+                .min(MAX_DRAIN_DELAY_MS);
+            tracing::warn!(delay_ms, "shutdown requested via /shutdown");
+            shutdown.schedule(Duration::from_millis(delay_ms));
+            reply::json(&ShutdownResponse {
+                draining: true,
+                delay_ms,
+            })
+            .into_response()
+        })
+}
@@ -0,0 +1,92 @@
+//! Everything that outlives a single request and is shared between the
+//! route tree and the background tasks. Per-route helpers that only read
+//! the config are built by `routes` instead.
+
+use std::sync::atomic::AtomicU64;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use crate::access_log::Sampling;
+use crate::config::Config;
+use crate::drain::Drain;
+use crate::hostname::Hostname;
+use crate::kv::Store;
+use crate::lifecycle::Lifecycle;
+use crate::maintenance::Maintenance;
+use crate::metrics::Metrics;
+use crate::metrics_push::MetricsPush;
+use crate::mirror::Mirror;
+use crate::readiness::UpstreamCheck;
+use crate::reload::{self, LiveConfig};
+use crate::resources::Resources;
+use crate::selfping::SelfPing;
+use crate::shutdown::Shutdown;
+
+#[derive(Clone)]
+pub struct State {
+    pub(crate) hostname: Hostname,
+    pub(crate) live: LiveConfig,
+    pub(crate) lifecycle: Arc<Lifecycle>,
+    pub shutdown: Shutdown,
+    pub(crate) maintenance: Arc<Maintenance>,
+    pub(crate) drain: Arc<Drain>,
+    pub(crate) upstream: Option<Arc<UpstreamCheck>>,
+    pub(crate) hello_requests: Arc<AtomicU64>,
+    /// This state's own registry, labelled with its `VARIANT`, so two
+    /// states in one process never share a metric.
+    pub(crate) metrics: Metrics,
+    /// `MAX_JSON_DEPTH`, for every route that decodes a JSON body.
+    pub(crate) max_json_depth: usize,
+    pub(crate) kv: Arc<Store>,
+    pub(crate) resources: Arc<Resources>,
+    pub(crate) sampling: Arc<Sampling>,
+    pub self_ping: Arc<SelfPing>,
+    pub mirror: Arc<Mirror>,
+    pub metrics_push: Option<Arc<MetricsPush>>,
+}
+
+impl State {
+    /// Builds the state without starting anything, so tests can drive the
+    /// routes without signal handlers or timers.
+    pub fn new(config: &Config) -> Self {
+        let hello_requests = Arc::new(AtomicU64::new(0));
+        let metrics = Metrics::new(config.variant.as_deref(), hello_requests.clone());
+        let hostname = Hostname::from_config(config);
+        let lifecycle = Lifecycle::new(Duration::from_secs(config.warmup_seconds));
+        Self {
+            live: Arc::new(RwLock::new(config.clone())),
+            shutdown: Shutdown::new(lifecycle.clone()),
+            maintenance: Maintenance::new(config.maintenance_mode),
+            drain: Arc::new(Drain::default()),
+            upstream: UpstreamCheck::from_config(config),
+            hello_requests,
+            kv: Store::from_config(config, hostname.clone(), metrics.clone()),
+            resources: Resources::from_config(config, lifecycle.clone(), metrics.clone()),
+            sampling: Sampling::from_config(config, metrics.clone()),
+            self_ping: SelfPing::from_config(config, metrics.clone()),
+            mirror: Mirror::from_config(config, metrics.clone()),
+            metrics_push: MetricsPush::from_config(config, hostname.clone(), metrics.clone()),
+            metrics,
+            max_json_depth: config.max_json_depth,
+            hostname,
+            lifecycle,
+        }
+    }
+
+    /// Starts the warmup timer, signal handlers, background sweeps and the
+    /// metrics push. Self-ping is left to the caller, which starts it once
+    /// the listener is bound, and so is the final metrics push.
+    pub fn spawn(&self) {
+        self.lifecycle.spawn_warmup();
+        self.shutdown.listen_for_signals();
+        reload::listen_for_sighup(self.live.clone(), self.maintenance.clone());
+        if let Some(upstream) = &self.upstream {
+            upstream.spawn();
+        }
+        self.kv.spawn(&self.shutdown);
+        self.resources.spawn(&self.shutdown);
+        if let Some(push) = &self.metrics_push {
+            push.spawn(&self.shutdown);
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use std::sync::Arc;
+
+use serde::Serialize;
+use tokio::runtime::Handle;
+use utoipa::ToSchema;
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+use crate::{admin, filters, reply};
+
+/// Tokio keeps no per-task registry outside `tokio_unstable` builds, so
+/// unlike Go's goroutine dump this is runtime-wide counters only.
+const NOTE: &str = "approximation: runtime-wide counters sampled without a pause, \
+                    not a per-task dump; worker_local_queue_depths needs a \
+                    tokio_unstable build";
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct TaskStats {
+    num_workers: usize,
+    num_alive_tasks: usize,
+    injection_queue_depth: usize,
+    /// `null` unless built with `RUSTFLAGS="--cfg tokio_unstable"`.
+    worker_local_queue_depths: Option<Vec<usize>>,
+    workers: Vec<WorkerStats>,
+    timestamp: String,
+    note: &'static str,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct WorkerStats {
+    busy_seconds: f64,
+    park_count: u64,
+}
+
+impl TaskStats {
+    fn sample() -> Self {
+        let metrics = Handle::current().metrics();
+        let num_workers = metrics.num_workers();
+
+        #[cfg(tokio_unstable)]
+        let worker_local_queue_depths = Some(
+            (0..num_workers)
+                .map(|worker| metrics.worker_local_queue_depth(worker))
+                .collect(),
+        );
+        #[cfg(not(tokio_unstable))]
+        let worker_local_queue_depths = None;
+
+        Self {
+            num_workers,
+            num_alive_tasks: metrics.num_alive_tasks(),
+            injection_queue_depth: metrics.global_queue_depth(),
+            worker_local_queue_depths,
+            workers: (0..num_workers)
+                .map(|worker| WorkerStats {
+                    busy_seconds: metrics.worker_total_busy_duration(worker).as_secs_f64(),
+                    park_count: metrics.worker_park_count(worker),
+                })
+                .collect(),
+            timestamp: chrono::Utc::now().to_rfc3339(),
+            note: NOTE,
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    operation_id = "debug_tasks",
+    path = "/debug/tasks",
+    tag = "admin",
+    security((), ("admin_token" = [])),
+    responses(
+        (status = 200, description = "Tokio runtime counters; an approximation, not a task dump", body = TaskStats),
+        (status = 401, description = "Missing or wrong X-Admin-Token", body = ErrorResponse),
+        (status = 404, description = "ENABLE_DEBUG_ENDPOINTS is off", body = ErrorResponse),
+    )
+)]
+/// `GET /debug/tasks`: what the Tokio runtime is doing right now, for
+/// telling a hang (alive tasks piling up, queues growing, workers never
+/// parking) from an idle process.
+pub fn route(
+    enabled: bool,
+    admin_token: Option<Arc<str>>,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    warp::path!("debug" / "tasks")
+        .and(warp::get())
+        .and(filters::enabled(enabled))
+        .and(admin::require_token(admin_token))
+        .map(|| reply::json(&TaskStats::sample()).into_response())
+}
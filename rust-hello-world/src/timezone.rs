@@ -0,0 +1,144 @@
+//! The zone `timestamp_local` is rendered in on `/` and `/hello/{name}`:
+//! `?tz=` when the request has one, else `TIMEZONE`. `timestamp` and
+//! `timestamp_utc` stay UTC whatever the zone.
+
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use warp::{Filter, Rejection};
+
+use crate::config::Config;
+
+/// Listed in the 400 for an unknown `?tz=`.
+const EXAMPLES: &[&str] = &[
+    "UTC",
+    "Asia/Jakarta",
+    "Asia/Kolkata",
+    "Europe/London",
+    "America/New_York",
+];
+
+/// `TIMEZONE`, or UTC when it is unset or not an IANA name.
+pub fn from_config(config: &Config) -> Tz {
+    let Some(name) = config.timezone.as_deref() else {
+        return Tz::UTC;
+    };
+    name.parse().unwrap_or_else(|_| {
+        tracing::warn!(timezone = name, "unknown TIMEZONE, using UTC");
+        Tz::UTC
+    })
+}
+
+/// `at` in `zone`, as RFC 3339 with the zone's offset at that instant.
+pub fn local(at: DateTime<Utc>, zone: Tz) -> String {
+    at.with_timezone(&zone).to_rfc3339()
+}
+
+/// Rejection for a `?tz=` that is not an IANA name. Serialized as-is into
+/// the 400 response.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct UnknownTimezone {
+    error: String,
+    /// A few names that are accepted.
+    examples: Vec<&'static str>,
+}
+
+impl warp::reject::Reject for UnknownTimezone {}
+
+#[derive(Deserialize)]
+struct TzQuery {
+    tz: Option<String>,
+}
+
+/// The `?tz=` zone, `None` when the query has none.
+pub fn query() -> impl Filter<Extract = (Option<Tz>,), Error = Rejection> + Clone {
+    warp::query::<TzQuery>().and_then(|query: TzQuery| async move {
+        match query.tz.filter(|name| !name.is_empty()) {
+            None => Ok(None),
+            Some(name) => name.parse().map(Some).map_err(|_| {
+                warp::reject::custom(UnknownTimezone {
+                    error: format!("unknown timezone {:?}", name),
+                    examples: EXAMPLES.to_vec(),
+                })
+            }),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn at(rfc3339: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(rfc3339)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    #[test]
+    fn renders_fractional_offsets() {
+        let noon = Utc.with_ymd_and_hms(2026, 1, 15, 12, 0, 0).unwrap();
+        assert_eq!(
+            local(noon, chrono_tz::Asia::Kolkata),
+            "2026-01-15T17:30:00+05:30"
+        );
+        assert_eq!(
+            local(noon, chrono_tz::Asia::Kathmandu),
+            "2026-01-15T17:45:00+05:45"
+        );
+        assert_eq!(
+            local(noon, chrono_tz::Asia::Jakarta),
+            "2026-01-15T19:00:00+07:00"
+        );
+    }
+
+    #[test]
+    fn follows_daylight_saving() {
+        let zone = chrono_tz::America::New_York;
+        assert_eq!(
+            local(at("2026-01-15T12:00:00Z"), zone),
+            "2026-01-15T07:00:00-05:00"
+        );
+        assert_eq!(
+            local(at("2026-07-15T12:00:00Z"), zone),
+            "2026-07-15T08:00:00-04:00"
+        );
+    }
+
+    #[test]
+    fn unknown_timezone_env_falls_back_to_utc() {
+        let mut config = Config::from_env();
+        config.timezone = Some("Mars/Olympus_Mons".to_string());
+        assert_eq!(from_config(&config), Tz::UTC);
+        config.timezone = Some("Asia/Jakarta".to_string());
+        assert_eq!(from_config(&config), chrono_tz::Asia::Jakarta);
+    }
+
+    #[tokio::test]
+    async fn query_rejects_unknown_names() {
+        let filter = query();
+        let zone = warp::test::request()
+            .path("/?tz=Europe/Berlin")
+            .filter(&filter)
+            .await
+            .unwrap();
+        assert_eq!(zone, Some(chrono_tz::Europe::Berlin));
+        let none = warp::test::request()
+            .path("/")
+            .filter(&filter)
+            .await
+            .unwrap();
+        assert_eq!(none, None);
+
+        let rejection = warp::test::request()
+            .path("/?tz=Nowhere")
+            .filter(&filter)
+            .await
+            .unwrap_err();
+        let unknown = rejection.find::<UnknownTimezone>().unwrap();
+        assert!(unknown.examples.contains(&"Asia/Jakarta"));
+    }
+}
@@ -0,0 +1,242 @@
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConfig};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+use warp::hyper::server::conn::AddrStream;
+
+use crate::config::Config;
+
+/// Builds the acceptor for the application listener from `TLS_CERT_PATH`
+/// and `TLS_KEY_PATH`. With `MTLS_CA_CERT_PATH` as well, every client must
+/// present a certificate signed by that CA; anyone else gets a TLS alert
+/// during the handshake and never reaches HTTP.
+///
+/// `Ok(None)` means plain HTTP. Half a configuration is an error rather
+/// than a silent downgrade.
+pub fn acceptor(config: &Config) -> Result<Option<TlsAcceptor>, String> {
+    let (cert_path, key_path) = match (&config.tls_cert_path, &config.tls_key_path) {
+        (Some(cert), Some(key)) => (cert, key),
+        (None, None) if config.mtls_ca_cert_path.is_none() => return Ok(None),
+        _ => return Err(
+            "TLS needs both TLS_CERT_PATH and TLS_KEY_PATH (MTLS_CA_CERT_PATH requires them too)"
+                .to_string(),
+        ),
+    };
+
+    let certs = CertificateDer::pem_file_iter(cert_path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|err| format!("cannot read TLS_CERT_PATH {}: {}", cert_path, err))?;
+    let key = PrivateKeyDer::from_pem_file(key_path)
+        .map_err(|err| format!("cannot read TLS_KEY_PATH {}: {}", key_path, err))?;
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(|err| err.to_string())?;
+    let builder = match &config.mtls_ca_cert_path {
+        None => builder.with_no_client_auth(),
+        Some(ca_path) => {
+            let mut roots = RootCertStore::empty();
+            for cert in CertificateDer::pem_file_iter(ca_path)
+                .map_err(|err| format!("cannot read MTLS_CA_CERT_PATH {}: {}", ca_path, err))?
+            {
+                let cert = cert
+                    .map_err(|err| format!("cannot read MTLS_CA_CERT_PATH {}: {}", ca_path, err))?;
+                roots
+                    .add(cert)
+                    .map_err(|err| format!("invalid CA in MTLS_CA_CERT_PATH: {}", err))?;
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                .build()
+                .map_err(|err| format!("cannot use MTLS_CA_CERT_PATH: {}", err))?;
+            builder.with_client_cert_verifier(verifier)
+        }
+    };
+    let mut server = builder
+        .with_single_cert(certs, key)
+        .map_err(|err| format!("TLS_CERT_PATH and TLS_KEY_PATH do not match: {}", err))?;
+    server.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+    tracing::info!(
+        mtls = config.mtls_ca_cert_path.is_some(),
+        "TLS enabled on the application listener"
+    );
+    Ok(Some(TlsAcceptor::from(Arc::new(server))))
+}
+
+/// An accepted TLS connection together with what the handshake proved
+/// about the client.
+pub struct TlsConnection {
+    stream: TlsStream<AddrStream>,
+    /// Subject CN of the verified client certificate, under mTLS.
+    pub client_subject: Option<String>,
+}
+
+impl TlsConnection {
+    pub fn new(stream: TlsStream<AddrStream>) -> Self {
+        let client_subject = stream
+            .get_ref()
+            .1
+            .peer_certificates()
+            .and_then(|chain| chain.first())
+            .and_then(|cert| subject_common_name(cert));
+        Self {
+            stream,
+            client_subject,
+        }
+    }
+
+    pub fn remote_addr(&self) -> std::net::SocketAddr {
+        self.stream.get_ref().0.remote_addr()
+    }
+}
+
+impl AsyncRead for TlsConnection {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for TlsConnection {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
+
+/// DER encoding of the commonName attribute type, 2.5.4.3.
+const COMMON_NAME_OID: &[u8] = &[0x55, 0x04, 0x03];
+
+/// Reads the subject CN out of an X.509 certificate. rustls has already
+/// verified the certificate, so this only walks the structure:
+/// Certificate → tbsCertificate → subject → RDNs → commonName.
+fn subject_common_name(cert: &[u8]) -> Option<String> {
+    let (_, certificate, _) = read_tlv(cert)?;
+    let (_, tbs, _) = read_tlv(certificate)?;
+    let mut fields = tbs;
+    // The version field is optional and context-tagged [0].
+    let (tag, _, rest) = read_tlv(fields)?;
+    if tag == 0xa0 {
+        fields = rest;
+    }
+    // serialNumber, signature, issuer, validity, then subject.
+    for _ in 0..4 {
+        fields = read_tlv(fields)?.2;
+    }
+    let (_, mut rdns, _) = read_tlv(fields)?;
+    while !rdns.is_empty() {
+        let (_, set, rest) = read_tlv(rdns)?;
+        rdns = rest;
+        let mut attributes = set;
+        while !attributes.is_empty() {
+            let (_, attribute, rest) = read_tlv(attributes)?;
+            attributes = rest;
+            let (_, oid, value) = read_tlv(attribute)?;
+            if oid == COMMON_NAME_OID {
+                let (_, name, _) = read_tlv(value)?;
+                return String::from_utf8(name.to_vec()).ok();
+            }
+        }
+    }
+    None
+}
+
+/// Splits one DER tag-length-value off `input`: `(tag, value, rest)`.
+fn read_tlv(input: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, input) = input.split_first()?;
+    let (&first, mut input) = input.split_first()?;
+    let len = if first < 0x80 {
+        usize::from(first)
+    } else {
+        let octets = usize::from(first & 0x7f);
+        if octets == 0 || octets > std::mem::size_of::<usize>() || input.len() < octets {
+            return None;
+        }
+        let (len, rest) = input.split_at(octets);
+        input = rest;
+        len.iter()
+            .fold(0usize, |acc, b| (acc << 8) | usize::from(*b))
+    };
+    if input.len() < len {
+        return None;
+    }
+    let (value, rest) = input.split_at(len);
+    Some((tag, value, rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// v1 certificate (no version field) for `/O=home-k8s/CN=billing-service`.
+    const CLIENT_CERT: &str = "\
+-----BEGIN CERTIFICATE-----
+MIIBOjCB4AIUVp4Ox08woqOAt+mk9kAQJQUbMqMwCgYIKoZIzj0EAwIwEjEQMA4G
+A1UEAwwHdGVzdC1jYTAeFw0yNjEwMTQxOTIxNThaFw0zNjEwMTExOTIxNThaMC0x
+ETAPBgNVBAoMCGhvbWUtazhzMRgwFgYDVQQDDA9iaWxsaW5nLXNlcnZpY2UwWTAT
+BgcqhkjOPQIBBggqhkjOPQMBBwNCAARr5GWlAVTbhw/MMQNuDz/FcO+lvHGiFNvi
+GT1JBR1tKz37mU1v8PD2cWGImg8jG/8PE5cJ/qbFFscLmJkV71XwMAoGCCqGSM49
+BAMCA0kAMEYCIQC1Rc207nFY8FYrtiTP10wnhZzJONv1+C1p/P8/oEVXAwIhAPWY
+w3k/Vt1wqHWYuDwPnC44gAOSfMCSZad2uDWC4iVD
+-----END CERTIFICATE-----";
+
+    /// v3 certificate with extensions for `/CN=localhost`.
+    const SERVER_CERT: &str = "\
+-----BEGIN CERTIFICATE-----
+MIIBhzCCASygAwIBAgIUVp4Ox08woqOAt+mk9kAQJQUbMqIwCgYIKoZIzj0EAwIw
+EjEQMA4GA1UEAwwHdGVzdC1jYTAeFw0yNjEwMTQxOTIxNThaFw0zNjEwMTExOTIx
+NThaMBQxEjAQBgNVBAMMCWxvY2FsaG9zdDBZMBMGByqGSM49AgEGCCqGSM49AwEH
+A0IABH12Wqkr65ca+ZvPjgZp+QYxveCKpRrpFkHu2YaFQJ60d7n4+wIEITqhv6b0
+j+ZneaCOoWkqXXB021eQzW5IoUSjXjBcMBoGA1UdEQQTMBGCCWxvY2FsaG9zdIcE
+fwAAATAdBgNVHQ4EFgQUtaurWAvOEJAZxvzoDtauTSdQkNYwHwYDVR0jBBgwFoAU
+mbblC/KuQ+U3xKs2AbVbJoHPzmYwCgYIKoZIzj0EAwIDSQAwRgIhAObhmA2T+Dym
+ARd6oIWZD3Imga3BmVaH5QceJVUuv/84AiEAyUjQKeZar8uNQKtmFcGErQ7fLiOS
+P7oCYkr11uQQbF0=
+-----END CERTIFICATE-----";
+
+    fn der(pem: &str) -> CertificateDer<'static> {
+        CertificateDer::from_pem_slice(pem.as_bytes()).expect("test certificate")
+    }
+
+    #[test]
+    fn common_name_is_read_from_the_subject_not_the_issuer() {
+        assert_eq!(
+            subject_common_name(&der(CLIENT_CERT)).as_deref(),
+            Some("billing-service")
+        );
+        assert_eq!(
+            subject_common_name(&der(SERVER_CERT)).as_deref(),
+            Some("localhost")
+        );
+    }
+
+    #[test]
+    fn truncated_input_is_not_a_name() {
+        let cert = der(CLIENT_CERT);
+        assert_eq!(subject_common_name(&cert[..cert.len() / 2]), None);
+        assert_eq!(read_tlv(&[0x30, 0x82, 0x01]), None);
+    }
+}
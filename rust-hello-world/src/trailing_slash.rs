@@ -0,0 +1,103 @@
+//! `TRAILING_SLASH`: what a path with one trailing slash, such as
+//! `/health/`, means. `/` itself is never affected.
+//!
+//! `strict`, the default, answers 404. `lenient` lets it through to
+//! warp's `path::end()`, which accepts a single trailing slash, and
+//! `redirect` sends a 308 to the path without it, which clients follow with
+//! the same method and body.
+
+use warp::path::FullPath;
+use warp::reject::Reject;
+use warp::{Filter, Rejection};
+
+use crate::config::Config;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrailingSlash {
+    Lenient,
+    Strict,
+    Redirect,
+}
+
+impl TrailingSlash {
+    pub fn from_config(config: &Config) -> Self {
+        match config.trailing_slash.as_deref() {
+            None | Some("strict") => Self::Strict,
+            Some("lenient") => Self::Lenient,
+            Some("redirect") => Self::Redirect,
+            Some(other) => {
+                tracing::warn!(value = other, "unknown TRAILING_SLASH, using strict");
+                Self::Strict
+            }
+        }
+    }
+}
+
+/// Rejection for `redirect`, carrying the `Location` to send.
+#[derive(Debug)]
+pub struct Redirect(pub String);
+
+impl Reject for Redirect {}
+
+/// `path` without its single trailing slash, if it has exactly one and is
+/// not `/`.
+fn canonical(path: &str) -> Option<&str> {
+    path.strip_suffix('/')
+        .filter(|path| !path.is_empty() && !path.ends_with('/'))
+}
+
+/// Goes in front of every route. Rejects with not found (`strict`) or
+/// `Redirect` (`redirect`) when the path has a trailing slash; `lenient`
+/// lets everything through.
+pub fn check(mode: TrailingSlash) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::path::full()
+        .and(
+            warp::query::raw()
+                .map(Some)
+                .or(warp::any().map(|| None))
+                .unify(),
+        )
+        .and_then(move |path: FullPath, query: Option<String>| async move {
+            let Some(canonical) = canonical(path.as_str()) else {
+                return Ok(());
+            };
+            match mode {
+                TrailingSlash::Lenient => Ok(()),
+                TrailingSlash::Strict => Err(warp::reject::not_found()),
+                TrailingSlash::Redirect => {
+                    let location = match query {
+                        Some(query) => format!("{}?{}", canonical, query),
+                        None => canonical.to_string(),
+                    };
+                    Err(warp::reject::custom(Redirect(location)))
+                }
+            }
+        })
+        .untuple_one()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_a_single_trailing_slash_is_stripped() {
+        assert_eq!(canonical("/health/"), Some("/health"));
+        assert_eq!(canonical("/kv/a/"), Some("/kv/a"));
+        assert_eq!(canonical("/health"), None);
+        assert_eq!(canonical("/"), None);
+        assert_eq!(canonical("/health//"), None);
+    }
+
+    #[tokio::test]
+    async fn redirect_keeps_the_query() {
+        let rejection = warp::test::request()
+            .path("/kv/?limit=5")
+            .filter(&check(TrailingSlash::Redirect))
+            .await
+            .err()
+            .unwrap();
+        let Redirect(location) = rejection.find().unwrap();
+        assert_eq!(location, "/kv?limit=5");
+    }
+}
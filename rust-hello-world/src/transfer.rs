@@ -0,0 +1,277 @@
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::Instant;
+
+use rand::rngs::StdRng;
+use rand::{RngCore, SeedableRng};
+use serde::Serialize;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio_stream::{Stream, StreamExt};
+use warp::http::header::{HeaderValue, CONTENT_LENGTH, CONTENT_TYPE, RETRY_AFTER};
+use warp::http::StatusCode;
+use warp::hyper::body::{Body, Buf, Bytes};
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+use crate::body_limit::PayloadTooLarge;
+use crate::config::Config;
+use crate::{reply, upload, ErrorResponse};
+
+const MB: u64 = 1024 * 1024;
+
+/// Generated download data goes out in pieces of this size, so memory per
+/// transfer stays flat however many megabytes were asked for.
+const CHUNK_BYTES: usize = 64 * 1024;
+
+/// Transfers finish in seconds, so the next one is a reasonable time to
+/// come back.
+const RETRY_AFTER_SECS: &str = "5";
+
+/// Limits for `GET /download/{mb}` and the streaming `POST /upload`, from
+/// `MAX_TRANSFER_MB` and `MAX_CONCURRENT_TRANSFERS`. Both directions share
+/// the same slots.
+pub struct Transfers {
+    pub max_bytes: u64,
+    slots: Arc<Semaphore>,
+}
+
+#[derive(Serialize)]
+struct UploadStats {
+    bytes_received: u64,
+    elapsed_ms: f64,
+    throughput_mb_per_sec: f64,
+}
+
+impl Transfers {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            max_bytes: config.max_transfer_mb.saturating_mul(MB),
+            slots: Arc::new(Semaphore::new(config.max_concurrent_transfers)),
+        }
+    }
+
+    fn slot(&self) -> Option<OwnedSemaphorePermit> {
+        self.slots.clone().try_acquire_owned().ok()
+    }
+
+    fn download(&self, megabytes: u64) -> Response {
+        let len = match megabytes
+            .checked_mul(MB)
+            .filter(|len| *len <= self.max_bytes)
+        {
+            Some(len) => len,
+            None => {
+                return error(
+                    StatusCode::BAD_REQUEST,
+                    format!("at most {} MB per download", self.max_bytes / MB),
+                )
+            }
+        };
+        let Some(permit) = self.slot() else {
+            return busy();
+        };
+        let chunks = RandomChunks {
+            rng: StdRng::from_entropy(),
+            remaining: len,
+            _permit: permit,
+        };
+        let mut response = Response::new(Body::wrap_stream(tokio_stream::iter(chunks)));
+        let headers = response.headers_mut();
+        headers.insert(CONTENT_LENGTH, HeaderValue::from(len));
+        headers.insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("application/octet-stream"),
+        );
+        response
+    }
+
+    /// Counts and drops the body chunk by chunk, so nothing beyond the
+    /// chunk in hand is ever held.
+    async fn sink<S, B>(&self, body: S) -> Result<Response, Rejection>
+    where
+        S: Stream<Item = Result<B, warp::Error>>,
+        B: Buf,
+    {
+        let Some(_permit) = self.slot() else {
+            return Ok(busy());
+        };
+        let start = Instant::now();
+        let mut body = std::pin::pin!(body);
+        let mut received = 0u64;
+        while let Some(chunk) = body.next().await {
+            let chunk = chunk.map_err(|err| {
+                tracing::debug!(error = %err, "upload stream ended early");
+                warp::reject::custom(PayloadAborted)
+            })?;
+            received += chunk.remaining() as u64;
+            if received > self.max_bytes {
+                return Err(warp::reject::custom(PayloadTooLarge::new(self.max_bytes)));
+            }
+        }
+        let elapsed = start.elapsed().as_secs_f64();
+        let throughput = if elapsed > 0.0 {
+            received as f64 / MB as f64 / elapsed
+        } else {
+            0.0
+        };
+        Ok(reply::json(&UploadStats {
+            bytes_received: received,
+            elapsed_ms: elapsed * 1000.0,
+            throughput_mb_per_sec: throughput,
+        })
+        .into_response())
+    }
+}
+
+/// The client went away or broke framing mid-upload. Nobody is left to
+/// read the answer, so it falls through to the generic 500.
+#[derive(Debug)]
+struct PayloadAborted;
+
+impl warp::reject::Reject for PayloadAborted {}
+
+/// Incompressible filler, `CHUNK_BYTES` at a time. Holds the transfer slot
+/// until hyper drops the body, whether it finished or the client left.
+struct RandomChunks {
+    rng: StdRng,
+    remaining: u64,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl Iterator for RandomChunks {
+    type Item = Result<Bytes, Infallible>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let len = self.remaining.min(CHUNK_BYTES as u64) as usize;
+        let mut chunk = vec![0; len];
+        self.rng.fill_bytes(&mut chunk);
+        self.remaining -= len as u64;
+        Some(Ok(Bytes::from(chunk)))
+    }
+}
+
+fn busy() -> Response {
+    let mut response = error(
+        StatusCode::TOO_MANY_REQUESTS,
+        "too many concurrent transfers".to_string(),
+    );
+    response
+        .headers_mut()
+        .insert(RETRY_AFTER, HeaderValue::from_static(RETRY_AFTER_SECS));
+    response
+}
+
+fn error(status: StatusCode, error: String) -> Response {
+    warp::reply::with_status(reply::json(&ErrorResponse { error }), status).into_response()
+}
+
+#[utoipa::path(
+    get,
+    operation_id = "download",
+    path = "/download/{megabytes}",
+    tag = "app",
+    params(("megabytes" = u64, Path, description = "MiB of random data to send")),
+    responses(
+        (status = 200, description = "Pseudo-random bytes", body = String, content_type = "application/octet-stream"),
+        (status = 400, description = "More than MAX_TRANSFER_MB", body = ErrorResponse),
+        (status = 429, description = "MAX_CONCURRENT_TRANSFERS already running", body = ErrorResponse),
+    )
+)]
+/// `GET /download/{megabytes}`: streams that many MiB of random data with
+/// an exact `Content-Length`, for measuring bandwidth through the ingress.
+pub fn download_route(
+    transfers: Arc<Transfers>,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    warp::path!("download" / u64)
+        .and(warp::get())
+        .map(move |megabytes| transfers.download(megabytes))
+}
+
+/// `POST /upload` with anything but a multipart body: reads and discards
+/// it, then reports the throughput. Multipart bodies are file uploads and
+/// go to `upload::route` instead.
+pub fn upload_route(
+    transfers: Arc<Transfers>,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    warp::path("upload")
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(upload::multipart(false))
+        .and(warp::body::stream())
+        .and_then(move |body| {
+            let transfers = transfers.clone();
+            async move { transfers.sink(body).await }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use warp::hyper::body::to_bytes;
+
+    use super::*;
+
+    fn transfers(max_mb: u64, slots: usize) -> Transfers {
+        Transfers {
+            max_bytes: max_mb * MB,
+            slots: Arc::new(Semaphore::new(slots)),
+        }
+    }
+
+    #[tokio::test]
+    async fn download_sends_exactly_the_requested_size() {
+        let response = transfers(4, 1).download(3);
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_LENGTH], (3 * MB).to_string());
+        let body = to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(body.len() as u64, 3 * MB);
+    }
+
+    #[tokio::test]
+    async fn download_over_the_cap_is_refused() {
+        assert_eq!(
+            transfers(4, 1).download(5).status(),
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[tokio::test]
+    async fn slot_is_held_until_the_body_is_dropped() {
+        let transfers = transfers(4, 1);
+        let first = transfers.download(1);
+        assert_eq!(
+            transfers.download(1).status(),
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        drop(first);
+        assert_eq!(transfers.download(1).status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn upload_counts_the_streamed_body() {
+        let filter = upload_route(Arc::new(transfers(1, 1)));
+        let response = warp::test::request()
+            .method("POST")
+            .path("/upload")
+            .body(vec![0u8; 1000])
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let stats: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(stats["bytes_received"], 1000);
+    }
+
+    #[tokio::test]
+    async fn multipart_upload_is_left_to_the_file_store() {
+        let filter = upload_route(Arc::new(transfers(1, 1)));
+        let res = warp::test::request()
+            .method("POST")
+            .path("/upload")
+            .header("content-type", "multipart/form-data; boundary=x")
+            .filter(&filter)
+            .await;
+        assert!(res.is_err_and(|err| err.is_not_found()));
+    }
+}
@@ -0,0 +1,257 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::Serialize;
+use tokio::io::AsyncWriteExt;
+use tokio_stream::StreamExt;
+use utoipa::ToSchema;
+use warp::hyper::body::Buf;
+use warp::multipart::{FormData, Part};
+use warp::reject::Reject;
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+use crate::body_limit::PayloadTooLarge;
+use crate::config::Config;
+use crate::{filters, reply};
+
+/// A part whose content type is not in `UPLOAD_ALLOWED_TYPES`.
+#[derive(Debug)]
+pub struct UnsupportedType(pub String);
+
+impl Reject for UnsupportedType {}
+
+/// Writing to `UPLOAD_DIR` failed; answered as a 500.
+#[derive(Debug)]
+struct StorageFailed;
+
+impl Reject for StorageFailed {}
+
+/// Settings for `POST /upload`, from `ENABLE_UPLOAD`, `UPLOAD_DIR`,
+/// `MAX_UPLOAD_BYTES` and `UPLOAD_ALLOWED_TYPES`.
+pub struct Uploads {
+    pub enabled: bool,
+    dir: PathBuf,
+    pub max_bytes: u64,
+    allowed_types: Vec<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct StoredFile {
+    original_name: String,
+    stored_as: String,
+    size_bytes: u64,
+    content_type: String,
+}
+
+impl Uploads {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            enabled: config.enable_upload,
+            dir: PathBuf::from(&config.upload_dir),
+            max_bytes: config.max_upload_bytes,
+            allowed_types: config
+                .upload_allowed_types
+                .iter()
+                .map(|t| t.to_ascii_lowercase())
+                .collect(),
+        }
+    }
+
+    /// Compares the media type only, so `text/plain; charset=utf-8`
+    /// matches `text/plain`.
+    fn is_allowed(&self, content_type: &str) -> bool {
+        let essence = content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        self.allowed_types.contains(&essence)
+    }
+
+    /// Stores every file part of `form`. If any part is rejected, files
+    /// already written for this request are removed again.
+    async fn store(&self, mut form: FormData) -> Result<Vec<StoredFile>, Rejection> {
+        tokio::fs::create_dir_all(&self.dir)
+            .await
+            .map_err(|err| io_error(&self.dir, err))?;
+
+        let mut stored = Vec::new();
+        while let Some(part) = form.next().await {
+            let result = match part {
+                Ok(part) if part.filename().is_some() => self.store_part(part).await,
+                Ok(_) => continue,
+                Err(err) => Err(warp::reject::custom(filters::InvalidBody(err.to_string()))),
+            };
+            match result {
+                Ok(file) => stored.push(file),
+                Err(rejection) => {
+                    for file in &stored {
+                        let _ = tokio::fs::remove_file(self.dir.join(&file.stored_as)).await;
+                    }
+                    return Err(rejection);
+                }
+            }
+        }
+        Ok(stored)
+    }
+
+    async fn store_part(&self, part: Part) -> Result<StoredFile, Rejection> {
+        let content_type = part
+            .content_type()
+            .unwrap_or("application/octet-stream")
+            .to_string();
+        if !self.is_allowed(&content_type) {
+            return Err(warp::reject::custom(UnsupportedType(content_type)));
+        }
+        let original_name = part.filename().unwrap_or_default().to_string();
+        let stored_as = uuid::Uuid::new_v4().to_string();
+        let path = self.dir.join(&stored_as);
+
+        match self.write_part(part, &path).await {
+            Ok(size_bytes) => {
+                tracing::info!(original_name, stored_as, size_bytes, "stored upload");
+                Ok(StoredFile {
+                    original_name,
+                    stored_as,
+                    size_bytes,
+                    content_type,
+                })
+            }
+            Err(rejection) => {
+                let _ = tokio::fs::remove_file(&path).await;
+                Err(rejection)
+            }
+        }
+    }
+
+    async fn write_part(&self, part: Part, path: &Path) -> Result<u64, Rejection> {
+        let mut file = tokio::fs::File::create(path)
+            .await
+            .map_err(|err| io_error(path, err))?;
+        let mut size = 0u64;
+        let mut stream = part.stream();
+        while let Some(chunk) = stream.next().await {
+            let chunk =
+                chunk.map_err(|err| warp::reject::custom(filters::InvalidBody(err.to_string())))?;
+            size += chunk.remaining() as u64;
+            if size > self.max_bytes {
+                return Err(warp::reject::custom(PayloadTooLarge::new(self.max_bytes)));
+            }
+            file.write_all(chunk.chunk())
+                .await
+                .map_err(|err| io_error(path, err))?;
+        }
+        file.flush().await.map_err(|err| io_error(path, err))?;
+        Ok(size)
+    }
+}
+
+fn io_error(path: &Path, err: std::io::Error) -> Rejection {
+    tracing::error!(path = %path.display(), error = %err, "upload write failed");
+    warp::reject::custom(StorageFailed)
+}
+
+#[utoipa::path(
+    post,
+    operation_id = "upload",
+    path = "/upload",
+    tag = "app",
+    request_body(content = String, content_type = "multipart/form-data", description = "One or more file parts"),
+    responses(
+        (status = 200, description = "Metadata for every stored file", body = [StoredFile]),
+        (status = 404, description = "ENABLE_UPLOAD is off", body = ErrorResponse),
+        (status = 413, description = "Larger than MAX_UPLOAD_BYTES"),
+        (status = 415, description = "Content type not in UPLOAD_ALLOWED_TYPES", body = ErrorResponse),
+    )
+)]
+/// `POST /upload`: stores each file part under `UPLOAD_DIR` with a UUID
+/// name. Absent unless `ENABLE_UPLOAD` is set. Bodies that are not
+/// multipart go to the bandwidth sink in `transfer`.
+pub fn route(
+    uploads: Arc<Uploads>,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    warp::path("upload")
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(filters::enabled(uploads.enabled))
+        .and(multipart(true))
+        .and(form(uploads.max_bytes))
+        .and_then(move |form: FormData| {
+            let uploads = uploads.clone();
+            async move {
+                let stored = uploads.store(form).await?;
+                Ok::<_, Rejection>(reply::json(&stored).into_response())
+            }
+        })
+}
+
+/// The multipart body, capped at `max_bytes`. warp reports the cap with its
+/// own `PayloadTooLarge`, which carries no limit, so it is swapped for ours
+/// to give the same 413 body as every other route.
+fn form(max_bytes: u64) -> impl Filter<Extract = (FormData,), Error = Rejection> + Clone {
+    warp::multipart::form()
+        .max_length(max_bytes)
+        .or_else(move |err: Rejection| async move {
+            if err.find::<warp::reject::PayloadTooLarge>().is_some() {
+                Err(warp::reject::custom(PayloadTooLarge::new(max_bytes)))
+            } else {
+                Err(err)
+            }
+        })
+}
+
+/// Passes when whether the body is `multipart/form-data` matches
+/// `expected`, so the file store and the bandwidth sink can share a path.
+pub fn multipart(expected: bool) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::header::optional::<String>("content-type")
+        .and_then(move |content_type: Option<String>| async move {
+            let multipart = content_type.is_some_and(|value| {
+                value
+                    .trim_start()
+                    .to_ascii_lowercase()
+                    .starts_with("multipart/form-data")
+            });
+            if multipart == expected {
+                Ok(())
+            } else {
+                Err(warp::reject::not_found())
+            }
+        })
+        .untuple_one()
+}
+
+#[cfg(test)]
+mod tests {
+    use warp::http::StatusCode;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn oversized_multipart_body_gets_the_common_413() {
+        let uploads = Arc::new(Uploads {
+            enabled: true,
+            dir: std::env::temp_dir(),
+            max_bytes: 64,
+            allowed_types: vec!["text/plain".to_string()],
+        });
+        let body = format!(
+            "--x\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n\
+             Content-Type: text/plain\r\n\r\n{}\r\n--x--\r\n",
+            "a".repeat(256)
+        );
+        let filter = route(uploads).recover(crate::handle_rejection);
+        let response = warp::test::request()
+            .method("POST")
+            .path("/upload")
+            .header("content-type", "multipart/form-data; boundary=x")
+            .body(body)
+            .reply(&filter)
+            .await;
+
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["limit_bytes"], 64);
+    }
+}
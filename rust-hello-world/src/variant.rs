@@ -0,0 +1,105 @@
+use std::sync::Arc;
+
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+use crate::hostname::Hostname;
+
+/// Variant names that already are colors are shown as themselves, so a
+/// blue/green demo really is blue and green.
+const NAMED_COLORS: &[&str] = &[
+    "blue", "green", "red", "orange", "yellow", "purple", "pink", "teal", "gray", "black",
+];
+
+/// Background color for `variant`: the name itself when it is a color,
+/// otherwise a hue picked from a stable hash of the name, so every pod of a
+/// variant agrees without any coordination.
+fn color_for(variant: &str) -> String {
+    let lower = variant.to_ascii_lowercase();
+    if NAMED_COLORS.contains(&lower.as_str()) {
+        return lower;
+    }
+    // FNV-1a: tiny, and unlike `DefaultHasher` identical across builds.
+    let hash = lower.bytes().fold(0x811c_9dc5_u32, |hash, byte| {
+        (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193)
+    });
+    format!("hsl({}, 65%, 45%)", hash % 360)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn page(variant: &str, hostname: &str) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>{variant}</title>
+<style>
+  body {{ margin: 0; height: 100vh; display: flex; flex-direction: column;
+         align-items: center; justify-content: center; background: {color};
+         color: #fff; font-family: sans-serif; text-shadow: 0 1px 3px #0008; }}
+  h1 {{ font-size: 6rem; margin: 0; }}
+</style>
+</head>
+<body>
+<h1>{variant}</h1>
+<p>{hostname}</p>
+</body>
+</html>
+"#,
+        color = color_for(variant),
+        variant = escape(variant),
+        hostname = escape(hostname),
+    )
+}
+
+#[utoipa::path(
+    get,
+    operation_id = "color",
+    path = "/color",
+    tag = "app",
+    responses((status = 200, description = "Full-page color for this pod's VARIANT", body = String, content_type = "text/html"))
+)]
+/// `GET /color`: a page filled with this variant's color, so shifting
+/// canary weights is visible by refreshing a browser.
+pub fn route(
+    variant: Option<Arc<str>>,
+    hostname: Hostname,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    warp::path("color")
+        .and(warp::path::end())
+        .and(warp::get())
+        .map(move || {
+            let variant = variant.as_deref().unwrap_or("default");
+            warp::reply::html(page(variant, &hostname.get())).into_response()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn color_names_are_used_as_is() {
+        assert_eq!(color_for("Blue"), "blue");
+        assert_eq!(color_for("green"), "green");
+    }
+
+    #[test]
+    fn other_names_hash_to_a_stable_hue() {
+        assert_eq!(color_for("canary"), color_for("canary"));
+        assert_ne!(color_for("canary"), color_for("stable"));
+        assert!(color_for("canary").starts_with("hsl("));
+    }
+
+    #[test]
+    fn page_escapes_the_variant() {
+        assert!(page("<b>", "pod").contains("&lt;b&gt;"));
+    }
+}
@@ -0,0 +1,37 @@
+use serde::Serialize;
+use utoipa::ToSchema;
+use warp::reply::Response;
+
+use crate::conditional::{Conditions, Tagged};
+use crate::reply::{self, JsonBody};
+
+#[derive(Serialize, ToSchema)]
+pub struct VersionInfo {
+    version: &'static str,
+    git_sha: &'static str,
+}
+
+/// Build information for `GET /version`. It cannot change while the
+/// process runs, so it is serialized once and polling clients that send
+/// the ETag back get a bodiless 304.
+pub struct Version {
+    body: JsonBody,
+    tagged: Tagged,
+}
+
+impl Version {
+    pub fn new() -> Self {
+        let info = VersionInfo {
+            version: env!("CARGO_PKG_VERSION"),
+            git_sha: option_env!("GIT_SHA").unwrap_or("unknown"),
+        };
+        Self {
+            body: reply::json(&info),
+            tagged: Tagged::default(),
+        }
+    }
+
+    pub fn reply(&self, conditions: Conditions) -> Response {
+        self.tagged.reply(self.body.clone(), &conditions)
+    }
+}
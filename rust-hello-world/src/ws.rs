@@ -0,0 +1,118 @@
+use futures_util::{SinkExt, StreamExt};
+use serde::Serialize;
+use warp::reply::Response;
+use warp::ws::{Message, WebSocket, Ws};
+use warp::{Filter, Rejection, Reply};
+
+use crate::feature_flags;
+use crate::hostname::Hostname;
+use crate::metrics::Metrics;
+
+#[derive(Serialize)]
+struct Echo<'a> {
+    original: &'a str,
+    echo_at: String,
+    hostname: String,
+}
+
+#[utoipa::path(
+    get,
+    operation_id = "ws",
+    path = "/ws",
+    tag = "app",
+    responses(
+        (status = 101, description = "Upgraded; text frames are echoed as JSON, binary frames as-is"),
+        (status = 400, description = "Not a WebSocket handshake"),
+        (status = 404, description = "FF_ECHO_ENABLED is off", body = ErrorResponse),
+    )
+)]
+/// `GET /ws`: a WebSocket echo server for testing upgrades through the
+/// ingress. Answers 404 while `FF_ECHO_ENABLED` is off.
+pub fn route(
+    hostname: Hostname,
+    metrics: Metrics,
+) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    warp::path("ws")
+        .and(warp::path::end())
+        .and(feature_flags::require(feature_flags::ECHO))
+        .and(warp::ws())
+        .map(move |ws: Ws| {
+            let hostname = hostname.clone();
+            let metrics = metrics.clone();
+            ws.on_upgrade(move |socket| echo(socket, hostname, metrics))
+                .into_response()
+        })
+}
+
+/// Echoes until the client closes or the connection breaks. Pings are
+/// answered and close frames acknowledged by tungstenite itself, so only
+/// data frames need handling here.
+async fn echo(socket: WebSocket, hostname: Hostname, metrics: Metrics) {
+    let _active = metrics.websocket_connected();
+    let (mut tx, mut rx) = socket.split();
+    while let Some(message) = rx.next().await {
+        let message = match message {
+            Ok(message) => message,
+            Err(err) => {
+                tracing::debug!(error = %err, "websocket receive failed");
+                break;
+            }
+        };
+        let reply = if let Ok(text) = message.to_str() {
+            let echo = Echo {
+                original: text,
+                echo_at: chrono::Utc::now().to_rfc3339(),
+                hostname: hostname.get(),
+            };
+            match serde_json::to_string(&echo) {
+                Ok(json) => Message::text(json),
+                Err(err) => {
+                    tracing::error!(error = %err, "failed to serialize websocket echo");
+                    continue;
+                }
+            }
+        } else if message.is_binary() {
+            Message::binary(message.into_bytes())
+        } else if message.is_close() {
+            break;
+        } else {
+            continue;
+        };
+        if let Err(err) = tx.send(reply).await {
+            tracing::debug!(error = %err, "websocket send failed");
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn text_is_wrapped_and_binary_echoed_verbatim() {
+        let filter = route(
+            Hostname::Static(Arc::from("pod-a")),
+            Metrics::new(None, Arc::default()),
+        );
+        let mut client = warp::test::ws()
+            .path("/ws")
+            .handshake(filter)
+            .await
+            .expect("handshake");
+
+        client.send_text("hello").await;
+        let reply = client.recv().await.expect("text reply");
+        let echo: serde_json::Value = serde_json::from_str(reply.to_str().unwrap()).unwrap();
+        assert_eq!(echo["original"], "hello");
+        assert_eq!(echo["hostname"], "pod-a");
+        assert!(echo["echo_at"].is_string());
+
+        client.send(Message::binary(vec![0, 159, 146, 150])).await;
+        let reply = client.recv().await.expect("binary reply");
+        assert!(reply.is_binary());
+        assert_eq!(reply.as_bytes(), &[0, 159, 146, 150]);
+    }
+}
@@ -0,0 +1,108 @@
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const REQUEST: &[u8] = b"GET /health HTTP/1.1\r\nHost: test\r\nConnection: close\r\n\r\n";
+
+struct Server(Child);
+
+impl Server {
+    fn spawn(envs: &[(&str, &str)]) -> Self {
+        let child = Command::new(env!("CARGO_BIN_EXE_rust-hello-world"))
+            .env_clear()
+            .envs(envs.iter().copied())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("spawn server");
+        Server(child)
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn retry<T>(mut connect: impl FnMut() -> std::io::Result<T>) -> T {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        match connect() {
+            Ok(conn) => return conn,
+            Err(err) if Instant::now() > deadline => panic!("server never came up: {err}"),
+            Err(_) => thread::sleep(Duration::from_millis(50)),
+        }
+    }
+}
+
+fn exchange(mut conn: impl Read + Write) -> String {
+    conn.write_all(REQUEST).unwrap();
+    let mut response = String::new();
+    conn.read_to_string(&mut response).unwrap();
+    response
+}
+
+fn assert_health_ok(response: &str) {
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+    assert!(response.ends_with("\r\n\r\nOK"), "{response}");
+}
+
+#[test]
+fn serves_over_tcp_port() {
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+        .to_string();
+    let _server = Server::spawn(&[("PORT", &port)]);
+
+    let conn = retry(|| TcpStream::connect(("127.0.0.1", port.parse::<u16>().unwrap())));
+    assert_health_ok(&exchange(conn));
+}
+
+#[test]
+fn serves_over_unix_socket_replacing_stale_file() {
+    let path: PathBuf = std::env::temp_dir().join(format!(
+        "rust-hello-world-{}-{}.sock",
+        std::process::id(),
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos()
+    ));
+    // Leave a stale socket behind, as a crashed previous instance would.
+    drop(UnixListener::bind(&path).unwrap());
+    assert!(path.exists());
+
+    let path_str = path.to_str().unwrap();
+    let _server = Server::spawn(&[("UNIX_SOCKET_PATH", path_str)]);
+
+    let conn = retry(|| UnixStream::connect(&path));
+    assert_health_ok(&exchange(conn));
+
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn exits_with_status_1_when_the_port_is_taken() {
+    let taken = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = taken.local_addr().unwrap().port().to_string();
+    let mut server = Server::spawn(&[("PORT", &port), ("BIND_ADDR", "127.0.0.1")]);
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let status = loop {
+        if let Some(status) = server.0.try_wait().unwrap() {
+            break status;
+        }
+        assert!(Instant::now() < deadline, "server kept running");
+        thread::sleep(Duration::from_millis(50));
+    };
+    assert_eq!(status.code(), Some(1));
+}